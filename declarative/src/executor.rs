@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use subgit_sync::SetupRequest;
+use subgit_sync::SignedCommitPolicy;
 
 use crate::harness::{TestConfig, Executor};
 use std::process::ExitStatus;
@@ -29,15 +30,26 @@ impl Executor for DefaultExecutor {
             subgit_git_location: root.join(SUBGIT).to_string_lossy().into(),
             upstream_map_path: "subgit".to_owned(),
             subgit_map_path: None,
+            extra_mappings: vec!(),
+            excludes: vec!(),
+            trailers: false,
+            import_mailmap: None,
+            export_mailmap: None,
+            sync_committer: None,
+            signed_commits: SignedCommitPolicy::Strip,
+            rename_refs: vec!(),
+            review_refs: vec!(),
             log_level: self.log_level.clone(),
             log_file: self.log_file.as_ref().map(|name| root.join(name)),
             upstream_hook_path: "hooks/post-receive".into(),
-            subgit_hook_path: "hooks/update".into(),
+            subgit_hook_path: Some("hooks/update".into()),
+            atomic: false,
             upstream_working_clone_url: None,
             subgit_working_clone_url: None,
             env_based_recursion_detection: None,
             disable_recursion_detection: true,
             match_ref:  "refs/heads/,HEAD".into(),
+            protections: vec!(),
         }
     }
 }
@@ -59,6 +71,48 @@ impl ToArgs for SetupRequest {
             base.push(subgit_map_path);
         }
 
+        for extra_mapping in self.extra_mappings {
+            base.push("-M".to_owned());
+            base.push(extra_mapping);
+        }
+
+        for exclude in self.excludes {
+            base.push("-x".to_owned());
+            base.push(exclude);
+        }
+
+        if self.trailers {
+            base.push("-t".to_owned());
+        }
+
+        if let Some(import_mailmap) = self.import_mailmap {
+            base.push("-i".to_owned());
+            base.push(import_mailmap.to_string_lossy().to_string());
+        }
+
+        if let Some(export_mailmap) = self.export_mailmap {
+            base.push("-e".to_owned());
+            base.push(export_mailmap.to_string_lossy().to_string());
+        }
+
+        if let Some(sync_committer) = self.sync_committer {
+            base.push("-c".to_owned());
+            base.push(sync_committer);
+        }
+
+        base.push("-s".to_owned());
+        base.push(self.signed_commits.to_string());
+
+        for rename_ref in self.rename_refs {
+            base.push("-R".to_owned());
+            base.push(rename_ref);
+        }
+
+        for review_ref in self.review_refs {
+            base.push("-v".to_owned());
+            base.push(review_ref);
+        }
+
         if let Some(log_level) = self.log_level {
             base.push("-l".to_owned());
             base.push(format!("{}", log_level));
@@ -73,8 +127,14 @@ impl ToArgs for SetupRequest {
         base.push(self.upstream_hook_path.to_string_lossy().to_owned().to_string());
 
 
-        base.push("-h".to_owned());
-        base.push(self.subgit_hook_path.to_string_lossy().to_owned().to_string());
+        if let Some(subgit_hook_path) = self.subgit_hook_path {
+            base.push("-h".to_owned());
+            base.push(subgit_hook_path.to_string_lossy().to_owned().to_string());
+        }
+
+        if self.atomic {
+            base.push("-a".to_owned());
+        }
 
         if let Some(upstream_working_clone_url) = self.upstream_working_clone_url {
             base.push("-U".to_owned());
@@ -97,6 +157,11 @@ impl ToArgs for SetupRequest {
         base.push("-m".to_owned());
         base.push(self.match_ref);
 
+        for protection in self.protections {
+            base.push("-P".to_owned());
+            base.push(protection.to_string());
+        }
+
         base
     }
 }
@@ -9,6 +9,7 @@ use std::cmp::Ordering;
 use crate::tree::Commit;
 use crate::tree::FileChange;
 use std::path::Path;
+use std::os::unix::fs::PermissionsExt;
 use crate::tree::ChangeSet;
 
 const NEEDS_ALLOW_UNRELATED_HISTORIES_MERGE_FLAG: Version = Version {
@@ -82,13 +83,25 @@ pub struct GitWrapper {
 
 impl FileChange {
     pub fn apply<R: AsRef<Path>, P: AsRef<Path>>(&self, root: R, path: P) {
+        let full_path = root.as_ref().join(path.as_ref());
+        if let FileChange::Deleted = self {
+            std::fs::remove_file(&full_path).unwrap();
+            return;
+        }
+
+        // Replace rather than write through, so a symlink's target is never modified
+        if std::fs::symlink_metadata(&full_path).is_ok() {
+            std::fs::remove_file(&full_path).unwrap();
+        }
+        std::fs::create_dir_all(&full_path.parent().unwrap()).unwrap();
         match self {
-            FileChange::Deleted => std::fs::remove_file(&root.as_ref().join(path.as_ref())).unwrap(),
-            FileChange::Content(content)=> {
-                std::fs::create_dir_all(&root.as_ref().join(path.as_ref()).parent().unwrap())
-                    .unwrap();
-                std::fs::write(&root.as_ref().join(path.as_ref()), &content).unwrap()
+            FileChange::Deleted => unreachable!(),
+            FileChange::Content(content) => std::fs::write(&full_path, &content).unwrap(),
+            FileChange::Executable(content) => {
+                std::fs::write(&full_path, &content).unwrap();
+                std::fs::set_permissions(&full_path, std::fs::Permissions::from_mode(0o755)).unwrap()
             }
+            FileChange::Symlink(target) => std::os::unix::fs::symlink(target, &full_path).unwrap(),
         }
     }
 }
@@ -90,12 +90,13 @@ impl Test {
         });
         let diff_messages : Vec<Diff> = branch_map.into_iter().map(|(branch, files)| {
             crate::util::clear_path(&compare_root);
-            crate::util::write_files(&compare_root, files).expect(&format!("Couldn't write files for {} branch", &branch));
+            files.iter().for_each(|(path, change)| change.apply(&compare_root, path));
             comparisons.clone().into_iter().flat_map({
                 let branch = branch.clone();
                 move |c| {
                     crate::util::command(self.path.join(c.path), "git", &["checkout", &branch]).unwrap();
-                    let result = crate::util::compare_dir_content(&c.comp, self.path.join(&c.path));
+                    let mut result = crate::util::compare_dir_content(&c.comp, self.path.join(&c.path));
+                    result.push_str(&crate::util::compare_dir_modes(&c.comp, self.path.join(&c.path)));
                     if result.len() > 0 {
                         Some(Diff {
                             branch: branch.clone(),
@@ -6,7 +6,7 @@ mod harness;
 mod executor;
 mod git;
 
-pub use crate::tree::{CommitTree,ChangeSetGenerator};
+pub use crate::tree::{CommitTree,ChangeSetGenerator,ChangeSet,FileChange};
 pub use crate::executor::DefaultExecutor;
 pub use crate::git::GitConsumer;
 pub use crate::harness::{TestConfig, Test};
@@ -9,10 +9,14 @@ use std::collections::HashSet;
 #[derive(Clone, Debug)]
 pub enum FileChange {
     Deleted,
-    Content(String)
+    Content(String),
+    /// A regular file with the executable bit set (mode 100755)
+    Executable(String),
+    /// A symlink pointing at the given target (mode 120000)
+    Symlink(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ChangeSet {
     pub files: HashMap<PathBuf, FileChange>,
 }
@@ -121,13 +125,18 @@ impl TreeRecord {
     }
 }
 
+impl ChangesForLabel for ChangeSet {
+    fn construct(self, _label: &str) -> ChangeSet {
+        self
+    }
+}
+
 impl ChangesForLabel for MergeCommit {
     fn construct(self, _label: &str) -> ChangeSet {
         ChangeSet::new()
     }
 }
 
-pub type FileContent = String;
 pub type BranchName = String;
 
 impl Deref for Commit {
@@ -193,12 +202,14 @@ impl CommitTree {
         }
     }
 
-    pub fn reify(&self) -> HashMap<BranchName, HashMap<PathBuf, FileContent>> {
+    pub fn reify(&self) -> HashMap<BranchName, HashMap<PathBuf, FileChange>> {
         self.branches.iter().map(|(branch, commit_ref)| {
             let branch = branch.clone();
-            let ancestor_path = self.ancestor_path(*commit_ref);
+            let mut ancestor_path = self.ancestor_path(*commit_ref);
+            // Parents always come before their children, so this applies later changes last
+            ancestor_path.sort_by_key(|v| v.0);
             eprintln!("Commit value for {} - {}", &branch, ancestor_path.len());
-            let mut file_map : HashMap<PathBuf, FileContent> = HashMap::new();
+            let mut file_map : HashMap<PathBuf, FileChange> = HashMap::new();
 
             ancestor_path.iter()
                 .map(|v| &self.commits[v.0])
@@ -207,7 +218,7 @@ impl CommitTree {
                     commit.changes.files.iter().for_each(|(path, action)| {
                         match action {
                             FileChange::Deleted => file_map.remove(path),
-                            FileChange::Content(content) => file_map.insert(path.clone(), content.clone())
+                            _ => file_map.insert(path.clone(), action.clone())
                         };
                     })
                 });
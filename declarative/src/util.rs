@@ -96,6 +96,51 @@ pub fn compare_dir_content<D1: AsRef<Path>, D2: AsRef<Path>>(origin: D1, comp: D
     String::from_utf8(raw.stdout).unwrap()
 }
 
+/// Lists the files under origin whose type (regular, executable or symlink) differs in comp.
+/// Content differences are left to compare_dir_content.
+pub fn compare_dir_modes<D1: AsRef<Path>, D2: AsRef<Path>>(origin: D1, comp: D2) -> String {
+    let mut message = String::new();
+    compare_modes_under(origin.as_ref(), comp.as_ref(), Path::new(""), &mut message);
+    message
+}
+
+fn describe_mode(path: &Path) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+    let meta = std::fs::symlink_metadata(path).ok()?;
+    Some(if meta.file_type().is_symlink() {
+        format!("symlink -> {}", std::fs::read_link(path).unwrap().to_string_lossy())
+    } else if meta.is_dir() {
+        "directory".to_owned()
+    } else if meta.permissions().mode() & 0o111 != 0 {
+        "executable".to_owned()
+    } else {
+        "file".to_owned()
+    })
+}
+
+fn compare_modes_under(origin: &Path, comp: &Path, relative: &Path, message: &mut String) {
+    for entry in std::fs::read_dir(origin.join(relative)).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let relative = relative.join(entry.file_name());
+        let expected = describe_mode(&origin.join(&relative));
+        let actual = describe_mode(&comp.join(&relative));
+        // Missing files are already reported by compare_dir_content
+        if actual.is_some() && expected != actual {
+            message.push_str(&format!(
+                "Mode differs for {}: expected {:?}, found {:?}\n",
+                relative.to_string_lossy(),
+                expected,
+                actual
+            ));
+        } else if expected.as_deref() == Some("directory") {
+            compare_modes_under(origin, comp, &relative, message);
+        }
+    }
+}
+
 pub fn assert_dir_content_equal<D1: AsRef<Path>, D2: AsRef<Path>>(origin: D1, comp: D2) {
     let raw = command_raw(
         std::env::current_dir().unwrap(),
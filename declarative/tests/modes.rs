@@ -0,0 +1,73 @@
+use declarative::*;
+
+fn mode_changes(label: &str) -> ChangeSet {
+    let mut changes = ChangeSet::new();
+    changes.add(format!("subgit/{}.sh", label), FileChange::Executable(format!("#!/bin/sh\necho {}\n", label)));
+    changes.add(format!("subgit/{}-link", label), FileChange::Symlink(format!("{}.sh", label)));
+    changes
+}
+
+fn upstream_mode_changes(label: &str) -> ChangeSet {
+    let mut changes = mode_changes(label);
+    changes.add(format!("{}-up.sh", label), FileChange::Executable(format!("#!/bin/sh\necho up {}\n", label)));
+    changes
+}
+
+#[test]
+pub fn import_modes() {
+    let config : TestConfig = config_for!("import_modes");
+    let (target, executor) = target_executor();
+
+    let tree = {
+        let mut tree = CommitTree::new();
+        let root = tree.root("root", target.empty());
+        let a1 = tree.commit("a1", target.both(), &root);
+        let a2 = tree.commit("a2", upstream_mode_changes("a2"), &a1);
+
+        tree.branch("master", &a2);
+
+        tree
+    };
+
+    config.run_setup(tree.clone(), executor).verify(tree);
+}
+
+#[test]
+pub fn push_modes_both_ways() {
+    let config : TestConfig = config_for!("push_modes_both_ways");
+    let (target, executor) = target_executor();
+
+    let mut tree = CommitTree::new();
+    let root = tree.root("root", target.empty());
+    let a1 = tree.commit("a1", target.both(), &root);
+    tree.branch("master", &a1);
+
+    let test = config.run_setup(tree.clone(), executor);
+
+    let mut expected = tree;
+    let b = expected.commit("_B_", upstream_mode_changes("b"), &a1);
+    let c = {
+        let mut changes = mode_changes("c");
+        // Flip the modes of the files from the upstream commit
+        changes.add("subgit/b.sh", FileChange::Content("#!/bin/sh\necho no longer executable\n".to_owned()));
+        changes.add("subgit/b-link", FileChange::Executable("Used to be a link\n".to_owned()));
+        expected.commit("_C_", changes, &b)
+    };
+    expected.branch("master", &c);
+    let expected = expected;
+
+    // Upstream: Add executables and symlinks
+    let up = test.upstream_consumer("up");
+    up.commit(b);
+    up.push("master");
+
+    sleep(3);
+
+    // Subgit: Add more, and change the modes of the upstream ones
+    let sg = test.subgit_consumer("sg");
+    sg.pull();
+    sg.commit(c);
+    sg.push("master");
+
+    test.verify(expected);
+}
@@ -1,8 +1,11 @@
 use crate::git;
+use crate::model::ref_filters::RefFilters;
+use crate::model::settings::{HookPaths, SyncSettings};
+use crate::model::uninstall::DataDisposal;
+use failure::format_err;
 use fs2::FileExt;
 use git2::Oid;
 use hex;
-use log::LevelFilter;
 use std::env;
 use std::fs;
 use std::fs::File;
@@ -15,18 +18,6 @@ pub trait RefFilter {
     fn matches<R: AsRef<str>>(&self, ref_name: R) -> bool;
 }
 
-impl RefFilter for Vec<String> {
-    fn matches<R: AsRef<str>>(&self, ref_name: R) -> bool {
-        self.iter().any(|v| ref_name.as_ref().starts_with(v))
-    }
-}
-
-impl<'a> RefFilter for &'a Vec<String> {
-    fn matches<R: AsRef<str>>(&self, ref_name: R) -> bool {
-        self.iter().any(|v| ref_name.as_ref().starts_with(v))
-    }
-}
-
 #[derive(Debug)]
 pub struct SubGitEnv {
     pub git_dir: PathBuf,
@@ -49,6 +40,59 @@ pub struct RefSyncRequest {
 #[derive(Debug)]
 pub struct SyncAll {
     pub env: SubGitEnv,
+    /// Only list the refs that would be pruned
+    pub dry_run: bool,
+    /// Subgit-only refs that are never pruned
+    pub keep: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct ListBackups {
+    pub env: SubGitEnv,
+}
+
+#[derive(Debug)]
+pub struct RestoreBackup {
+    pub env: SubGitEnv,
+    pub backup: String,
+    /// The ref to restore the backup as, instead of the ref it was backed up from
+    pub ref_name: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Status {
+    pub git_dir: PathBuf,
+    /// Print the refs as JSON instead of one line per ref
+    pub json: bool,
+}
+
+#[derive(Debug)]
+pub struct Verify {
+    pub git_dir: PathBuf,
+    /// Only check the commits the synced refs point to
+    pub tips: bool,
+}
+
+#[derive(Debug)]
+pub struct RebuildMap {
+    pub git_dir: PathBuf,
+    /// The new database to write
+    pub output: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Uninstall {
+    pub git_dir: PathBuf,
+    /// Only list the steps
+    pub dry_run: bool,
+    pub data: DataDisposal,
+}
+
+#[derive(Debug)]
+pub struct Upgrade {
+    pub git_dir: PathBuf,
+    /// The binary to install
+    pub binary: PathBuf,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -228,25 +272,16 @@ pub struct Setup {
     pub upstream_git_location: PathBuf,
     pub subgit_git_location: PathBuf,
 
-    // The path mapping
-    pub upstream_map_path: PathBuf,
-    pub subgit_map_path: Option<PathBuf>,
+    // What goes into the settings file
+    pub settings: SyncSettings,
 
-    // The log level to use
-    pub log_level: LevelFilter,
+    // The log of the setup itself
     pub log_file: PathBuf,
 
     // The hook paths
-    pub upstream_hook_path: PathBuf,
-    pub subgit_hook_path: PathBuf,
+    pub hooks: HookPaths,
     pub upstream_working_clone_url: Option<String>,
     pub subgit_working_clone_url: Option<String>,
-
-    // recursion detection
-    pub recursion_detection: RecursionDetection,
-
-    // ref matching
-    pub filters: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -257,12 +292,34 @@ pub struct UpdateHook {
     pub new_sha: Oid,
 }
 
+/// The subgit hook installed as a pre-receive hook, which exports all of the ref updates of a push together
+#[derive(Debug)]
+pub struct PreReceiveHook {
+    pub env: SubGitEnv,
+    pub updates: Vec<RefUpdateRequest>,
+}
+
+#[derive(Debug)]
+pub struct RefUpdateRequest {
+    pub ref_name: String,
+    pub old_sha: Oid,
+    pub new_sha: Oid,
+}
+
 #[derive(Debug)]
 pub enum Action {
     SyncRefs(SyncRefs),
     SyncAll(SyncAll),
+    ListBackups(ListBackups),
+    RestoreBackup(RestoreBackup),
+    Status(Status),
+    Verify(Verify),
+    RebuildMap(RebuildMap),
+    Uninstall(Uninstall),
+    Upgrade(Upgrade),
     Setup(Setup),
     UpdateHook(UpdateHook),
+    PreReceiveHook(PreReceiveHook),
 }
 
 pub fn lock<P: AsRef<Path>>(root: P) -> Result<File, failure::Error> {
@@ -278,37 +335,40 @@ pub fn lock<P: AsRef<Path>>(root: P) -> Result<File, failure::Error> {
     Ok(file)
 }
 
+/// Like lock, but fails right away if the lock is taken - e.g. by a sync in progress
+pub fn try_lock<P: AsRef<Path>>(root: P) -> Result<File, failure::Error> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .open(root.as_ref().join("data/lock"))?;
+    file.try_lock_exclusive()
+        .map_err(|_| format_err!("A sync is in progress - try again once it's done"))?;
+    Ok(file)
+}
+
 impl Setup {
     fn run(self) -> RunResult {
-        let subgit_map_path = self
-            .subgit_map_path
-            .map(|v| v.to_string_lossy().to_string());
         let mut wrapped = crate::model::WrappedSubGit::run_creation(
             self.subgit_git_location,
             self.upstream_git_location,
-            self.upstream_map_path.to_str().unwrap(),
-            subgit_map_path.as_ref().map(String::as_str),
-            self.log_level,
+            self.settings,
             self.log_file,
             crate::model::BinSource {
                 location: self.copy_from,
                 symlink: false,
             },
-            self.subgit_hook_path,
+            self.hooks,
             self.subgit_working_clone_url,
-            self.upstream_hook_path,
             self.upstream_working_clone_url,
-            self.recursion_detection,
-            self.filters,
         )?;
         wrapped.import_initial_empty_commits();
         wrapped.update_all_from_upstream()?;
+        wrapped.sync_head()?;
 
         Ok(())
     }
 }
 
-fn empty(_filters: &Vec<String>) {}
+fn empty(_filters: &RefFilters) {}
 
 impl UpdateHook {
     pub fn run(self) -> RunResult {
@@ -316,6 +376,7 @@ impl UpdateHook {
 
         if let Some(mut wrapped) = maybe_wrapped {
             info!("Opened Wrapped");
+            wrapped.check_protections(&self.ref_name, self.old_sha, self.new_sha)?;
             info!("Running update");
             wrapped.update_self();
             wrapped.push_ref_change_upstream(self.ref_name, self.old_sha, self.new_sha)?;
@@ -327,6 +388,32 @@ impl UpdateHook {
     }
 }
 
+impl PreReceiveHook {
+    pub fn run(self) -> RunResult {
+        let maybe_wrapped = crate::model::WrappedSubGit::open(self.env.git_dir, Some(empty))?;
+
+        if let Some(mut wrapped) = maybe_wrapped {
+            info!("Opened Wrapped");
+            wrapped.use_quarantined_objects()?;
+            for update in &self.updates {
+                wrapped.check_protections(&update.ref_name, update.old_sha, update.new_sha)?;
+            }
+            info!("Running atomic update");
+            wrapped.update_self();
+            wrapped.push_ref_changes_upstream_atomically(
+                self.updates
+                    .into_iter()
+                    .map(|update| (update.ref_name, update.old_sha, update.new_sha))
+                    .collect(),
+            )?;
+
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+}
+
 impl SyncAll {
     pub fn run(self) -> RunResult {
         let maybe_wrapped = crate::model::WrappedSubGit::open(self.env.git_dir, Some(empty))?;
@@ -335,11 +422,133 @@ impl SyncAll {
             info!("Running Sync All");
 
             wrapped.update_self();
+            let stale_refs = wrapped.stale_refs(&RefFilters::new(&self.keep)?)?;
+            if self.dry_run {
+                stale_refs.iter().for_each(|ref_name| println!("Would remove {}", ref_name));
+                return Ok(());
+            }
             wrapped.update_all_from_upstream()?;
+            wrapped.prune_refs(&stale_refs)?;
+            wrapped.sync_head()?;
+            Ok(())
+        } else {
             Ok(())
+        }
+    }
+}
+
+impl ListBackups {
+    pub fn run(self) -> RunResult {
+        let maybe_wrapped = crate::model::WrappedSubGit::open(self.env.git_dir, Some(empty))?;
+
+        if let Some(wrapped) = maybe_wrapped {
+            for backup in wrapped.list_backups()? {
+                println!("{} {}", backup.sha, backup.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RestoreBackup {
+    pub fn run(self) -> RunResult {
+        let maybe_wrapped = crate::model::WrappedSubGit::open(self.env.git_dir, Some(empty))?;
+
+        if let Some(mut wrapped) = maybe_wrapped {
+            info!("Restoring {}", self.backup);
+            wrapped.update_self();
+            let ref_name = wrapped.restore_backup(&self.backup, self.ref_name)?;
+            println!("Restored {} as {}", self.backup, ref_name);
+        }
+        Ok(())
+    }
+}
+
+impl Status {
+    pub fn run(self) -> RunResult {
+        let statuses = crate::model::status::ref_statuses(&self.git_dir)?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&statuses)?);
         } else {
+            let short = |sha: &Option<String>| sha.as_ref().map(|sha| sha[..7].to_owned()).unwrap_or_else(|| "-".to_owned());
+            for status in &statuses {
+                println!(
+                    "{:<8} {} -> {} (upstream {}, expected subgit {}, subgit {})",
+                    status.state.as_str(),
+                    status.ref_name,
+                    status.subgit_ref_name,
+                    short(&status.upstream_sha),
+                    short(&status.expected_subgit_sha),
+                    short(&status.subgit_sha),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Verify {
+    pub fn run(self) -> RunResult {
+        let report = crate::model::verify::verify(&self.git_dir, self.tips)?;
+        for mismatch in &report.mismatches {
+            println!("{}", mismatch);
+        }
+        println!("Verified {} commit pairs, found {} mismatches", report.checked, report.mismatches.len());
+        if report.mismatches.is_empty() {
             Ok(())
+        } else {
+            Err(format_err!("The histories of the upstream and the subgit don't match"))
+        }
+    }
+}
+
+impl RebuildMap {
+    pub fn run(self) -> RunResult {
+        let rebuilt = crate::model::rebuild::rebuild_into(&self.git_dir, &self.output)?;
+        for unresolved in &rebuilt.unresolved {
+            println!("{}", unresolved);
         }
+        println!(
+            "Wrote {} commit pairs to {}, {} commits left to resolve by hand",
+            rebuilt.pairs.len(),
+            self.output.to_string_lossy(),
+            rebuilt.unresolved.len()
+        );
+        Ok(())
+    }
+}
+
+impl Uninstall {
+    pub fn run(self) -> RunResult {
+        let steps = crate::model::uninstall::plan(&self.git_dir, self.data)?;
+        if self.dry_run {
+            steps.iter().for_each(|step| println!("{}", step.describe(true)));
+            return Ok(());
+        }
+        // Waits for any sync in progress to finish
+        let _lock = lock(&self.git_dir)?;
+        for step in steps {
+            step.run()?;
+            println!("{}", step.describe(false));
+        }
+        Ok(())
+    }
+}
+
+impl Upgrade {
+    pub fn run(self) -> RunResult {
+        let _lock = try_lock(&self.git_dir)?;
+        let upgraded = crate::model::upgrade::upgrade(&self.git_dir, &self.binary)?;
+        if upgraded.replaced_hook {
+            println!("Installed {} as the hook", self.binary.to_string_lossy());
+        } else {
+            println!("The hook already is {}", self.binary.to_string_lossy());
+        }
+        for hook in upgraded.hooks {
+            println!("Checked {}", hook.to_string_lossy());
+        }
+        println!("Migrated the settings and the map");
+        Ok(())
     }
 }
 
@@ -347,7 +556,7 @@ impl SyncRefs {
     pub fn run(self) -> RunResult {
         let maybe_wrapped = crate::model::WrappedSubGit::open(
             &self.env.git_dir,
-            Some(|filters: &Vec<String>| {
+            Some(|filters: &RefFilters| {
                 let ref_names: Vec<_> = (&self.requests)
                     .iter()
                     .filter(|req| filters.matches(&req.ref_name))
@@ -367,14 +576,15 @@ impl SyncRefs {
             self.requests
                 .into_iter()
                 .for_each(|request| {
-                    if wrapped.filters.matches(&request.ref_name){
-                        wrapped.import_upstream_commits(
+                    if wrapped.syncs_upstream_ref(&request.ref_name) {
+                        wrapped.import_upstream_ref(
                             &request.ref_name,
                             git::optionify_sha(request.old_upstream_sha),
                             git::optionify_sha(request.new_upstream_sha),
                         );
                     }
                 });
+            wrapped.sync_head()?;
             Ok(())
         } else {
             Ok(())
@@ -388,7 +598,15 @@ impl Action {
         match self {
             Action::Setup(setup) => setup.run(),
             Action::UpdateHook(update) => update.run(),
+            Action::PreReceiveHook(pre_receive) => pre_receive.run(),
             Action::SyncAll(sync_all) => sync_all.run(),
+            Action::ListBackups(list_backups) => list_backups.run(),
+            Action::RestoreBackup(restore_backup) => restore_backup.run(),
+            Action::Status(status) => status.run(),
+            Action::Verify(verify) => verify.run(),
+            Action::RebuildMap(rebuild_map) => rebuild_map.run(),
+            Action::Uninstall(uninstall) => uninstall.run(),
+            Action::Upgrade(upgrade) => upgrade.run(),
             Action::SyncRefs(sync_refs) => sync_refs.run(),
         }
     }
@@ -2,6 +2,17 @@ use crate::action;
 pub use crate::action::EnvDetect;
 use crate::action::{Action, SubGitEnv};
 use crate::make_absolute;
+use crate::model::excludes::Excludes;
+use crate::model::mailmap::Identities;
+use crate::model::protection::{Protections, RefProtection};
+use crate::model::ref_filters::RefFilters;
+use crate::model::ref_names::RefNames;
+use crate::model::settings::IdentitySettings;
+use crate::model::settings::PathMapping;
+use crate::model::settings::RefRename;
+use crate::model::settings::{HookPaths, SyncSettings};
+use crate::model::signing::SignedCommitPolicy;
+use crate::model::uninstall::DataDisposal;
 use crate::model::settings::SETTINGS_FILE;
 use git2::Oid;
 use log::LevelFilter;
@@ -10,7 +21,7 @@ use std::env;
 use std::ffi::OsString;
 use std::fs::{canonicalize, read_link};
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
 use failure::format_err;
@@ -40,13 +51,76 @@ fn parse_env_base_recursion_detection(input: &&str) -> EnvDetect {
     }
 }
 
+fn parse_path_mapping(input: &str) -> Result<PathMapping, failure::Error> {
+    let mut iter = input.splitn(2, ":");
+    match (iter.next(), iter.next()) {
+        (Some(upstream), Some(subgit)) if !upstream.is_empty() => Ok(PathMapping {
+            upstream: upstream.trim_matches('/').to_string(),
+            subgit: subgit.trim_matches('/').to_string(),
+        }),
+        _ => Err(format_err!("Bad path mapping '{}' - expected UPSTREAM_PATH:SUBGIT_PATH", input)),
+    }
+}
+
+fn parse_ref_rename(input: &str) -> Result<RefRename, failure::Error> {
+    let mut iter = input.splitn(2, ":");
+    match (iter.next(), iter.next()) {
+        (Some(upstream), Some(subgit)) if !upstream.is_empty() && !subgit.is_empty() => Ok(RefRename {
+            upstream: upstream.to_string(),
+            subgit: subgit.to_string(),
+        }),
+        _ => Err(format_err!("Bad ref rename '{}' - expected UPSTREAM_REF:SUBGIT_REF", input)),
+    }
+}
+
+// Mapped directories can't nest, or the same files would be claimed by two mappings
+fn check_mappings(mappings: &[PathMapping]) -> Result<(), failure::Error> {
+    let overlaps = |a: &str, b: &str| {
+        let (a, b): (&Path, &Path) = (a.as_ref(), b.as_ref());
+        a.starts_with(b) || b.starts_with(a)
+    };
+    for (idx, mapping) in mappings.iter().enumerate() {
+        for other in mappings.iter().skip(idx + 1) {
+            if overlaps(&mapping.upstream, &other.upstream) {
+                return Err(format_err!("Upstream paths '{}' and '{}' overlap", mapping.upstream, other.upstream));
+            }
+            if overlaps(&mapping.subgit, &other.subgit) {
+                return Err(format_err!("Subgit paths '{}' and '{}' overlap", mapping.subgit, other.subgit));
+            }
+        }
+    }
+    Ok(())
+}
+
 fn str_to_vec(input: String) -> Vec<String> {
     let iter = input.split(",");
     iter.map(|v| v.to_owned()).collect()
 }
 
+fn read_lines(path: Option<&PathBuf>) -> Result<Vec<String>, failure::Error> {
+    match path {
+        Some(path) => Ok(std::fs::read_to_string(path)?.lines().map(|line| line.to_owned()).collect()),
+        None => Ok(vec![]),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn test_path_mappings() {
+        let mapping = super::parse_path_mapping("docs/public/:docs").unwrap();
+        assert_eq!(mapping.upstream, "docs/public");
+        assert_eq!(mapping.subgit, "docs");
+        assert!(super::parse_path_mapping("docs").is_err());
+
+        let core = super::parse_path_mapping("libs/core:core").unwrap();
+        assert!(super::check_mappings(&[core.clone(), mapping.clone()]).is_ok());
+        let root = super::parse_path_mapping("libs/other:").unwrap();
+        assert!(super::check_mappings(&[core.clone(), root]).is_err());
+        let nested = super::parse_path_mapping("libs:other").unwrap();
+        assert!(super::check_mappings(&[core, nested]).is_err());
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(
@@ -86,6 +160,52 @@ pub struct SetupRequest {
     #[structopt(short = "p", long = "subgit_map_path")]
     pub subgit_map_path: Option<String>,
 
+    /// Additional paths to republish, in the form of UPSTREAM_PATH:SUBGIT_PATH - can be specified multiple times
+    /// None of the upstream paths may contain each other, and neither may the subgit paths
+    #[structopt(short = "M", long = "map")]
+    pub extra_mappings: Vec<String>,
+
+    /// Paths inside the mapped directories to never publish to the subgit, as gitignore style patterns
+    /// (e.g. 'internal/' or '*.secret.yaml') - can be specified multiple times
+    /// Subgit pushes that add matching files are rejected
+    #[structopt(short = "x", long = "exclude")]
+    pub excludes: Vec<String>,
+
+    /// Adds an 'Upstream-Commit: <sha>' trailer to imported commits, and a 'Subgit-Commit: <sha>' trailer
+    /// to exported commits, pointing back to the commit they were copied from
+    #[structopt(short = "t", long = "trailers")]
+    pub trailers: bool,
+
+    /// A mailmap file to rewrite the authors and committers of the commits imported into the subgit with
+    #[structopt(short = "i", long = "import_mailmap", parse(from_os_str))]
+    pub import_mailmap: Option<PathBuf>,
+
+    /// A mailmap file to rewrite the authors and committers of the commits exported to the upstream with
+    #[structopt(short = "e", long = "export_mailmap", parse(from_os_str))]
+    pub export_mailmap: Option<PathBuf>,
+
+    /// The identity to use as the committer of all synchronized commits, in the form of 'Name <email>'
+    /// The authors are kept (or mapped by the mailmap files)
+    #[structopt(short = "c", long = "sync_committer")]
+    pub sync_committer: Option<String>,
+
+    /// What to do with signed commits, since their signatures can't be carried over - one of 'strip' (drops the
+    /// signature and adds a Signature-Stripped trailer), 'refuse', 'resign-gpg:KEY_ID' or 'resign-ssh:KEY_FILE'
+    #[structopt(short = "s", long = "signed_commits", default_value = "strip")]
+    pub signed_commits: SignedCommitPolicy,
+
+    /// Publishes an upstream ref under another name in the subgit, in the form of UPSTREAM_REF:SUBGIT_REF - can
+    /// be specified multiple times. Both names may contain a single '*' (e.g. 'refs/tags/component-x-v*:refs/tags/v*')
+    /// Refs that aren't renamed keep their name, unless a rename claims it
+    #[structopt(short = "R", long = "rename_ref")]
+    pub rename_refs: Vec<String>,
+
+    /// Mirrors upstream review refs read-only into the subgit, in the form of UPSTREAM_REF:SUBGIT_REF like --rename_ref
+    /// (e.g. 'refs/merge-requests/*/head:refs/review/*') - can be specified multiple times
+    /// Only the ones with changes to the mapped paths are mirrored, and pushes to them are rejected
+    #[structopt(short = "v", long = "review_refs")]
+    pub review_refs: Vec<String>,
+
     /// The log level to use when logging to file from the hooks
     #[structopt(short = "l", long = "log_level")]
     pub log_level: Option<LevelFilter>,
@@ -102,13 +222,14 @@ pub struct SetupRequest {
     )]
     pub upstream_hook_path: PathBuf,
     /// The hook path to use in the subgit repository
-    #[structopt(
-        short = "h",
-        long = "subgit_hook_path",
-        default_value = "hooks/update",
-        parse(from_os_str)
-    )]
-    pub subgit_hook_path: PathBuf,
+    /// Defaults to hooks/update, or hooks/pre-receive with --atomic
+    #[structopt(short = "h", long = "subgit_hook_path", parse(from_os_str))]
+    pub subgit_hook_path: Option<PathBuf>,
+
+    /// Installs the subgit hook as a pre-receive hook, which exports all the refs of a push to the upstream
+    /// in a single atomic push - the whole push is accepted or rejected together
+    #[structopt(short = "a", long = "atomic")]
+    pub atomic: bool,
 
     /// Specify an external url to push changes to, when exporting commits to the upstream from the subgit
     /// If not set, uses the file path to the upstream repo
@@ -142,9 +263,239 @@ pub struct SetupRequest {
     )]
     pub disable_recursion_detection: bool,
 
-    /// Only operate on the refs that start with these values - pass in a comma separated list
-    #[structopt(short = "m", long = "match_ref", default_value = "refs/heads/,HEAD")]
+    /// Only operate on the matching refs - pass in a comma separated list of ref prefixes, globs
+    /// (refs/heads/release/*) or anchored regexes (^refs/heads/[0-9]+$). Prefix an entry with ! to
+    /// exclude the refs it matches; the last matching entry wins
+    #[structopt(short = "m", long = "match_ref", default_value = "refs/heads/,refs/tags/,HEAD")]
     pub match_ref: String,
+
+    /// Restricts the updates pushed to the subgit, in the form of RESTRICTION[,RESTRICTION]:REF_PATTERN - can be
+    /// specified multiple times. The restrictions are deny-create, deny-delete and deny-non-ff, and the pattern is
+    /// a ref prefix, glob or anchored regex like in --match_ref (e.g. 'deny-delete,deny-non-ff:refs/heads/master')
+    #[structopt(short = "P", long = "protect")]
+    pub protections: Vec<RefProtection>,
+}
+
+/// Imports every matching upstream ref into the subgit, and removes the subgit refs that no upstream
+/// ref corresponds to anymore. Run it from the subgit repository as 'GIT_DIR=. data/hook sync-all'
+#[derive(Clone, StructOpt)]
+#[structopt(name = "sync-all")]
+pub struct SyncAllRequest {
+    /// Only list the subgit refs that would be removed, without changing anything
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Subgit refs to keep even though the upstream doesn't have them, as a ref prefix, glob or anchored
+    /// regex like in --match_ref - can be specified multiple times
+    #[structopt(short = "k", long = "keep")]
+    pub keep: Vec<String>,
+}
+
+/// Lists the subgit tips saved when an upstream force push was imported, as 'SHA BACKUP_REF' lines.
+/// Run it from the subgit repository as 'GIT_DIR=. data/hook list-backups'
+#[derive(Clone, StructOpt)]
+#[structopt(name = "list-backups")]
+pub struct ListBackupsRequest {}
+
+/// Restores a backed up subgit tip as a new ref, and exports it to the upstream.
+/// Run it from the subgit repository as 'GIT_DIR=. data/hook restore-backup BACKUP_REF [REF]'
+#[derive(Clone, StructOpt)]
+#[structopt(name = "restore-backup")]
+pub struct RestoreBackupRequest {
+    /// The backup to restore, as listed by list-backups (e.g. refs/subgit-backup/heads/master/20240101T120000Z)
+    pub backup: String,
+
+    /// The ref to restore it as - defaults to the ref it was backed up from, which must not exist anymore
+    pub ref_name: Option<String>,
+}
+
+/// Reports the sync state of every ref matching the filters: the upstream sha, the subgit sha it
+/// translates to, the actual subgit sha and a verdict (in-sync, behind, diverged or missing).
+/// It doesn't need a hook environment - run it as 'subgit-sync status SUBGIT_GIT_LOCATION'
+#[derive(Clone, StructOpt)]
+#[structopt(name = "status")]
+pub struct StatusRequest {
+    /// The path to the subgit repository
+    #[structopt(parse(from_os_str))]
+    pub subgit_git_location: PathBuf,
+
+    /// Print the refs as a JSON array, for monitoring
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+impl StatusRequest {
+    fn convert(self) -> Result<Action, failure::Error> {
+        Ok(Action::Status(action::Status {
+            git_dir: open_subgit_location(&self.subgit_git_location)?,
+            json: self.json,
+        }))
+    }
+}
+
+/// Checks that the mapped commits of the upstream and the subgit have the same content in the mapped
+/// directories and consistent parents, and lists every mismatch with both shas. It doesn't need a hook
+/// environment - run it as 'subgit-sync verify SUBGIT_GIT_LOCATION'
+#[derive(Clone, StructOpt)]
+#[structopt(name = "verify")]
+pub struct VerifyRequest {
+    /// The path to the subgit repository
+    #[structopt(parse(from_os_str))]
+    pub subgit_git_location: PathBuf,
+
+    /// Only check the commits the synced refs point to, instead of every commit in the map
+    #[structopt(long = "tips")]
+    pub tips: bool,
+}
+
+/// Rebuilds the commit map (data/map.sqlite) from the history of the upstream and the subgit, into a
+/// new database to look over before it replaces the old one. It doesn't need a hook environment - run
+/// it as 'subgit-sync rebuild-map SUBGIT_GIT_LOCATION'
+#[derive(Clone, StructOpt)]
+#[structopt(name = "rebuild-map")]
+pub struct RebuildMapRequest {
+    /// The path to the subgit repository
+    #[structopt(parse(from_os_str))]
+    pub subgit_git_location: PathBuf,
+
+    /// Where to write the new database, which must not exist yet - defaults to data/map.rebuilt.sqlite
+    /// in the subgit repository
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    pub output: Option<PathBuf>,
+}
+
+/// Takes subgit-sync out of the subgit and upstream repositories: removes the hooks that still point at
+/// the subgit's hook, deletes the refs/sync refs and archives (or deletes) the data directory. It doesn't
+/// need a hook environment - run it as 'subgit-sync uninstall SUBGIT_GIT_LOCATION'
+#[derive(Clone, StructOpt)]
+#[structopt(name = "uninstall")]
+pub struct UninstallRequest {
+    /// The path to the subgit repository
+    #[structopt(parse(from_os_str))]
+    pub subgit_git_location: PathBuf,
+
+    /// Only list what would be removed, without changing anything
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Where to move the data directory - defaults to SUBGIT_GIT_LOCATION.subgit-data-TIMESTAMP next to
+    /// the subgit repository
+    #[structopt(short = "a", long = "archive", parse(from_os_str))]
+    pub archive: Option<PathBuf>,
+
+    /// Delete the data directory instead of archiving it
+    #[structopt(long = "delete-data", conflicts_with = "archive")]
+    pub delete_data: bool,
+}
+
+/// Upgrades an installed subgit to this binary: swaps it in as data/hook and migrates the settings and
+/// the map to its format. It refuses to run while a sync is in progress. It doesn't need a hook
+/// environment - run the new binary as 'subgit-sync upgrade SUBGIT_GIT_LOCATION'
+#[derive(Clone, StructOpt)]
+#[structopt(name = "upgrade")]
+pub struct UpgradeRequest {
+    /// The path to the subgit repository
+    #[structopt(parse(from_os_str))]
+    pub subgit_git_location: PathBuf,
+}
+
+fn open_subgit_location(location: &Path) -> Result<PathBuf, failure::Error> {
+    let git_dir = canonicalize(location).map_err(|err| format_err!("Cannot open {:?}: {}", location, err))?;
+    if !git_dir.join("data").join(SETTINGS_FILE).is_file() {
+        return Err(format_err!("{:?} is not a subgit repository", location));
+    }
+    Ok(git_dir)
+}
+
+impl VerifyRequest {
+    fn convert(self) -> Result<Action, failure::Error> {
+        Ok(Action::Verify(action::Verify {
+            git_dir: open_subgit_location(&self.subgit_git_location)?,
+            tips: self.tips,
+        }))
+    }
+}
+
+impl RebuildMapRequest {
+    fn convert(self) -> Result<Action, failure::Error> {
+        let git_dir = open_subgit_location(&self.subgit_git_location)?;
+        let output = match self.output {
+            Some(output) => make_absolute(output)?,
+            None => git_dir.join("data").join("map.rebuilt.sqlite"),
+        };
+        Ok(Action::RebuildMap(action::RebuildMap { git_dir, output }))
+    }
+}
+
+impl UninstallRequest {
+    fn convert(self) -> Result<Action, failure::Error> {
+        let archive = match self.archive {
+            Some(archive) if archive.exists() => return Err(format_err!("{:?} already exists", archive)),
+            Some(archive) => Some(make_absolute(archive)?),
+            None => None,
+        };
+        Ok(Action::Uninstall(action::Uninstall {
+            git_dir: open_subgit_location(&self.subgit_git_location)?,
+            dry_run: self.dry_run,
+            data: if self.delete_data {
+                DataDisposal::Delete
+            } else {
+                DataDisposal::Archive(archive)
+            },
+        }))
+    }
+}
+
+impl UpgradeRequest {
+    fn convert(self) -> Result<Action, failure::Error> {
+        Ok(Action::Upgrade(action::Upgrade {
+            git_dir: open_subgit_location(&self.subgit_git_location)?,
+            binary: env::current_exe()?,
+        }))
+    }
+}
+
+/// Parses the commands that work the same in and outside of the hooks, before the environment is detected
+pub fn parse_standalone_command(args: &[String]) -> Option<Result<Action, failure::Error>> {
+    match args.get(1).map(String::as_str) {
+        Some("status") => Some(
+            StatusRequest::from_iter_safe(&args[1..])
+                .map_err(|err| format_err!("{}", err))
+                .and_then(StatusRequest::convert),
+        ),
+        Some("verify") => Some(
+            VerifyRequest::from_iter_safe(&args[1..])
+                .map_err(|err| format_err!("{}", err))
+                .and_then(VerifyRequest::convert),
+        ),
+        Some("rebuild-map") => Some(
+            RebuildMapRequest::from_iter_safe(&args[1..])
+                .map_err(|err| format_err!("{}", err))
+                .and_then(RebuildMapRequest::convert),
+        ),
+        Some("uninstall") => Some(
+            UninstallRequest::from_iter_safe(&args[1..])
+                .map_err(|err| format_err!("{}", err))
+                .and_then(UninstallRequest::convert),
+        ),
+        Some("upgrade") => Some(
+            UpgradeRequest::from_iter_safe(&args[1..])
+                .map_err(|err| format_err!("{}", err))
+                .and_then(UpgradeRequest::convert),
+        ),
+        _ => None,
+    }
+}
+
+impl SyncAllRequest {
+    fn convert(self, env: SubGitEnv) -> Result<Action, failure::Error> {
+        RefFilters::new(&self.keep)?;
+        Ok(Action::SyncAll(action::SyncAll {
+            env,
+            dry_run: self.dry_run,
+            keep: self.keep,
+        }))
+    }
 }
 
 impl SetupRequest {
@@ -165,31 +516,93 @@ impl SetupRequest {
             }
         };
 
+        let mut mappings = vec![PathMapping {
+            upstream: self.upstream_map_path.trim_matches('/').to_string(),
+            subgit: self.subgit_map_path.unwrap_or_default().trim_matches('/').to_string(),
+        }];
+        for extra in &self.extra_mappings {
+            mappings.push(parse_path_mapping(extra)?);
+        }
+        check_mappings(&mappings)?;
+        Excludes::new(&self.excludes)?;
+        let identities = IdentitySettings {
+            import: read_lines(self.import_mailmap.as_ref())?,
+            export: read_lines(self.export_mailmap.as_ref())?,
+            committer: self.sync_committer,
+        };
+        Identities::new(&identities)?;
+        let ref_names = self
+            .rename_refs
+            .iter()
+            .map(|rename| parse_ref_rename(rename))
+            .collect::<Result<Vec<RefRename>, failure::Error>>()?;
+        let review_refs = self
+            .review_refs
+            .iter()
+            .map(|review| parse_ref_rename(review))
+            .collect::<Result<Vec<RefRename>, failure::Error>>()?;
+        RefNames::new(&[ref_names.clone(), review_refs.clone()].concat())?;
+        let filters = str_to_vec(self.match_ref);
+        RefFilters::new(&filters)?;
+        Protections::new(&self.protections)?;
+        let atomic = self.atomic;
+        let subgit_hook_path = self
+            .subgit_hook_path
+            .unwrap_or_else(|| PathBuf::from(if atomic { "hooks/pre-receive" } else { "hooks/update" }));
+
         Ok(Action::Setup(action::Setup {
             copy_from,
             upstream_git_location: PathBuf::from(self.upstream_git_location),
             subgit_git_location: PathBuf::from(self.subgit_git_location),
 
-            upstream_map_path: PathBuf::from(self.upstream_map_path),
-            subgit_map_path: self.subgit_map_path.map(|v| PathBuf::from(v)),
-
-            log_level: self.log_level.unwrap_or(LevelFilter::Debug),
+            settings: SyncSettings {
+                mappings,
+                excludes: self.excludes,
+                trailers: self.trailers,
+                identities,
+                signed_commits: self.signed_commits,
+                ref_names,
+                review_refs,
+                file_log_level: self.log_level.unwrap_or(LevelFilter::Debug),
+                recursion_detection,
+                filters,
+                protections: self.protections,
+            },
             log_file: self
                 .log_file
                 .unwrap_or(PathBuf::from("git_subgit_setup.log")),
 
-            subgit_hook_path: self.subgit_hook_path,
+            hooks: HookPaths {
+                subgit: subgit_hook_path,
+                upstream: self.upstream_hook_path,
+            },
             subgit_working_clone_url: self.subgit_working_clone_url,
-            upstream_hook_path: self.upstream_hook_path,
             upstream_working_clone_url: self.upstream_working_clone_url,
-
-            recursion_detection,
-
-            filters: str_to_vec(self.match_ref),
         }))
     }
 }
 
+/// Reads the 'OLD_SHA NEW_SHA REF_NAME' lines that git passes to the post-receive and pre-receive hooks
+fn read_ref_updates() -> Result<Vec<(Oid, Oid, String)>, failure::Error> {
+    let mut s = String::new();
+    std::io::stdin().read_to_string(&mut s)?;
+
+    s.lines()
+        .map(|v| v.trim())
+        .map(|line| {
+            let entries = line.split(" ").collect::<Vec<&str>>();
+            match entries[..] {
+                [old_sha, new_sha, ref_name] => Ok((
+                    Oid::from_str(old_sha)?,
+                    Oid::from_str(new_sha)?,
+                    ref_name.to_string(),
+                )),
+                _ => Err(format_err!("Bad args")),
+            }
+        })
+        .collect()
+}
+
 #[allow(unused)]
 fn read_to_string<R: Read>(readable: &mut R) -> String {
     let mut s = String::new();
@@ -241,24 +654,14 @@ impl ExecEnv {
     {
         match self {
             ExecEnv::Upstream(env) => {
-                let mut s = String::new();
-                std::io::stdin().read_to_string(&mut s).unwrap();
-
-                let reqs = s
-                    .lines()
-                    .map(|v| v.trim())
-                    .map(|line| -> Result<action::RefSyncRequest, failure::Error> {
-                        let entries = line.split(" ").collect::<Vec<&str>>();
-                        match entries[..] {
-                            [old_sha, new_sha, ref_name] => Ok(action::RefSyncRequest {
-                                ref_name: ref_name.to_string(),
-                                old_upstream_sha: Oid::from_str(old_sha)?,
-                                new_upstream_sha: Oid::from_str(new_sha)?,
-                            }),
-                            _ => Err(format_err!("Bad args")),
-                        }
+                let reqs = read_ref_updates()?
+                    .into_iter()
+                    .map(|(old_sha, new_sha, ref_name)| action::RefSyncRequest {
+                        ref_name,
+                        old_upstream_sha: old_sha,
+                        new_upstream_sha: new_sha,
                     })
-                    .collect::<Result<Vec<action::RefSyncRequest>, failure::Error>>()?;
+                    .collect();
 
                 Ok(Action::SyncRefs(action::SyncRefs {
                     env,
@@ -273,10 +676,36 @@ impl ExecEnv {
                     .collect();
                 string_args.iter().for_each(|v| debug!("Arg: {}", v));
                 match args.len() {
-                    2 => match string_args[1].as_str() {
-                        "sync-all" => Ok(Action::SyncAll(action::SyncAll { env })),
-                        bad_arg => Err(format_err!("Invalid argument: '{}'", bad_arg)),
-                    },
+                    _ if string_args.get(1).map(|arg| arg == "sync-all").unwrap_or(false) => {
+                        SyncAllRequest::from_iter_safe(&string_args[1..])
+                            .map_err(|err| format_err!("{}", err))?
+                            .convert(env)
+                    }
+                    1 => Ok(Action::PreReceiveHook(action::PreReceiveHook {
+                        env,
+                        updates: read_ref_updates()?
+                            .into_iter()
+                            .map(|(old_sha, new_sha, ref_name)| action::RefUpdateRequest {
+                                ref_name,
+                                old_sha,
+                                new_sha,
+                            })
+                            .collect(),
+                    })),
+                    _ if string_args.get(1).map(|arg| arg == "list-backups").unwrap_or(false) => {
+                        ListBackupsRequest::from_iter_safe(&string_args[1..]).map_err(|err| format_err!("{}", err))?;
+                        Ok(Action::ListBackups(action::ListBackups { env }))
+                    }
+                    _ if string_args.get(1).map(|arg| arg == "restore-backup").unwrap_or(false) => {
+                        let request = RestoreBackupRequest::from_iter_safe(&string_args[1..])
+                            .map_err(|err| format_err!("{}", err))?;
+                        Ok(Action::RestoreBackup(action::RestoreBackup {
+                            env,
+                            backup: request.backup,
+                            ref_name: request.ref_name,
+                        }))
+                    }
+                    2 => Err(format_err!("Invalid argument: '{}'", string_args[1])),
                     4 => Ok(Action::UpdateHook(action::UpdateHook {
                         env,
                         ref_name: string_args.get(1).unwrap().clone(),
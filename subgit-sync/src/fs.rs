@@ -16,6 +16,7 @@ pub fn remove_if_exists<P: AsRef<Path>>(path: P) -> Result<(), failure::Error> {
     Ok(())
 }
 
+#[allow(unused)]
 pub fn content_of_file_if_exists<P: AsRef<Path>>(path: &P) -> Option<String> {
     let path: &Path = path.as_ref();
     if !path.exists() {
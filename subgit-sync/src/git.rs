@@ -1,7 +1,9 @@
 use git2;
-use git2::{Commit, Oid, Repository, Signature, Sort};
+use git2::{Commit, ObjectType, Odb, Oid, Repository, Signature, Sort, Tree};
 use std;
-use std::path::Path;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use failure::format_err;
 
 pub fn get_git_options() -> Option<Vec<String>> {
@@ -29,6 +31,21 @@ pub fn optionify_sha(oid: Oid) -> Option<Oid> {
     }
 }
 
+/// The signature as it's written in the headers of an object, like "Name <email> 1500000000 +0200"
+pub fn signature_header(signature: &Signature) -> String {
+    let when = signature.when();
+    let offset = when.offset_minutes();
+    format!(
+        "{} <{}> {} {}{:02}{:02}",
+        signature.name().unwrap_or(""),
+        signature.email().unwrap_or(""),
+        when.seconds(),
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
 pub fn no_sha() -> Oid {
     Oid::from_str("0000000000000000000000000000000000000000").unwrap()
 }
@@ -80,7 +97,7 @@ pub fn set_push_simple(repo: &Repository) {
     config.set_str("push.default", "simple").unwrap();
 }
 
-fn find_earliest_commit_on_folder(repo: &Repository, target: &str) -> Option<Oid> {
+fn find_earliest_commit_on_folder(repo: &Repository, targets: &[String]) -> Option<Oid> {
     //git rev-list --reverse --topo-order HEAD --
     let mut process = std::process::Command::new("git");
     process
@@ -92,11 +109,11 @@ fn find_earliest_commit_on_folder(repo: &Repository, target: &str) -> Option<Oid
     process.arg("--topo-order");
     process.arg("HEAD");
     process.arg("--");
-    process.arg(target);
+    process.args(targets);
 
     process.current_dir(repo.path());
 
-    debug!("Finding earliest commit in  {:?} / {}", repo.path(), target);
+    debug!("Finding earliest commit in  {:?} / {:?}", repo.path(), targets);
 
     let result = process.output().unwrap();
 
@@ -113,8 +130,8 @@ fn find_earliest_commit_on_folder(repo: &Repository, target: &str) -> Option<Oid
     }
 }
 
-pub fn find_safe_empty_na_commits(repo: &Repository, target: &str) -> Vec<Oid> {
-    let earliest = find_earliest_commit_on_folder(repo, target);
+pub fn find_safe_empty_na_commits(repo: &Repository, targets: &[String]) -> Vec<Oid> {
+    let earliest = find_earliest_commit_on_folder(repo, targets);
     let mut rev_walk = repo.revwalk().unwrap();
     if let Some(earliest_oid) = earliest {
         rev_walk.push(earliest_oid).unwrap();
@@ -147,6 +164,7 @@ pub fn fetch_all_ext(repo: &Repository) -> Result<(), failure::Error> {
         .env_clear()
         .env("PATH", std::env::var("PATH").unwrap());
     process.arg("fetch");
+    process.arg("--no-tags");
     process.arg("origin");
     process.arg("+refs/heads/*:refs/heads/*");
     process.arg("+refs/sync/*:refs/sync/*");
@@ -208,6 +226,9 @@ pub fn get_n_recent_shas(repo: &Repository, n: usize) -> Vec<Oid> {
 pub fn clone_remote<S: AsRef<str>, P: AsRef<Path>>(url: S, parent: P, name: &str) {
     std::process::Command::new("git")
         .arg("clone")
+        .arg("--no-checkout")
+        // Tags are pushed by sha, so the working clones don't need any - they would only go stale
+        .arg("--no-tags")
         .arg(url.as_ref())
         .arg(name)
         .current_dir(parent.as_ref())
@@ -219,6 +240,7 @@ pub fn clone_remote<S: AsRef<str>, P: AsRef<Path>>(url: S, parent: P, name: &str
 
 pub fn push_sha_ext<S: AsRef<str>>(
     repo: &Repository,
+    sha: Oid,
     ref_name: S,
     force_push: bool,
     git_push_options: Option<Vec<String>>,
@@ -238,16 +260,17 @@ pub fn push_sha_ext<S: AsRef<str>>(
     process.arg("origin");
     if force_push {
         info!("Force pushing");
-        process.arg(format!("+HEAD:{}", ref_name.as_ref()));
+        process.arg(format!("+{}:{}", sha, ref_name.as_ref()));
     } else {
         info!("Not force pushing");
-        process.arg(format!("HEAD:{}", ref_name.as_ref()));
+        process.arg(format!("{}:{}", sha, ref_name.as_ref()));
     }
 
     process.current_dir(repo.workdir().unwrap());
 
     info!(
-        "Pushing 'HEAD:{}' from {:?}",
+        "Pushing '{}:{}' from {:?}",
+        sha,
         ref_name.as_ref(),
         repo.workdir()
     );
@@ -265,6 +288,45 @@ pub fn push_sha_ext<S: AsRef<str>>(
     Ok(())
 }
 
+/// Pushes several refspecs at once, so that either all of the refs are updated or none is
+pub fn push_atomic(
+    repo: &Repository,
+    refspecs: &[String],
+    git_push_options: Option<Vec<String>>,
+) -> Result<(), failure::Error> {
+    let mut process = std::process::Command::new("git");
+    process
+        .env_clear()
+        .env("PATH", std::env::var("PATH").unwrap());
+    process.arg("push");
+    process.arg("--atomic");
+
+    if let Some(git_push_opts) = git_push_options {
+        for val in git_push_opts {
+            process.arg(format!("--push-option={}", val));
+        }
+    };
+
+    process.arg("origin");
+    process.args(refspecs);
+
+    process.current_dir(repo.workdir().unwrap());
+
+    info!("Pushing {:?} atomically from {:?}", refspecs, repo.workdir());
+
+    let result = process.output()?;
+
+    if !result.status.success() {
+        return Err(format_err!(
+                "Could not push - exit code was {}. Full result of push: {}",
+                &result.status,
+                String::from_utf8(result.stderr)?
+            ));
+    }
+
+    Ok(())
+}
+
 pub fn delete_remote_branch<S: AsRef<str>>(
     repo: &Repository,
     ref_name: S,
@@ -322,6 +384,240 @@ pub fn commit_empty(
     )?)
 }
 
+/// Finds the tree at the given path of a tree, returning None if there isn't a directory at that path
+pub fn find_subtree(tree: &Tree, path: &Path) -> Option<Oid> {
+    if path.as_os_str().is_empty() {
+        return Some(tree.id());
+    }
+    match tree.get_path(path) {
+        Ok(ref entry) if entry.kind() == Some(ObjectType::Tree) => Some(entry.id()),
+        _ => None,
+    }
+}
+
+/// Copies a tree and everything it references from the source object database to the dest object
+/// database, skipping any objects that already exist in the dest. Submodule (gitlink) entries are
+/// kept as is, since the commits they point to live in another repository.
+pub fn copy_tree(
+    source: &Repository,
+    dest: &Repository,
+    tree_oid: Oid,
+) -> Result<Oid, failure::Error> {
+    let source_odb = source.odb()?;
+    let dest_odb = dest.odb()?;
+    copy_tree_objects(source, &source_odb, &dest_odb, tree_oid)
+}
+
+// Odb::exists can't be trusted in this version of git2 - it treats "not found" as found
+fn odb_has(odb: &Odb, oid: Oid) -> bool {
+    odb.read_header(oid).is_ok()
+}
+
+fn copy_tree_objects(
+    source: &Repository,
+    source_odb: &Odb,
+    dest_odb: &Odb,
+    tree_oid: Oid,
+) -> Result<Oid, failure::Error> {
+    if odb_has(dest_odb, tree_oid) {
+        return Ok(tree_oid);
+    }
+    let tree = source.find_tree(tree_oid)?;
+    for entry in tree.iter() {
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                copy_tree_objects(source, source_odb, dest_odb, entry.id())?;
+            }
+            Some(ObjectType::Blob) if !odb_has(dest_odb, entry.id()) => {
+                let blob = source_odb.read(entry.id())?;
+                dest_odb.write(blob.kind(), blob.data())?;
+            }
+            _ => {}
+        }
+    }
+    let raw_tree = source_odb.read(tree_oid)?;
+    Ok(dest_odb.write(raw_tree.kind(), raw_tree.data())?)
+}
+
+/// Like copy_tree, but leaves out the entries that the filter excludes. The filter is given the path of the
+/// entry relative to the copied tree, and whether it's a directory. Returns None if nothing is left.
+pub fn copy_tree_filtered<F: Fn(&Path, bool) -> bool>(
+    source: &Repository,
+    dest: &Repository,
+    tree_oid: Oid,
+    excluded: &F,
+) -> Result<Option<Oid>, failure::Error> {
+    let source_odb = source.odb()?;
+    let dest_odb = dest.odb()?;
+    copy_filtered_tree_objects(source, &source_odb, dest, &dest_odb, tree_oid, Path::new(""), excluded)
+}
+
+fn copy_filtered_tree_objects<F: Fn(&Path, bool) -> bool>(
+    source: &Repository,
+    source_odb: &Odb,
+    dest: &Repository,
+    dest_odb: &Odb,
+    tree_oid: Oid,
+    prefix: &Path,
+    excluded: &F,
+) -> Result<Option<Oid>, failure::Error> {
+    let tree = source.find_tree(tree_oid)?;
+    let mut builder = dest.treebuilder(None)?;
+    for entry in tree.iter() {
+        let name = OsStr::from_bytes(entry.name_bytes());
+        let path = prefix.join(name);
+        let is_dir = entry.kind() == Some(ObjectType::Tree);
+        if excluded(&path, is_dir) {
+            continue;
+        }
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                let filtered = copy_filtered_tree_objects(source, source_odb, dest, dest_odb, entry.id(), &path, excluded)?;
+                if let Some(child_oid) = filtered {
+                    builder.insert(name, child_oid, entry.filemode())?;
+                }
+            }
+            Some(ObjectType::Blob) => {
+                if !odb_has(dest_odb, entry.id()) {
+                    let blob = source_odb.read(entry.id())?;
+                    dest_odb.write(blob.kind(), blob.data())?;
+                }
+                builder.insert(name, entry.id(), entry.filemode())?;
+            }
+            _ => {
+                builder.insert(name, entry.id(), entry.filemode())?;
+            }
+        }
+    }
+    if builder.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(builder.write()?))
+    }
+}
+
+/// Lists the entries of a tree, recursively, that the filter matches as (path, oid, filemode).
+/// Doesn't descend into matching directories.
+pub fn find_matching_entries<F: Fn(&Path, bool) -> bool>(
+    repo: &Repository,
+    tree: &Tree,
+    matches: &F,
+) -> Result<Vec<(PathBuf, Oid, i32)>, failure::Error> {
+    let mut found = Vec::new();
+    find_matching_entries_under(repo, tree, Path::new(""), matches, &mut found)?;
+    Ok(found)
+}
+
+fn find_matching_entries_under<F: Fn(&Path, bool) -> bool>(
+    repo: &Repository,
+    tree: &Tree,
+    prefix: &Path,
+    matches: &F,
+    found: &mut Vec<(PathBuf, Oid, i32)>,
+) -> Result<(), failure::Error> {
+    for entry in tree.iter() {
+        let path = prefix.join(OsStr::from_bytes(entry.name_bytes()));
+        let is_dir = entry.kind() == Some(ObjectType::Tree);
+        if matches(&path, is_dir) {
+            found.push((path, entry.id(), entry.filemode()));
+        } else if is_dir {
+            find_matching_entries_under(repo, &repo.find_tree(entry.id())?, &path, matches, found)?;
+        }
+    }
+    Ok(())
+}
+
+/// Builds a new tree from the base tree, with the entry at the given path replaced by the given subtree,
+/// or removed if there isn't one. Any directories left empty by a removal are removed as well.
+pub fn graft_tree(
+    repo: &Repository,
+    base: Option<&Tree>,
+    path: &Path,
+    subtree: Option<Oid>,
+) -> Result<Oid, failure::Error> {
+    graft_entry(repo, base, path, subtree.map(|oid| (oid, 0o040000)))
+}
+
+/// Like graft_tree, but places a regular file with the given content at the path
+pub fn graft_file(
+    repo: &Repository,
+    base: Option<&Tree>,
+    path: &Path,
+    content: Option<&[u8]>,
+) -> Result<Oid, failure::Error> {
+    let entry = match content {
+        Some(content) => Some((repo.blob(content)?, 0o100644)),
+        None => None,
+    };
+    graft_entry(repo, base, path, entry)
+}
+
+/// Like graft_tree, but places an arbitrary entry (oid and filemode) at the path
+pub fn graft_entry(
+    repo: &Repository,
+    base: Option<&Tree>,
+    path: &Path,
+    entry: Option<(Oid, i32)>,
+) -> Result<Oid, failure::Error> {
+    let components: Vec<&OsStr> = path.iter().collect();
+    match graft_components(repo, base, &components, entry)? {
+        Some((oid, _)) => Ok(oid),
+        None => Ok(repo.treebuilder(None)?.write()?),
+    }
+}
+
+fn graft_components(
+    repo: &Repository,
+    base: Option<&Tree>,
+    components: &[&OsStr],
+    entry: Option<(Oid, i32)>,
+) -> Result<Option<(Oid, i32)>, failure::Error> {
+    let (name, rest) = match components.split_first() {
+        Some((name, rest)) => (name.to_string_lossy(), rest),
+        None => return Ok(entry),
+    };
+    let child_base = match base.and_then(|tree| tree.get_name(&name)) {
+        Some(ref entry) if entry.kind() == Some(ObjectType::Tree) => Some(repo.find_tree(entry.id())?),
+        _ => None,
+    };
+    let child = graft_components(repo, child_base.as_ref(), rest, entry)?;
+
+    let mut builder = repo.treebuilder(base)?;
+    match child {
+        Some((child_oid, child_mode)) => {
+            builder.insert(name.as_ref(), child_oid, child_mode)?;
+        }
+        None => {
+            if builder.get(name.as_ref())?.is_some() {
+                builder.remove(name.as_ref())?;
+            }
+        }
+    }
+    if builder.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some((builder.write()?, 0o040000)))
+    }
+}
+
+/// Reads the content of the blob at the given path of a tree, if there is one
+pub fn read_blob_at(repo: &Repository, tree: &Tree, path: &Path) -> Option<Vec<u8>> {
+    match tree.get_path(path) {
+        Ok(ref entry) if entry.kind() == Some(ObjectType::Blob) => {
+            repo.find_blob(entry.id()).ok().map(|blob| blob.content().to_owned())
+        }
+        _ => None,
+    }
+}
+
+/// The commit an object (e.g. a tag) points to, if it points to one at all
+pub fn peel_to_commit(repo: &Repository, oid: Oid) -> Option<Oid> {
+    repo.find_object(oid, None)
+        .and_then(|object| object.peel(ObjectType::Commit))
+        .map(|commit| commit.id())
+        .ok()
+}
+
 pub fn get_refs(repo: &Repository, glob: &str) -> Result<Vec<(String, Oid)>, failure::Error> {
     let ref_list: Result<Vec<(String, Oid)>, _> = repo
         .references_glob(glob)?
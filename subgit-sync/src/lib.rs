@@ -9,10 +9,12 @@ extern crate serde_json;
 
 extern crate fs2;
 extern crate git2;
+extern crate glob;
 extern crate hex;
 extern crate libc;
 extern crate log_panics;
 extern crate nix;
+extern crate regex;
 extern crate simplelog;
 
 mod action;
@@ -29,9 +31,14 @@ pub use crate::model::WrappedSubGit;
 pub use crate::util::fork_into_child;
 pub use crate::util::StringError;
 pub use crate::cli::SetupRequest;
+pub use crate::model::signing::SignedCommitPolicy;
+pub use crate::model::protection::RefProtection;
 
 pub fn run() -> Result<(), failure::Error> {
-    let exec_env = cli::ExecEnv::detect();
-    let action = exec_env.parse_command(std::env::args())?;
+    let args: Vec<String> = std::env::args().collect();
+    let action = match cli::parse_standalone_command(&args) {
+        Some(action) => action?,
+        None => cli::ExecEnv::detect().parse_command(args)?,
+    };
     action.run()
 }
@@ -0,0 +1,103 @@
+use crate::git;
+use chrono::Utc;
+use failure::format_err;
+use git2::{Oid, Repository};
+
+/// Where the subgit tips that a non-fast-forward import would have dropped are kept
+pub const BACKUP_PREFIX: &str = "refs/subgit-backup/";
+
+/// A saved subgit tip, named like refs/subgit-backup/heads/master/20240101T120000Z-<sha>
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backup {
+    pub name: String,
+    /// The ref it was the tip of
+    pub ref_name: String,
+    pub timestamp: String,
+    pub sha: Oid,
+}
+
+impl Backup {
+    pub fn parse(name: &str, sha: Oid) -> Option<Backup> {
+        let rest = name.strip_prefix(BACKUP_PREFIX)?;
+        let (ref_part, last) = rest.rsplit_once('/')?;
+        let (timestamp, tip) = last.split_once('-')?;
+        if ref_part.is_empty() || timestamp.is_empty() || tip.len() != 40 || Oid::from_str(tip).is_err() {
+            return None;
+        }
+        Some(Backup {
+            name: name.to_owned(),
+            ref_name: format!("refs/{}", ref_part),
+            timestamp: timestamp.to_owned(),
+            sha,
+        })
+    }
+}
+
+pub fn is_backup_ref(ref_name: &str) -> bool {
+    ref_name.starts_with(BACKUP_PREFIX)
+}
+
+/// The name to back up the current tip of the ref under. The tip is part of the name, so that two tips
+/// dropped within the same second don't end up under the same name
+pub fn backup_ref_name(ref_name: &str, tip: Oid) -> String {
+    format!(
+        "{}{}/{}-{}",
+        BACKUP_PREFIX,
+        ref_name.strip_prefix("refs/").unwrap_or(ref_name),
+        Utc::now().format("%Y%m%dT%H%M%SZ"),
+        tip
+    )
+}
+
+/// The backups in the repo, oldest first for each ref
+pub fn list(repo: &Repository) -> Result<Vec<Backup>, failure::Error> {
+    let mut backups: Vec<Backup> = git::get_refs(repo, &format!("{}**", BACKUP_PREFIX))?
+        .into_iter()
+        .filter_map(|(name, sha)| Backup::parse(&name, sha))
+        .collect();
+    backups.sort_by(|a, b| (&a.ref_name, &a.timestamp, &a.name).cmp(&(&b.ref_name, &b.timestamp, &b.name)));
+    Ok(backups)
+}
+
+pub fn find(repo: &Repository, name: &str) -> Result<Backup, failure::Error> {
+    list(repo)?
+        .into_iter()
+        .find(|backup| backup.name == name)
+        .ok_or_else(|| format_err!("Could not find the backup {}", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{backup_ref_name, Backup};
+    use git2::Oid;
+
+    #[test]
+    fn test_backup_names() {
+        let sha = Oid::from_str("0123456789012345678901234567890123456789").unwrap();
+        let name = format!("refs/subgit-backup/heads/feature/x/20240101T120000Z-{}", sha);
+        assert_eq!(
+            Some(Backup {
+                name: name.clone(),
+                ref_name: "refs/heads/feature/x".to_owned(),
+                timestamp: "20240101T120000Z".to_owned(),
+                sha,
+            }),
+            Backup::parse(&name, sha)
+        );
+        assert_eq!(None, Backup::parse("refs/heads/master", sha));
+        assert_eq!(None, Backup::parse(&format!("refs/subgit-backup/master-{}", sha), sha));
+        assert_eq!(None, Backup::parse("refs/subgit-backup/heads/master/20240101T120000Z", sha));
+        assert_eq!(None, Backup::parse("refs/subgit-backup/heads/master/20240101T120000Z-0123", sha));
+        assert_eq!(None, Backup::parse(&format!("refs/subgit-backup/heads/master/-{}", sha), sha));
+
+        let name = backup_ref_name("refs/tags/v1", sha);
+        let backup = Backup::parse(&name, sha).unwrap();
+        assert_eq!("refs/tags/v1", backup.ref_name);
+        assert!(name.starts_with("refs/subgit-backup/tags/v1/"));
+        assert!(name.ends_with(&format!("Z-{}", sha)));
+        assert_eq!(16, backup.timestamp.len());
+
+        let other = Oid::from_str("9876543210987654321098765432109876543210").unwrap();
+        assert_ne!(name, backup_ref_name("refs/tags/v1", other));
+    }
+}
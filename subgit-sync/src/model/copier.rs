@@ -1,17 +1,22 @@
+use super::backups;
+use super::excludes::Excludes;
+use super::gitmodules;
+use super::Location;
+use super::mailmap::Identities;
 use super::map::CommitMapper;
+use super::ref_names::RefNames;
+use super::signing::{self, SignedCommitPolicy, SigningKey};
+use super::trailers;
 use crate::action::PushListener;
+use crate::action::RecursionDetection;
 use crate::git;
-use git2::{
-    build::CheckoutBuilder, Commit, Delta, Index, IndexAddOption, ObjectType, Oid, Repository,
-    ResetType,
-};
-use std::fs;
-use std::fs::OpenOptions;
-use std::io::Write;
+use failure::format_err;
+use git2::{Commit, ObjectType, Oid, Repository, Tag, Tree};
 use std::path::{Path, PathBuf};
 
 pub struct GitLocation<'a> {
-    pub location: &'a Path,
+    /// The mapped directories, in the same order on both sides of a copier
+    pub locations: Vec<&'a Path>,
     pub name: super::Location,
     pub bare: &'a Repository,
     pub working: &'a Repository,
@@ -20,21 +25,41 @@ pub struct GitLocation<'a> {
 pub struct Copier<'a> {
     pub source: GitLocation<'a>,
     pub dest: GitLocation<'a>,
+    pub excludes: &'a Excludes,
+    /// Whether to add trailers pointing back to the source commits
+    pub trailers: bool,
+    pub identities: &'a Identities,
+    pub signed_commits: &'a SignedCommitPolicy,
+    pub ref_names: &'a RefNames,
     pub mapper: CommitMapper<'a>,
 }
 
-impl<'a> GitLocation<'a> {
-    fn working_index(&self) -> Index {
-        self.working.index().unwrap()
-    }
+/// A ref (named like in the source) with its old and new source shas
+pub type RefChange = (String, Option<Oid>, Option<Oid>);
+
+/// How a ref has to change in the destination, once its commits are copied
+enum RefCopy {
+    /// Nothing to push - holds the destination sha to report, if any
+    Skip(Option<Oid>),
+    Delete { dest_ref: String },
+    Update {
+        dest_ref: String,
+        new_sha: Oid,
+        force_push: bool,
+        /// The subgit tip that the update drops, which is saved first
+        backup: Option<Oid>,
+    },
+}
 
-    fn workdir(&self) -> &Path {
-        &self
-            .working
-            .workdir()
-            .expect("The map repo must have a workdir")
-    }
+/// The ref being copied, which picks between the counterparts of a commit that has more than one
+struct CopyTarget<'r> {
+    source_ref: &'r str,
+    dest_ref: &'r str,
+    /// Where the ref points in the destination before the copy
+    dest_tip: Option<Oid>,
+}
 
+impl<'a> GitLocation<'a> {
     pub fn get_commits_between(
         &self,
         starting_shas: Vec<Oid>,
@@ -97,15 +122,7 @@ impl<'a> Copier<'a> {
                 .get_commits_between(vec![starting_sha], dest_sha_inclusive)
         } else {
             let mut starting_shas: Vec<Oid> = git::get_n_recent_shas(self.dest.bare, 10);
-            starting_shas.push(
-                self.dest
-                    .working
-                    .find_reference("HEAD")
-                    .unwrap()
-                    .peel_to_commit()
-                    .unwrap()
-                    .id(),
-            );
+            starting_shas.push(self.empty_sha());
             starting_shas = starting_shas
                 .iter()
                 .map(|sha| self.get_source_sha(sha))
@@ -115,20 +132,15 @@ impl<'a> Copier<'a> {
         }
     }
 
-    fn translate(&'a self, path: &Path) -> Option<PathBuf> {
-        let chopped = path.strip_prefix(self.source.location).ok();
-        chopped.map(|new_path| self.dest.workdir().join(self.dest.location.join(new_path)))
-    }
-
-    fn record_sha_update(&'a self, source_sha: &Oid, dest_sha: Oid) -> Oid {
+    fn record_sha_update(&'a self, source_sha: &Oid, dest_sha: Oid, target: &CopyTarget) -> Oid {
         info!(
-            "Mapping {} <-> {} ({} <-> {})",
-            source_sha, dest_sha, self.source.name, self.dest.name
+            "Mapping {} <-> {} ({} <-> {}) for {}",
+            source_sha, dest_sha, self.source.name, self.dest.name, target.source_ref
         );
         self.mapper
-            .set_translated(source_sha, self.source.name, &dest_sha);
+            .set_translated(source_sha, self.source.name, &dest_sha, Some(target.source_ref));
         self.mapper
-            .set_translated(&dest_sha, self.dest.name, source_sha);
+            .set_translated(&dest_sha, self.dest.name, source_sha, Some(target.dest_ref));
         dest_sha
     }
 
@@ -141,9 +153,24 @@ impl<'a> Copier<'a> {
             .expect("An empty commit should be an oid, not another reference")
     }
 
-    fn get_dest_sha(&'a self, source_sha: &Oid) -> Oid {
+    fn location_pairs(&self) -> Vec<(&'a Path, &'a Path)> {
+        self.source
+            .locations
+            .iter()
+            .cloned()
+            .zip(self.dest.locations.iter().cloned())
+            .collect()
+    }
+
+    fn get_dest_sha(&'a self, source_sha: &Oid, target: &CopyTarget) -> Oid {
         self.mapper
-            .get_translated(Some(source_sha), self.source.name)
+            .get_translated_for_ref(
+                Some(source_sha),
+                self.source.name,
+                target.source_ref,
+                self.dest.bare,
+                target.dest_tip,
+            )
             .unwrap()
     }
 
@@ -154,10 +181,13 @@ impl<'a> Copier<'a> {
     }
 
     pub fn import_initial_empty_commits(self) {
-        let commits_to_import = git::find_safe_empty_na_commits(
-            self.source.bare,
-            self.source.location.to_string_lossy().as_ref(),
-        );
+        let targets: Vec<String> = self
+            .source
+            .locations
+            .iter()
+            .map(|location| location.to_string_lossy().into_owned())
+            .collect();
+        let commits_to_import = git::find_safe_empty_na_commits(self.source.bare, &targets);
         if let Some(first_oid) = commits_to_import.first() {
             info!("Importing {} empty commits", commits_to_import.len());
             let empty_dest_sha = self.empty_sha();
@@ -166,12 +196,14 @@ impl<'a> Copier<'a> {
                     empty_source_sha,
                     self.source.name,
                     &empty_dest_sha,
+                    None,
                 );
             });
             self.mapper.set_translated(
                 &empty_dest_sha,
                 self.dest.name,
                 first_oid,
+                None,
             );
         }
         self.mapper.save_changes();
@@ -185,23 +217,140 @@ impl<'a> Copier<'a> {
         force_push: bool,
         git_push_opts: Option<Vec<String>>,
         push_listener: Option<PL>,
-    ) -> Option<Oid> {
+    ) -> Result<Option<Oid>, failure::Error> {
+        match self.prepare_ref(ref_name, supposed_old_source_sha, new_source_sha, force_push)? {
+            RefCopy::Skip(sha) => Ok(sha),
+            RefCopy::Delete { dest_ref } => {
+                if let Some(pl) = &push_listener {
+                    pl.pre_push(&dest_ref, git::no_sha());
+                }
+                git::delete_remote_branch(self.dest.working, &dest_ref, git_push_opts)
+                    .expect("Could not remove remote reference!");
+                if let Some(pl) = &push_listener {
+                    pl.post_push(&dest_ref, git::no_sha());
+                }
+                Ok(None)
+            }
+            RefCopy::Update {
+                dest_ref,
+                new_sha,
+                force_push,
+                backup,
+            } => {
+                if let Some(old_tip) = backup {
+                    let backup_ref = backups::backup_ref_name(&dest_ref, old_tip);
+                    warn!(
+                        "Importing {} into {} drops {} from the subgit - backing it up as {}",
+                        ref_name, dest_ref, old_tip, backup_ref
+                    );
+                    if let Some(pl) = &push_listener {
+                        pl.pre_push(&backup_ref, old_tip);
+                    }
+                    let res = git::push_sha_ext(self.dest.working, old_tip, &backup_ref, false, git_push_opts.clone());
+                    if let Some(pl) = &push_listener {
+                        pl.post_push(&backup_ref, old_tip);
+                    }
+                    // Nothing has been dropped yet, so the ref is left as it is
+                    res.map_err(|err| format_err!("Could not back up {} as {}: {}", old_tip, backup_ref, err))?;
+                }
+                if let Some(pl) = &push_listener {
+                    pl.pre_push(&dest_ref, new_sha);
+                }
+                let res = git::push_sha_ext(&self.dest.working, new_sha, &dest_ref, force_push, git_push_opts.clone());
+                if let Some(pl) = &push_listener {
+                    pl.post_push(&dest_ref, new_sha);
+                }
+                // A rejected push leaves the map and the notes as they were
+                res?;
+
+                self.mapper.save_changes_and_push_notes(&git_push_opts, &push_listener);
+
+                Ok(Some(new_sha))
+            }
+        }
+    }
+
+    /// Copies several refs, and updates them all in a single atomic push - either every ref is
+    /// updated in the destination, or none is (and the commit mappings aren't saved either)
+    pub fn copy_refs_atomically(
+        self,
+        refs: &[RefChange],
+        force_push: bool,
+        git_push_opts: Option<Vec<String>>,
+    ) -> Result<(), failure::Error> {
+        let mut refspecs: Vec<String> = vec![];
+        for (ref_name, old_source_sha, new_source_sha) in refs {
+            match self.prepare_ref(ref_name, *old_source_sha, *new_source_sha, force_push)? {
+                RefCopy::Skip(_) => (),
+                RefCopy::Delete { dest_ref } => refspecs.push(format!(":{}", dest_ref)),
+                RefCopy::Update {
+                    dest_ref,
+                    new_sha,
+                    force_push,
+                    ..
+                } => refspecs.push(format!("{}{}:{}", if force_push { "+" } else { "" }, new_sha, dest_ref)),
+            }
+        }
+
+        if !refspecs.is_empty() {
+            git::push_atomic(&self.dest.working, &refspecs, git_push_opts.clone())?;
+        }
+        self.mapper.save_changes_and_push_notes(&git_push_opts, &None::<&RecursionDetection>);
+
+        Ok(())
+    }
+
+    /// Copies the commits of a ref into the destination working repo, and works out how the
+    /// destination ref has to change - without pushing anything
+    fn prepare_ref(
+        &self,
+        ref_name: &str,
+        supposed_old_source_sha: Option<Oid>,
+        new_source_sha: Option<Oid>,
+        force_push: bool,
+    ) -> Result<RefCopy, failure::Error> {
         debug!(
             "Copying ref {:?} {:?}",
             supposed_old_source_sha, new_source_sha
         );
-        if new_source_sha == None {
-            if let Some(pl) = &push_listener {
-                pl.pre_push(&ref_name, git::no_sha());
-            }
-            git::delete_remote_branch(self.dest.working, &ref_name, git_push_opts)
-                .expect("Could not remove remote reference!");
-            if let Some(pl) = &push_listener {
-                pl.post_push(&ref_name, git::no_sha());
+        let dest_ref_name = match self.ref_names.translate(ref_name, self.dest.name) {
+            Some(name) => name,
+            None => {
+                warn!("Skipping {}, since its name is taken by a renamed ref in the {}", ref_name, self.dest.name);
+                return Ok(RefCopy::Skip(None));
             }
-            return None;
+        };
+        let dest_ref_name = dest_ref_name.as_str();
+        let target = CopyTarget {
+            source_ref: ref_name,
+            dest_ref: dest_ref_name,
+            dest_tip: self
+                .dest
+                .bare
+                .find_reference(dest_ref_name)
+                .and_then(|reference| reference.peel_to_commit())
+                .ok()
+                .map(|commit| commit.id()),
+        };
+        if new_source_sha == None {
+            return Ok(RefCopy::Delete {
+                dest_ref: dest_ref_name.to_owned(),
+            });
         }
 
+        // Tags can point to a tag object rather than a commit - the commits are copied up to the commit it
+        // points to, and then the tag object is recreated on top of the copy
+        let new_source_tag = self.source.bare.find_tag(new_source_sha.unwrap()).ok();
+        let new_source_sha = match git::peel_to_commit(self.source.bare, new_source_sha.unwrap()) {
+            Some(sha) => Some(sha),
+            None => {
+                warn!("Skipping {}, since it doesn't point to a commit", ref_name);
+                return Ok(RefCopy::Skip(None));
+            }
+        };
+        let supposed_old_source_sha =
+            supposed_old_source_sha.and_then(|source_sha| git::peel_to_commit(self.source.bare, source_sha));
+
         let old_source_sha = supposed_old_source_sha.and_then(|source_sha| {
             if self
                 .mapper
@@ -212,7 +361,7 @@ impl<'a> Copier<'a> {
             } else {
                 self.dest
                     .bare
-                    .find_reference(ref_name)
+                    .find_reference(dest_ref_name)
                     .ok()
                     .and_then(|reference| {
                         let old_dest_sha = reference.peel_to_commit().unwrap().id();
@@ -224,13 +373,30 @@ impl<'a> Copier<'a> {
             }
         });
 
-        if new_source_sha == old_source_sha {
-            return new_source_sha;
+        if new_source_sha == old_source_sha && new_source_tag.is_none() {
+            return Ok(RefCopy::Skip(new_source_sha));
         }
 
         let commits =
             self.get_unseen_source_commits_between(old_source_sha, &new_source_sha.unwrap()); //self.get_commits_to_import(old_upstream_sha, new_upstream_sha);
 
+        // Checked before anything is copied, so that the ref is left as it is rather than half copied
+        if let Some(signed) = self.refused_signed_commit(&commits) {
+            error!(
+                "Skipping {}, since {} is signed and signed commits are not synchronized",
+                ref_name, signed
+            );
+            return Ok(RefCopy::Skip(None));
+        }
+        if let Some(ref tag) = new_source_tag {
+            if *self.signed_commits == SignedCommitPolicy::Refuse
+                && signing::split_tag_signature(tag.message().unwrap_or("")).1.is_some()
+            {
+                error!("Skipping {}, since the tag is signed and signed tags are not synchronized", ref_name);
+                return Ok(RefCopy::Skip(None));
+            }
+        }
+
         let total_commits = commits.len();
         let mut current_commit = 0;
 
@@ -239,35 +405,27 @@ impl<'a> Copier<'a> {
             &total_commits, &ref_name
         );
 
-        commits
-            .into_iter()
-            .filter(|&oid| {
-                current_commit += 1;
-                if !self.mapper.has_sha(&oid, self.source.name) {
-                    debug!("Copying Commit ({}/{})", &current_commit, &total_commits);
-                    true
-                } else {
-                    debug!(
-                        "Skipping Commit ({}/{}) - already imported",
-                        &current_commit, &total_commits
-                    );
-                    false
-                }
-            })
-            //.take(20)
-            .map(|oid| self.copy_commit(&oid))
-            .last();
+        for oid in commits {
+            current_commit += 1;
+            if self.mapper.has_sha(&oid, self.source.name) {
+                debug!(
+                    "Skipping Commit ({}/{}) - already imported",
+                    &current_commit, &total_commits
+                );
+                continue;
+            }
+            debug!("Copying Commit ({}/{})", &current_commit, &total_commits);
+            self.copy_commit(&oid, &target)?;
+        }
 
         debug!("Copied commits - now copying branch");
-        let new_sha = self.get_dest_sha(&new_source_sha.unwrap());
-        self.dest
-            .working
-            .reset(
-                &self.dest.working.find_object(new_sha, None).unwrap(),
-                ResetType::Hard,
-                None,
-            )
-            .unwrap();
+        let new_sha = self.get_dest_sha(&new_source_sha.unwrap(), &target);
+        let new_sha = match new_source_tag {
+            Some(ref tag) => self.copy_tag(dest_ref_name, tag, new_sha)?,
+            None => new_sha,
+        };
+        // Tags are moved around freely, unlike branches
+        let force_push = force_push || dest_ref_name.starts_with("refs/tags/");
 
         debug!(
             "Source was {}, now assigning to {} in dest",
@@ -275,27 +433,219 @@ impl<'a> Copier<'a> {
             &new_sha
         );
 
-        if let Some(pl) = &push_listener {
-            pl.pre_push(&ref_name, new_sha);
+        Ok(RefCopy::Update {
+            dest_ref: dest_ref_name.to_owned(),
+            new_sha,
+            force_push,
+            backup: self.dropped_subgit_tip(target.dest_tip, new_sha),
+        })
+    }
+
+    /// The first signed commit among the ones about to be copied, when signed commits are refused
+    fn refused_signed_commit(&self, commits: &[Oid]) -> Option<Oid> {
+        if *self.signed_commits != SignedCommitPolicy::Refuse {
+            return None;
+        }
+        commits
+            .iter()
+            .filter(|oid| !self.mapper.has_sha(oid, self.source.name))
+            .find(|oid| signing::is_signed(self.source.bare, oid))
+            .cloned()
+    }
+
+    /// The current subgit tip when importing the new sha isn't a fast-forward, since the subgit
+    /// history only reachable from it would be lost
+    fn dropped_subgit_tip(&self, dest_tip: Option<Oid>, new_sha: Oid) -> Option<Oid> {
+        match self.dest.name {
+            Location::SUBGIT => (),
+            Location::UPSTREAM => return None,
         }
-        let res = git::push_sha_ext(&self.dest.working, ref_name, force_push, git_push_opts);
-        if let Some(pl) = &push_listener {
-            pl.post_push(&ref_name, new_sha);
+        let old_tip = dest_tip?;
+        let new_commit = git::peel_to_commit(self.dest.working, new_sha)?;
+        if old_tip == new_commit || self.dest.working.graph_descendant_of(new_commit, old_tip).unwrap_or(false) {
+            None
+        } else {
+            Some(old_tip)
         }
+    }
 
-        match &res {
-            Ok(_) => (),
-            Err(err) => eprint!("{}", &err),
+    /// Recreates an annotated tag in the destination, pointing to the copy of the commit it points to. The
+    /// tag object is written without a ref in the working repo, which would shadow a later tag of the same
+    /// name. Its signature can't be carried over, so it's stripped or replaced like the one of a commit.
+    fn copy_tag(&self, ref_name: &str, tag: &Tag, dest_sha: Oid) -> Result<Oid, failure::Error> {
+        let tagger = match tag.tagger() {
+            Some(tagger) => self.identities.author(&tagger, self.dest.name)?,
+            None => self.dest.working.find_commit(dest_sha)?.committer().to_owned(),
+        };
+        let name = match ref_name.strip_prefix("refs/tags/") {
+            Some(name) => name,
+            None => tag.name().unwrap_or(ref_name),
+        };
+        let (message, signature) = signing::split_tag_signature(tag.message().unwrap_or(""));
+        let message = match self.signed_commits {
+            SignedCommitPolicy::Strip if signature.is_some() => {
+                info!("Stripping the signature from the tag {}", tag.id());
+                trailers::append(message, trailers::SIGNATURE_STRIPPED, &tag.id().to_string())
+            }
+            _ => message.to_owned(),
         };
+        let mut content = format!(
+            "object {}\ntype commit\ntag {}\ntagger {}\n\n{}",
+            dest_sha,
+            name,
+            git::signature_header(&tagger),
+            message
+        );
+        if let SignedCommitPolicy::Resign(ref key) = self.signed_commits {
+            if signature.is_some() {
+                content.push_str(&key.sign(content.as_bytes())?);
+                info!("Re-signed the tag {}", tag.id());
+            }
+        }
+        info!("Copying the tag {} as {} pointing to {}", tag.id(), name, dest_sha);
+        Ok(self.dest.working.odb()?.write(ObjectType::Tag, content.as_bytes())?)
+    }
 
-        res.unwrap();
+    /// Merges the trees of two destination parents. Conflicts inside the mapped directories don't matter,
+    /// since those are replaced by the source commit afterwards - any other conflict would need a resolution
+    /// that nobody wrote, so the merge can't be copied.
+    fn merge_parent_trees(&self, first: Oid, second: Oid) -> Result<Tree<'a>, failure::Error> {
+        let repo = self.dest.working;
+        let mut index = repo.merge_commits(&repo.find_commit(first)?, &repo.find_commit(second)?, None)?;
+        let mut conflicts: Vec<PathBuf> = index
+            .iter()
+            // The stage of a conflicting entry isn't 0
+            .filter(|entry| (entry.flags >> 12) & 0x3 != 0)
+            .map(|entry| PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned()))
+            .collect();
+        dedup_vec(&mut conflicts);
+        for path in conflicts {
+            if !self.replaced_by_copy(&path) {
+                return Err(format_err!(
+                    "Can't copy the merge of {} and {}, since they conflict on {} outside of the mapped directories",
+                    first,
+                    second,
+                    path.to_string_lossy()
+                ));
+            }
+            index.remove_path(&path)?;
+        }
+        Ok(repo.find_tree(index.write_tree_to(repo)?)?)
+    }
 
-        self.mapper.save_changes();
+    /// Whether the destination path is overwritten by the mapped content of the copied commit - it's in a
+    /// mapped directory, and isn't an excluded file that's carried over from the destination parent
+    fn replaced_by_copy(&self, path: &Path) -> bool {
+        self.dest.locations.iter().any(|location| match path.strip_prefix(location) {
+            Ok(relative) => {
+                let carried_over = match self.dest.name {
+                    Location::UPSTREAM => relative
+                        .ancestors()
+                        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                        .any(|ancestor| self.excludes.is_excluded(ancestor, ancestor != relative)),
+                    Location::SUBGIT => false,
+                };
+                !carried_over
+            }
+            Err(_) => false,
+        })
+    }
 
-        Some(new_sha)
+    /// Replaces the (unsigned) commit with a copy signed by the server's key
+    fn resign(&self, unsigned_sha: Oid, key: &SigningKey) -> Result<Oid, failure::Error> {
+        let odb = self.dest.working.odb()?;
+        let unsigned = odb.read(unsigned_sha)?;
+        let signature = key.sign(unsigned.data())?;
+        Ok(self
+            .dest
+            .working
+            .commit_signed(std::str::from_utf8(unsigned.data())?, &signature, None)?)
     }
 
-    fn copy_commit(&'a self, source_sha: &Oid) -> Oid {
+    /// Copies a mapped directory over, leaving out anything that's excluded
+    fn copy_mapped_tree(&self, subtree_oid: Oid) -> Result<Option<Oid>, failure::Error> {
+        if self.excludes.is_empty() {
+            git::copy_tree(self.source.bare, self.dest.working, subtree_oid).map(Some)
+        } else {
+            git::copy_tree_filtered(self.source.bare, self.dest.working, subtree_oid, &|path, is_dir| {
+                self.excludes.is_excluded(path, is_dir)
+            })
+        }
+    }
+
+    /// The excluded files never make it to the subgit, so when exporting they're carried over from
+    /// the upstream parent instead
+    fn keep_excluded(
+        &self,
+        dest_parent_tree: &Tree,
+        dest_location: &Path,
+        subtree: Option<Oid>,
+    ) -> Result<Option<Oid>, failure::Error> {
+        if self.excludes.is_empty() {
+            return Ok(subtree);
+        }
+        if let Location::SUBGIT = self.dest.name {
+            return Ok(subtree);
+        }
+        let dest_parent_subtree = match git::find_subtree(dest_parent_tree, dest_location) {
+            Some(oid) => self.dest.working.find_tree(oid)?,
+            None => return Ok(subtree),
+        };
+        let excluded = git::find_matching_entries(self.dest.working, &dest_parent_subtree, &|path, is_dir| {
+            self.excludes.is_excluded(path, is_dir)
+        })?;
+        excluded.into_iter().try_fold(subtree, |subtree, (path, oid, filemode)| {
+            let base = match subtree {
+                Some(oid) => Some(self.dest.working.find_tree(oid)?),
+                None => None,
+            };
+            git::graft_entry(self.dest.working, base.as_ref(), &path, Some((oid, filemode))).map(Some)
+        })
+    }
+
+    /// Submodules are configured in the .gitmodules at the root of each repo, so their stanzas
+    /// get moved over with their paths rewritten, instead of the file being copied
+    fn translate_gitmodules(
+        &'a self,
+        source_tree: &Tree,
+        dest_parent_tree: &Tree,
+        dest_tree_oid: Oid,
+    ) -> Result<Oid, failure::Error> {
+        let gitmodules = Path::new(".gitmodules");
+        let source_content = git::read_blob_at(self.source.bare, source_tree, gitmodules);
+        let dest_content = git::read_blob_at(self.dest.working, dest_parent_tree, gitmodules);
+        if source_content.is_none() && dest_content.is_none() {
+            return Ok(dest_tree_oid);
+        }
+
+        let mut dest_tree = self.dest.working.find_tree(dest_tree_oid)?;
+        for (source_location, dest_location) in self.location_pairs() {
+            // The source's .gitmodules came along with the mapped dir - put back what the dest had there
+            if source_location.as_os_str().is_empty() && !dest_location.as_os_str().is_empty() {
+                let nested = dest_location.join(gitmodules);
+                let previous = git::read_blob_at(self.dest.working, dest_parent_tree, &nested);
+                let oid = git::graft_file(self.dest.working, Some(&dest_tree), &nested, previous.as_deref())?;
+                dest_tree = self.dest.working.find_tree(oid)?;
+            }
+        }
+
+        let source_content = source_content.map(|v| String::from_utf8_lossy(&v).into_owned());
+        let dest_content = dest_content.map(|v| String::from_utf8_lossy(&v).into_owned());
+        let translated = gitmodules::translate(
+            source_content.as_deref(),
+            dest_content.as_deref(),
+            &self.location_pairs(),
+            &|path| self.excludes.is_excluded(path, false) || self.excludes.is_excluded(path, true),
+        );
+        git::graft_file(
+            self.dest.working,
+            Some(&dest_tree),
+            gitmodules,
+            translated.as_ref().map(|v| v.as_bytes()),
+        )
+    }
+
+    fn copy_commit(&'a self, source_sha: &Oid, target: &CopyTarget) -> Result<Oid, failure::Error> {
         debug!(
             "Copying commit {} from '{}' to '{}'",
             source_sha, self.source.name, self.dest.name
@@ -313,7 +663,7 @@ impl<'a> Copier<'a> {
         // Get the dest parents
         let mut dest_parent_commit_shas = source_parent_shas
             .iter()
-            .map(|parent_sha| self.get_dest_sha(parent_sha))
+            .map(|parent_sha| self.get_dest_sha(parent_sha, target))
             .collect();
         dedup_vec(&mut dest_parent_commit_shas);
         // use the empty commit as a parent if there would be not parents otherwise
@@ -348,99 +698,55 @@ impl<'a> Copier<'a> {
 
         debug!("Dest parents: {:?}", dest_parent_commit_shas);
 
-        // Checkout the first dest parent
         let new_dest_head = *dest_parent_commit_shas.get(0).unwrap();
-        self.dest.working.set_head_detached(new_dest_head).unwrap();
-        debug!("Checked out the first parent in dest: {}", new_dest_head);
-        self.dest
+        let dest_parent_tree = self
+            .dest
             .working
-            .checkout_head(Some(CheckoutBuilder::new().force()))
+            .find_commit(new_dest_head)
+            .unwrap()
+            .tree()
             .unwrap();
-        info!("Set head to {}", new_dest_head);
+        // A merge has to keep what the other parent changed outside of the mapped directories as well
+        let dest_parent_tree = match dest_parent_commit_shas.as_slice() {
+            [first, second] => self.merge_parent_trees(*first, *second)?,
+            _ => dest_parent_tree,
+        };
 
         info!(
             "Copying {} with source parents of {:?}",
             source_sha, source_parent_shas
         );
-        let source_parent_tree = if source_parent_shas.len() > 1 {
-            dest_parent_commit_shas
-                .first()
-                .map(|dest_parent_sha| self.get_source_sha(dest_parent_sha))
-        } else {
-            source_parent_shas.first().map(|v| *v)
-        }
-        .map(|sha| self.source.bare.find_commit(sha).unwrap().tree().unwrap());
 
-        let diff = self
-            .source
-            .bare
-            .diff_tree_to_tree(
-                source_parent_tree.as_ref(),
-                Some(&source_commit.tree().unwrap()),
-                None,
-            )
-            .unwrap();
+        // Graft the mapped parts of the source commit onto the tree of the first dest parent
+        let source_tree = source_commit.tree().unwrap();
+        let dest_tree_oid = self
+            .location_pairs()
+            .into_iter()
+            .fold(dest_parent_tree.id(), |dest_tree_oid, (source_location, dest_location)| {
+                let source_subtree = match git::find_subtree(&source_tree, source_location) {
+                    Some(subtree_oid) => self
+                        .copy_mapped_tree(subtree_oid)
+                        .expect("Could not copy the mapped tree into the destination"),
+                    None => None,
+                };
+                let source_subtree = self
+                    .keep_excluded(&dest_parent_tree, dest_location, source_subtree)
+                    .expect("Could not keep the excluded files of the destination");
+                let dest_tree = self.dest.working.find_tree(dest_tree_oid).unwrap();
+                git::graft_tree(self.dest.working, Some(&dest_tree), dest_location, source_subtree)
+                    .expect("Could not build the destination tree")
+            });
+        let dest_tree_oid = self
+            .translate_gitmodules(&source_tree, &dest_parent_tree, dest_tree_oid)
+            .expect("Could not translate .gitmodules");
+        debug!(
+            "Built tree {} on top of {} (from {})",
+            dest_tree_oid,
+            dest_parent_tree.id(),
+            new_dest_head
+        );
 
-        let mut changes = false;
-
-        diff.deltas().for_each(|delta| {
-            let file_path = delta.new_file().path().expect("Bad git path");
-            self.translate(file_path)
-                .map(|applicable_path| debug!("{:?} {:?}", delta.status(), applicable_path));
-            match delta.status() {
-                Delta::Added => {
-                    self.translate(file_path).map(|applicable_path| {
-                        let parent = applicable_path.parent().expect("This path has a parent");
-                        fs::create_dir_all(parent)
-                            .expect(&format!("Could not create parent for {:?}", parent));
-                        let mut file = OpenOptions::new()
-                            .read(true)
-                            .write(true)
-                            .create(true)
-                            .open(&applicable_path)
-                            .expect(&format!("Could not open {:?}", applicable_path));
-                        let new_blob = self
-                            .source
-                            .bare
-                            .find_object(delta.new_file().id(), Some(ObjectType::Blob))
-                            .unwrap()
-                            .peel_to_blob()
-                            .unwrap();
-                        file.write_all(new_blob.content()).unwrap();
-                        changes = true;
-                    });
-                }
-                Delta::Modified => {
-                    self.translate(file_path).map(|applicable_path| {
-                        let mut file = OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .create(false)
-                            .open(&applicable_path)
-                            .expect(&format!("Could not open {:?}", applicable_path));
-                        let new_blob = self
-                            .source
-                            .bare
-                            .find_object(delta.new_file().id(), Some(ObjectType::Blob))
-                            .unwrap()
-                            .peel_to_blob()
-                            .unwrap();
-                        file.write_all(new_blob.content()).unwrap();
-                        changes = true
-                    });
-                }
-                Delta::Deleted => {
-                    self.translate(file_path).map(|applicable_path| {
-                        debug!("Removing {:?}", applicable_path);
-                        fs::remove_file(applicable_path).unwrap();
-                        changes = true
-                    });
-                }
-                other => {
-                    panic!("Cannot handle 'Delta::{:?}'", other);
-                }
-            }
-        });
+        let changes = dest_tree_oid != dest_parent_tree.id();
 
         if source_parent_shas.len() > 1 {
             debug!(
@@ -454,40 +760,53 @@ impl<'a> Copier<'a> {
 
         // Create a new commit if there are changes to record or if its a merge commit in the destination
         if dest_parent_commit_shas.len() > 1 || changes {
-            let mut index = self.dest.working_index();
-            index
-                .add_all(
-                    vec!["."],
-                    IndexAddOption::DEFAULT,
-                    Some(&mut |path, _| {
-                        debug!("Adding {:?} to index", path);
-                        0
-                    }),
-                )
-                .unwrap();
-            index.write().unwrap();
-            let index_tree_oid = index.write_tree().unwrap();
-            let index_tree = self.dest.working.find_tree(index_tree_oid).unwrap();
+            let dest_tree = self.dest.working.find_tree(dest_tree_oid).unwrap();
             let parent_commits: Vec<Commit> = dest_parent_commit_shas
                 .iter()
                 .map(|parent_sha| self.dest.working.find_commit(*parent_sha).unwrap())
                 .collect();
             let parent_commits_refs: Vec<&Commit> = parent_commits.iter().collect();
+            let message = if self.trailers {
+                trailers::translate(source_commit.message().unwrap(), self.source.name, self.dest.name, source_sha)
+            } else {
+                source_commit.message().unwrap().to_owned()
+            };
+            let signed = signing::is_signed(self.source.bare, source_sha);
+            let message = match self.signed_commits {
+                SignedCommitPolicy::Strip if signed => {
+                    info!("Stripping the signature from {}", source_sha);
+                    trailers::append(&message, trailers::SIGNATURE_STRIPPED, &source_sha.to_string())
+                }
+                _ => message,
+            };
 
             new_dest_sha = self
                 .dest
                 .working
                 .commit(
-                    Some("HEAD"),
-                    &source_commit.author(),
-                    &source_commit.committer(),
-                    source_commit.message().unwrap(),
-                    &index_tree,
+                    None,
+                    &self
+                        .identities
+                        .author(&source_commit.author(), self.dest.name)
+                        .expect("Could not map the author"),
+                    &self
+                        .identities
+                        .committer(&source_commit.committer(), self.dest.name)
+                        .expect("Could not map the committer"),
+                    &message,
+                    &dest_tree,
                     &parent_commits_refs,
                 )
                 .unwrap();
+
+            if let SignedCommitPolicy::Resign(ref key) = self.signed_commits {
+                if signed {
+                    new_dest_sha = self.resign(new_dest_sha, key).expect("Could not re-sign the commit");
+                    info!("Re-signed {} as {}", source_sha, new_dest_sha);
+                }
+            }
         }
 
-        return self.record_sha_update(source_sha, new_dest_sha);
+        Ok(self.record_sha_update(source_sha, new_dest_sha, target))
     }
 }
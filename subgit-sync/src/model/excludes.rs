@@ -0,0 +1,90 @@
+use glob::{MatchOptions, Pattern};
+use std::path::Path;
+
+/// A gitignore style pattern, matched against paths relative to a mapped directory
+///  * A pattern without a slash matches a file or directory name at any depth (`*.secret.yaml`)
+///  * A pattern with a slash is matched against the whole relative path (`docs/internal/*.md`)
+///  * A trailing slash only matches directories (`internal/`)
+struct Exclude {
+    pattern: Pattern,
+    anchored: bool,
+    dir_only: bool,
+}
+
+pub struct Excludes {
+    excludes: Vec<Exclude>,
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+impl Excludes {
+    pub fn new(patterns: &[String]) -> Result<Excludes, failure::Error> {
+        let excludes = patterns
+            .iter()
+            .map(|raw| {
+                let dir_only = raw.ends_with('/');
+                let trimmed = raw.trim_end_matches('/');
+                let anchored = trimmed.contains('/');
+                let pattern = Pattern::new(trimmed.trim_start_matches('/'))
+                    .map_err(|err| failure::format_err!("Bad exclude pattern '{}': {}", raw, err))?;
+                Ok(Exclude {
+                    pattern,
+                    anchored,
+                    dir_only,
+                })
+            })
+            .collect::<Result<Vec<Exclude>, failure::Error>>()?;
+        Ok(Excludes { excludes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.excludes.is_empty()
+    }
+
+    /// Whether the entry at the given path (relative to the mapped directory) is excluded.
+    /// Callers are expected to stop descending once a directory is excluded.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.excludes.iter().any(|exclude| {
+            if exclude.dir_only && !is_dir {
+                return false;
+            }
+            if exclude.anchored {
+                exclude.pattern.matches_path_with(path, MATCH_OPTIONS)
+            } else {
+                path.file_name()
+                    .map(|name| exclude.pattern.matches_with(&name.to_string_lossy(), MATCH_OPTIONS))
+                    .unwrap_or(false)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Excludes;
+    use std::path::Path;
+
+    #[test]
+    fn test_excludes() {
+        let excludes = Excludes::new(&[
+            "internal/".to_owned(),
+            "*.secret.yaml".to_owned(),
+            "/docs/draft-*.md".to_owned(),
+        ])
+        .unwrap();
+
+        assert!(excludes.is_excluded(Path::new("internal"), true));
+        assert!(excludes.is_excluded(Path::new("nested/internal"), true));
+        assert!(!excludes.is_excluded(Path::new("internal"), false));
+        assert!(excludes.is_excluded(Path::new("db.secret.yaml"), false));
+        assert!(excludes.is_excluded(Path::new("config/db.secret.yaml"), false));
+        assert!(!excludes.is_excluded(Path::new("config/db.yaml"), false));
+        assert!(excludes.is_excluded(Path::new("docs/draft-one.md"), false));
+        assert!(!excludes.is_excluded(Path::new("nested/docs/draft-one.md"), false));
+        assert!(!excludes.is_excluded(Path::new("docs/final.md"), false));
+    }
+}
@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+
+/// A section of a .gitmodules file, kept as the raw lines so that untouched sections are written back
+/// exactly as they were found
+#[derive(Debug, Clone, PartialEq)]
+struct Section {
+    /// The submodule name, if this is a [submodule "..."] section
+    name: Option<String>,
+    lines: Vec<String>,
+}
+
+impl Section {
+    fn value(&self, key: &str) -> Option<String> {
+        self.lines.iter().skip(1).filter_map(|line| parse_entry(line)).find_map(|(k, v)| {
+            if k.eq_ignore_ascii_case(key) {
+                Some(v)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        self.name.as_ref().and_then(|_| self.value("path")).map(PathBuf::from)
+    }
+
+    /// Moves the submodule to a new path, renaming it as well if its name was its old path
+    fn moved_to(&self, old_path: &Path, new_path: &Path) -> Section {
+        let old_path = old_path.to_string_lossy();
+        let new_path = new_path.to_string_lossy();
+        let name = self.name.as_ref().map(|name| {
+            if name.as_str() == old_path {
+                new_path.to_string()
+            } else {
+                name.clone()
+            }
+        });
+        let lines = self
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(idx, line)| {
+                if idx == 0 {
+                    format!("[submodule \"{}\"]", name.as_ref().unwrap())
+                } else {
+                    match parse_entry(line) {
+                        Some((key, _)) if key.eq_ignore_ascii_case("path") => {
+                            let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+                            format!("{}path = {}", indent, new_path)
+                        }
+                        _ => line.clone(),
+                    }
+                }
+            })
+            .collect();
+        Section { name, lines }
+    }
+}
+
+fn parse_entry(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.starts_with('#') || line.starts_with(';') {
+        return None;
+    }
+    let mut parts = line.splitn(2, '=');
+    let key = parts.next()?.trim();
+    let value = parts.next()?.trim().trim_matches('"');
+    Some((key.to_owned(), value.to_owned()))
+}
+
+fn parse_header(line: &str) -> Option<Option<String>> {
+    let line = line.trim();
+    if !line.starts_with('[') || !line.ends_with(']') {
+        return None;
+    }
+    let inner = line[1..line.len() - 1].trim();
+    match inner.strip_prefix("submodule") {
+        Some(name) => Some(Some(name.trim().trim_matches('"').to_owned())),
+        None => Some(None),
+    }
+}
+
+fn parse(content: &str) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    for line in content.lines() {
+        match parse_header(line) {
+            Some(name) => sections.push(Section {
+                name,
+                lines: vec![line.to_owned()],
+            }),
+            None => match sections.last_mut() {
+                Some(section) => section.lines.push(line.to_owned()),
+                None => sections.push(Section {
+                    name: None,
+                    lines: vec![line.to_owned()],
+                }),
+            },
+        }
+    }
+    sections
+}
+
+fn render(sections: &[Section]) -> String {
+    let mut content = String::new();
+    for line in sections.iter().flat_map(|section| section.lines.iter()) {
+        content.push_str(line);
+        content.push('\n');
+    }
+    content
+}
+
+fn relative_to<'a>(path: &'a Path, location: &Path) -> Option<&'a Path> {
+    path.strip_prefix(location).ok()
+}
+
+/// Builds the .gitmodules for a destination commit.
+///
+/// The submodules of the source that live under a mapped source location are moved under the matching
+/// dest location, replacing the ones the destination had there. Submodules of the destination outside of
+/// the mapped locations are left alone, in their original position, as are the ones that are excluded (given
+/// their path relative to the mapped directory). Returns None if there are no sections left.
+pub fn translate(
+    source: Option<&str>,
+    dest: Option<&str>,
+    locations: &[(&Path, &Path)],
+    excluded: &dyn Fn(&Path) -> bool,
+) -> Option<String> {
+    let mut translated: Vec<Section> = parse(source.unwrap_or(""))
+        .into_iter()
+        .filter_map(|section| {
+            let path = section.path()?;
+            locations.iter().find_map(|(source_location, dest_location)| {
+                let relative = relative_to(&path, source_location)?;
+                if excluded(relative) {
+                    None
+                } else if relative.as_os_str().is_empty() {
+                    Some(section.moved_to(&path, dest_location))
+                } else {
+                    Some(section.moved_to(&path, &dest_location.join(relative)))
+                }
+            })
+        })
+        .collect();
+
+    let mut sections = Vec::new();
+    for section in parse(dest.unwrap_or("")) {
+        match section.path() {
+            Some(ref path)
+                if locations.iter().any(|(_, dest_location)| {
+                    relative_to(path, dest_location).map(|relative| !excluded(relative)).unwrap_or(false)
+                }) =>
+            {
+                // Keep the position of a submodule that still exists
+                if let Some(idx) = translated.iter().position(|other| other.path().as_ref() == Some(path)) {
+                    sections.push(translated.remove(idx));
+                }
+            }
+            _ => sections.push(section),
+        }
+    }
+    sections.extend(translated);
+
+    if sections.iter().all(|section| section.name.is_none() && section.lines.iter().all(|line| line.trim().is_empty())) {
+        None
+    } else {
+        Some(render(&sections))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::translate;
+    use std::path::Path;
+
+    const UPSTREAM: &str = "[submodule \"sub/vendor/dep\"]\n\tpath = sub/vendor/dep\n\turl = https://example.com/dep.git\n\
+                            [submodule \"other\"]\n\tpath = other\n\turl = https://example.com/other.git\n";
+
+    #[test]
+    fn test_import_keeps_only_mapped_submodules() {
+        let subgit = translate(Some(UPSTREAM), None, &[(Path::new("sub"), Path::new(""))], &|_| false).unwrap();
+        assert_eq!(
+            "[submodule \"vendor/dep\"]\n\tpath = vendor/dep\n\turl = https://example.com/dep.git\n",
+            subgit
+        );
+    }
+
+    #[test]
+    fn test_export_round_trips() {
+        let subgit = translate(Some(UPSTREAM), None, &[(Path::new("sub"), Path::new(""))], &|_| false).unwrap();
+        let upstream = translate(Some(&subgit), Some(UPSTREAM), &[(Path::new(""), Path::new("sub"))], &|_| false).unwrap();
+        assert_eq!(UPSTREAM, upstream);
+    }
+
+    #[test]
+    fn test_export_removal() {
+        assert_eq!(
+            "[submodule \"other\"]\n\tpath = other\n\turl = https://example.com/other.git\n",
+            translate(None, Some(UPSTREAM), &[(Path::new(""), Path::new("sub"))], &|_| false).unwrap()
+        );
+        assert_eq!(None, translate(None, Some(UPSTREAM), &[(Path::new("sub"), Path::new(""))], &|_| false));
+    }
+
+    #[test]
+    fn test_excluded_submodules_stay_put() {
+        let excluded = |path: &Path| path.starts_with("vendor");
+        assert_eq!(None, translate(Some(UPSTREAM), None, &[(Path::new("sub"), Path::new(""))], &excluded));
+
+        let exported = translate(None, Some(UPSTREAM), &[(Path::new(""), Path::new("sub"))], &excluded).unwrap();
+        assert_eq!(UPSTREAM, exported);
+    }
+
+    #[test]
+    fn test_multiple_locations() {
+        let locations = [(Path::new("sub"), Path::new("sub")), (Path::new("other"), Path::new("more/other"))];
+        let subgit = translate(Some(UPSTREAM), None, &locations, &|_| false).unwrap();
+        assert_eq!(
+            "[submodule \"sub/vendor/dep\"]\n\tpath = sub/vendor/dep\n\turl = https://example.com/dep.git\n\
+             [submodule \"more/other\"]\n\tpath = more/other\n\turl = https://example.com/other.git\n",
+            subgit
+        );
+    }
+}
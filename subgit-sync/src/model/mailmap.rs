@@ -0,0 +1,195 @@
+use super::settings::IdentitySettings;
+use super::Location;
+use failure::format_err;
+use git2::Signature;
+
+/// A name and email, parsed from 'Name <email>'
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Identity {
+    pub fn parse(raw: &str) -> Result<Identity, failure::Error> {
+        match parse_identities(raw)?.as_slice() {
+            [(Some(name), email)] => Ok(Identity {
+                name: name.clone(),
+                email: email.clone(),
+            }),
+            _ => Err(format_err!("Expected an identity in the form of 'Name <email>', got '{}'", raw)),
+        }
+    }
+}
+
+/// Splits a line into its (optional name, email) pairs
+fn parse_identities(line: &str) -> Result<Vec<(Option<String>, String)>, failure::Error> {
+    let mut identities = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        let start = rest.find('<').ok_or_else(|| format_err!("Missing '<' in '{}'", line))?;
+        let end = rest.find('>').ok_or_else(|| format_err!("Missing '>' in '{}'", line))?;
+        if end < start {
+            return Err(format_err!("Bad identity in '{}'", line));
+        }
+        let name = rest[..start].trim();
+        identities.push((
+            if name.is_empty() { None } else { Some(name.to_owned()) },
+            rest[start + 1..end].trim().to_owned(),
+        ));
+        rest = rest[end + 1..].trim();
+    }
+    Ok(identities)
+}
+
+/// A single mailmap line
+#[derive(Debug)]
+struct Entry {
+    proper_name: Option<String>,
+    proper_email: Option<String>,
+    commit_name: Option<String>,
+    commit_email: String,
+}
+
+/// The rules from a git mailmap file, supporting all four forms of line:
+///  * `Proper Name <commit@email>`
+///  * `<proper@email> <commit@email>`
+///  * `Proper Name <proper@email> <commit@email>`
+///  * `Proper Name <proper@email> Commit Name <commit@email>`
+#[derive(Debug, Default)]
+pub struct Mailmap {
+    entries: Vec<Entry>,
+}
+
+impl Mailmap {
+    pub fn parse<S: AsRef<str>>(lines: &[S]) -> Result<Mailmap, failure::Error> {
+        let mut entries = Vec::new();
+        for line in lines {
+            let line = line.as_ref().split('#').next().unwrap();
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = match parse_identities(line)?.as_slice() {
+                [(name, email)] => Entry {
+                    proper_name: name.clone(),
+                    proper_email: None,
+                    commit_name: None,
+                    commit_email: email.clone(),
+                },
+                [(proper_name, proper_email), (commit_name, commit_email)] => Entry {
+                    proper_name: proper_name.clone(),
+                    proper_email: Some(proper_email.clone()),
+                    commit_name: commit_name.clone(),
+                    commit_email: commit_email.clone(),
+                },
+                _ => return Err(format_err!("Bad mailmap line '{}'", line)),
+            };
+            entries.push(entry);
+        }
+        Ok(Mailmap { entries })
+    }
+
+    /// Maps the name and email the way git does - the entries for just the email are combined (e.g. one
+    /// giving the name and another the email), and then an entry also matching the commit name wins over them
+    pub fn map(&self, name: &str, email: &str) -> (String, String) {
+        let email_only = self.entries.iter().filter(|entry| entry.commit_name.is_none());
+        let with_name = self.entries.iter().filter(|entry| entry.commit_name.as_deref() == Some(name));
+        email_only
+            .chain(with_name)
+            .filter(|entry| entry.commit_email.eq_ignore_ascii_case(email))
+            .fold((name.to_owned(), email.to_owned()), |(name, email), entry| {
+                (
+                    entry.proper_name.clone().unwrap_or(name),
+                    entry.proper_email.clone().unwrap_or(email),
+                )
+            })
+    }
+}
+
+/// How the authors and committers are rewritten when commits cross over
+pub struct Identities {
+    import: Mailmap,
+    export: Mailmap,
+    committer: Option<Identity>,
+}
+
+impl Identities {
+    pub fn new(settings: &IdentitySettings) -> Result<Identities, failure::Error> {
+        Ok(Identities {
+            import: Mailmap::parse(&settings.import)?,
+            export: Mailmap::parse(&settings.export)?,
+            committer: match settings.committer {
+                Some(ref committer) => Some(Identity::parse(committer)?),
+                None => None,
+            },
+        })
+    }
+
+    fn mailmap(&self, dest: Location) -> &Mailmap {
+        match dest {
+            Location::SUBGIT => &self.import,
+            Location::UPSTREAM => &self.export,
+        }
+    }
+
+    pub fn author(&self, author: &Signature, dest: Location) -> Result<Signature<'static>, failure::Error> {
+        let (name, email) = self.mailmap(dest).map(
+            &String::from_utf8_lossy(author.name_bytes()),
+            &String::from_utf8_lossy(author.email_bytes()),
+        );
+        Ok(Signature::new(&name, &email, &author.when())?)
+    }
+
+    /// The committer is either the configured sync identity, or is mapped like the author
+    pub fn committer(&self, committer: &Signature, dest: Location) -> Result<Signature<'static>, failure::Error> {
+        match self.committer {
+            Some(ref identity) => Ok(Signature::new(&identity.name, &identity.email, &committer.when())?),
+            None => self.author(committer, dest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Identity, Mailmap};
+
+    #[test]
+    fn test_mailmap() {
+        let mailmap = Mailmap::parse(&[
+            "# Comments and blank lines are skipped",
+            "",
+            "Jane Doe <jane@corp.example>",
+            "<jane@example.com> <jane@corp.example>",
+            "Joe <joe@example.com> <joe@corp.example>",
+            "Build Bot <bot@example.com> ci <shared@corp.example>",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            ("Jane Doe".to_owned(), "jane@example.com".to_owned()),
+            mailmap.map("jane", "Jane@Corp.example")
+        );
+        assert_eq!(("Joe".to_owned(), "joe@example.com".to_owned()), mailmap.map("joe", "joe@corp.example"));
+        assert_eq!(
+            ("Build Bot".to_owned(), "bot@example.com".to_owned()),
+            mailmap.map("ci", "shared@corp.example")
+        );
+        assert_eq!(
+            ("someone".to_owned(), "shared@corp.example".to_owned()),
+            mailmap.map("someone", "shared@corp.example")
+        );
+        assert!(Mailmap::parse(&["no email here"]).is_err());
+    }
+
+    #[test]
+    fn test_identity() {
+        assert_eq!(
+            Identity {
+                name: "Sync Bot".to_owned(),
+                email: "sync@example.com".to_owned()
+            },
+            Identity::parse("Sync Bot <sync@example.com>").unwrap()
+        );
+        assert!(Identity::parse("<sync@example.com>").is_err());
+    }
+}
@@ -1,17 +1,108 @@
-use git2::Oid;
+use crate::action::PushListener;
+use crate::git;
+use git2::{Oid, Repository, Signature};
 use hex;
 use rusqlite::Connection;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use rusqlite::Transaction;
+use std::cell::RefCell;
+
+/// Where the mapping is shown to users - each commit gets a note naming its counterpart
+pub const NOTES_REF: &str = "refs/notes/subgit";
 
 pub struct CommitMapper<'a> {
     conn: Transaction<'a>,
+    notes: Option<MapNotes<'a>>,
+    /// The notes for the mappings recorded so far, which are only written once the mappings are saved
+    pending_notes: RefCell<Vec<(Oid, super::Location, Oid)>>,
+}
+
+/// The working repositories to write the mapping notes in, which are pushed along with the synced refs
+pub struct MapNotes<'a> {
+    pub upstream: &'a Repository,
+    pub subgit: &'a Repository,
+}
+
+impl<'a> MapNotes<'a> {
+    fn repo(&self, location: super::Location) -> &'a Repository {
+        match location {
+            super::Location::UPSTREAM => self.upstream,
+            super::Location::SUBGIT => self.subgit,
+        }
+    }
+
+    fn write(&self, sha: &Oid, source: super::Location, translated: &Oid) {
+        let counterpart = match source {
+            super::Location::UPSTREAM => super::Location::SUBGIT,
+            super::Location::SUBGIT => super::Location::UPSTREAM,
+        };
+        let signature = Signature::now("subgit-sync", "subgit-sync@localhost").unwrap();
+        self.repo(source)
+            .note(
+                &signature,
+                &signature,
+                Some(NOTES_REF),
+                *sha,
+                &format!("{}: {}\n", super::trailers::trailer_key(counterpart), translated),
+                true,
+            )
+            .expect("Could not write the mapping note");
+    }
+
+    /// Pushes the notes like the synced refs - the push listener only applies to pushes into the subgit
+    fn push<PL: PushListener>(&self, git_push_opts: &Option<Vec<String>>, push_listener: &Option<PL>) {
+        for (repo, listener) in &[(self.upstream, None), (self.subgit, push_listener.as_ref())] {
+            let notes_sha = match repo.refname_to_id(NOTES_REF) {
+                Ok(sha) => sha,
+                Err(_) => continue,
+            };
+            if let Some(pl) = listener {
+                pl.pre_push(NOTES_REF, notes_sha);
+            }
+            let res = git::push_sha_ext(repo, notes_sha, NOTES_REF, false, git_push_opts.clone());
+            if let Some(pl) = listener {
+                pl.post_push(NOTES_REF, notes_sha);
+            }
+            // The mapping itself lives in sqlite, so failing to publish it isn't fatal
+            if let Err(err) = res {
+                warn!("Could not push the mapping notes from {:?}: {}", repo.workdir(), err);
+            }
+        }
+    }
 }
 
 pub struct ReadOnlyMapper<'a> {
     conn: &'a Connection
 }
 
+/// The tables of map files made before mappings were tied to refs, which lack the ref_name column
+pub fn tables_without_ref_name(conn: &Connection) -> Result<Vec<&'static str>, failure::Error> {
+    let mut tables = vec![];
+    for location in &[super::Location::UPSTREAM, super::Location::SUBGIT] {
+        let table = location.as_source_table();
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let mut rows = stmt.query(&[] as &[&dyn rusqlite::types::ToSql])?;
+        let mut has_ref_name = false;
+        while let Some(row) = rows.next() {
+            let name: String = row?.get(1);
+            has_ref_name = has_ref_name || name == "ref_name";
+        }
+        if !has_ref_name {
+            tables.push(table);
+        }
+    }
+    Ok(tables)
+}
+
+/// Adds the ref_name column to the tables of map files made before mappings were tied to refs
+pub fn upgrade_schema(conn: &Connection) -> Result<(), failure::Error> {
+    for table in tables_without_ref_name(conn)? {
+        info!("Adding the ref_name column to {}", table);
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN ref_name TEXT", table), &[] as &[&dyn rusqlite::types::ToSql])?;
+    }
+    Ok(())
+}
+
 impl<'a> ReadOnlyMapper<'a> {
     pub fn new(conn: &Connection) -> ReadOnlyMapper {
         ReadOnlyMapper { conn }
@@ -62,17 +153,127 @@ impl<'a> ReadOnlyMapper<'a> {
     ) -> bool {
         self.get_translated(Some(sha), source).is_some()
     }
+
+    /// Every counterpart of the sha with the ref it was recorded for, newest first. A sha has more than
+    /// one counterpart when commits that don't touch the mapped directories collapse into their parent.
+    fn get_candidates(&self, sha: &Oid, source: super::Location) -> Vec<(Oid, Option<String>)> {
+        let mut stmt = self.conn.prepare(&format!(r#"
+            SELECT dest, ref_name
+            FROM {}
+            WHERE :source = source
+            ORDER BY timestamp DESC
+        "#, source.as_source_table())).unwrap();
+        let mut rows = stmt.query_named(&[
+            (":source", &format!("{}", sha)),
+        ]).expect("Could not read from sqlite connection");
+        let mut candidates = vec![];
+        while let Some(row) = rows.next() {
+            let row = row.expect("Could not read next row from sqlite connection");
+            let value: String = row.get(0);
+            let dest = Oid::from_bytes(&hex::decode(value.as_bytes()).unwrap())
+                .expect("The format should be correct for a stored sha");
+            candidates.push((dest, row.get(1)));
+        }
+        candidates
+    }
+
+    /// Every counterpart of the sha, newest first
+    pub fn get_all_translated(&self, sha: &Oid, source: super::Location) -> Vec<Oid> {
+        self.get_candidates(sha, source).into_iter().map(|(dest, _)| dest).collect()
+    }
+
+    /// Every mapped commit pair as (upstream sha, subgit sha), whichever side it was copied from
+    pub fn get_all_pairs(&self) -> Vec<(Oid, Oid)> {
+        let mut stmt = self.conn.prepare(&format!(r#"
+            SELECT source, dest FROM {}
+            UNION
+            SELECT dest, source FROM {}
+        "#, super::Location::UPSTREAM.as_source_table(), super::Location::SUBGIT.as_source_table())).unwrap();
+        let mut rows = stmt.query(&[] as &[&dyn rusqlite::types::ToSql]).expect("Could not read from sqlite connection");
+        let mut pairs = vec![];
+        while let Some(row) = rows.next() {
+            let row = row.expect("Could not read next row from sqlite connection");
+            let upstream: String = row.get(0);
+            let subgit: String = row.get(1);
+            pairs.push((
+                Oid::from_str(&upstream).expect("The format should be correct for a stored sha"),
+                Oid::from_str(&subgit).expect("The format should be correct for a stored sha"),
+            ));
+        }
+        pairs
+    }
+
+    /// Translates the sha for a ref being synced (named like in the source). Since the same commit can be
+    /// mapped from several branches, the newest counterpart in the history of the destination ref is
+    /// preferred, then the newest one recorded for the ref, and then simply the newest one. Pairs are
+    /// keyed on (source, dest), so a pair only carries the ref it was first recorded for - the ref is a
+    /// hint, and the history of the destination tip is what tells the branches apart.
+    pub fn get_translated_for_ref(
+        &self,
+        maybe_sha: Option<&Oid>,
+        source: super::Location,
+        ref_name: &str,
+        dest_repo: &Repository,
+        dest_tip: Option<Oid>,
+    ) -> Option<Oid> {
+        let candidates = match maybe_sha {
+            Some(sha) => self.get_candidates(sha, source),
+            None => return None,
+        };
+        let in_history = |candidate: &Oid| match dest_tip {
+            Some(tip) => tip == *candidate || dest_repo.graph_descendant_of(tip, *candidate).unwrap_or(false),
+            None => false,
+        };
+        candidates
+            .iter()
+            .find(|(dest, _)| in_history(dest))
+            .or_else(|| candidates.iter().find(|(_, name)| name.as_ref().map(|name| name == ref_name).unwrap_or(false)))
+            .or_else(|| candidates.first())
+            .map(|(dest, _)| *dest)
+    }
 }
 
 impl<'a> CommitMapper<'a> {
     pub fn new(conn: &mut Connection) -> CommitMapper {
         CommitMapper {
-            conn: conn.transaction().unwrap()
+            conn: conn.transaction().unwrap(),
+            notes: None,
+            pending_notes: RefCell::new(vec![]),
         }
     }
 
-    pub fn save_changes(self) {
+    pub fn with_notes(conn: &'a mut Connection, notes: MapNotes<'a>) -> CommitMapper<'a> {
+        CommitMapper {
+            conn: conn.transaction().unwrap(),
+            notes: Some(notes),
+            pending_notes: RefCell::new(vec![]),
+        }
+    }
+
+    /// Saves the mappings and writes their notes. Dropping the mapper instead rolls both back
+    fn commit(self) -> Option<MapNotes<'a>> {
         self.conn.commit().unwrap();
+        if let Some(ref notes) = self.notes {
+            for (sha, source, translated) in self.pending_notes.borrow().iter() {
+                notes.write(sha, *source, translated);
+            }
+        }
+        self.notes
+    }
+
+    pub fn save_changes(self) {
+        self.commit();
+    }
+
+    /// Saves the mappings and publishes their notes - once the refs they're about have been pushed
+    pub fn save_changes_and_push_notes<PL: PushListener>(
+        self,
+        git_push_opts: &Option<Vec<String>>,
+        push_listener: &Option<PL>,
+    ) {
+        if let Some(notes) = self.commit() {
+            notes.push(git_push_opts, push_listener);
+        }
     }
 
     pub fn get_translated(
@@ -83,6 +284,17 @@ impl<'a> CommitMapper<'a> {
         ReadOnlyMapper::new(&self.conn).get_translated(maybe_sha, source)
     }
 
+    pub fn get_translated_for_ref(
+        &self,
+        maybe_sha: Option<&Oid>,
+        source: super::Location,
+        ref_name: &str,
+        dest_repo: &Repository,
+        dest_tip: Option<Oid>,
+    ) -> Option<Oid> {
+        ReadOnlyMapper::new(&self.conn).get_translated_for_ref(maybe_sha, source, ref_name, dest_repo, dest_tip)
+    }
+
     pub fn has_sha(
         &self,
         sha: &Oid,
@@ -91,19 +303,41 @@ impl<'a> CommitMapper<'a> {
         ReadOnlyMapper::new(&self.conn).has_sha(sha, source)
     }
 
+    /// Records the counterpart of a sha - the ref is the one being synced (named like in the source), if any
     pub fn set_translated(
         &self,
         sha: &Oid,
         source: super::Location,
         translated: &Oid,
+        ref_name: Option<&str>,
+    ) {
+        self.set_translated_at(sha, source, translated, ref_name, Utc::now());
+    }
+
+    /// Like `set_translated`, but as of the given time, which orders the counterparts of a sha
+    pub fn set_translated_at(
+        &self,
+        sha: &Oid,
+        source: super::Location,
+        translated: &Oid,
+        ref_name: Option<&str>,
+        timestamp: DateTime<Utc>,
     ) {
         self.conn.execute_named(
             &format!(r#"
-                    INSERT INTO {} (source, dest, timestamp)
-                    VALUES (:source, :dest, :timestamp)
+                    INSERT INTO {} (source, dest, timestamp, ref_name)
+                    VALUES (:source, :dest, :timestamp, :ref_name)
                 "#, source.as_source_table()),
-            &[(":source", &format!("{}", sha)), (":dest", &format!("{}", translated)), (":timestamp", &Utc::now())],
+            &[
+                (":source", &format!("{}", sha)),
+                (":dest", &format!("{}", translated)),
+                (":timestamp", &timestamp),
+                (":ref_name", &ref_name),
+            ],
         ).unwrap();
+        if self.notes.is_some() {
+            self.pending_notes.borrow_mut().push((*sha, source, *translated));
+        }
     }
 }
 
@@ -111,7 +345,9 @@ impl<'a> CommitMapper<'a> {
 mod test {
     use crate::model::Location;
     use rusqlite::Connection;
-    use crate::model::map::CommitMapper;
+    use crate::model::map::{tables_without_ref_name, upgrade_schema, CommitMapper, ReadOnlyMapper};
+    use git2::{Oid, Repository, Signature};
+    use chrono::{TimeZone, Utc};
 
     #[test]
     fn test_empty_sqlite_transaction(){
@@ -125,4 +361,74 @@ mod test {
 
         cm.save_changes();
     }
+
+    #[test]
+    fn test_upgrade_schema() {
+        let map = Connection::open_in_memory().unwrap();
+        #[allow(non_snake_case)]
+        let EMPTY : Vec<String>= vec!();
+        for table in &["from_upstream", "from_local"] {
+            map.execute(&format!("CREATE TABLE {} (source TEXT NOT NULL, dest TEXT NOT NULL, timestamp DATETIME NOT NULL, PRIMARY KEY (source, dest))", table), &EMPTY).unwrap();
+        }
+
+        assert_eq!(vec!["from_upstream", "from_local"], tables_without_ref_name(&map).unwrap());
+        upgrade_schema(&map).unwrap();
+        upgrade_schema(&map).unwrap();
+        assert!(tables_without_ref_name(&map).unwrap().is_empty());
+        map.execute("INSERT INTO from_local (source, dest, timestamp, ref_name) VALUES ('a', 'b', 0, 'refs/heads/master')", &EMPTY).unwrap();
+    }
+
+    #[test]
+    fn test_translated_for_ref() {
+        let dir = std::env::temp_dir().join(format!("subgit-map-test-{}", std::process::id()));
+        let repo = Repository::init_bare(&dir).unwrap();
+        let sig = Signature::now("test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+        let commit = |message: &str, parents: &[Oid]| {
+            let parents: Vec<_> = parents.iter().map(|oid| repo.find_commit(*oid).unwrap()).collect();
+            let parents: Vec<_> = parents.iter().collect();
+            repo.commit(None, &sig, &sig, message, &tree, &parents).unwrap()
+        };
+        let base = commit("base", &[]);
+        let master = commit("master", &[base]);
+        let feature = commit("feature", &[base]);
+
+        let mut map = Connection::open_in_memory().unwrap();
+        #[allow(non_snake_case)]
+        let EMPTY : Vec<String>= vec!();
+        map.execute(&Location::SUBGIT.create_statement(), &EMPTY).unwrap();
+        let source = Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        let cm = CommitMapper::new(&mut map);
+        cm.set_translated(&source, Location::SUBGIT, &base, Some("refs/heads/master"));
+        cm.set_translated(&source, Location::SUBGIT, &feature, Some("refs/heads/feature"));
+
+        let for_ref = |ref_name, tip| cm.get_translated_for_ref(Some(&source), Location::SUBGIT, ref_name, &repo, tip);
+        assert_eq!(Some(base), for_ref("refs/heads/master", Some(master)));
+        assert_eq!(Some(feature), for_ref("refs/heads/master", Some(feature)));
+        assert_eq!(Some(base), for_ref("refs/heads/master", None));
+        assert_eq!(Some(feature), for_ref("refs/heads/other", None));
+        assert_eq!(Some(feature), cm.get_translated(Some(&source), Location::SUBGIT));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_translated_at() {
+        let mut map = Connection::open_in_memory().unwrap();
+        #[allow(non_snake_case)]
+        let EMPTY : Vec<String>= vec!();
+        map.execute(&Location::SUBGIT.create_statement(), &EMPTY).unwrap();
+        let source = Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        let first = Oid::from_str("2222222222222222222222222222222222222222").unwrap();
+        let second = Oid::from_str("3333333333333333333333333333333333333333").unwrap();
+        let synced = Oid::from_str("4444444444444444444444444444444444444444").unwrap();
+        let cm = CommitMapper::new(&mut map);
+        let all_translated = |cm: &CommitMapper| ReadOnlyMapper::new(&cm.conn).get_all_translated(&source, Location::SUBGIT);
+        cm.set_translated_at(&source, Location::SUBGIT, &second, None, Utc.timestamp(10, 0));
+        cm.set_translated_at(&source, Location::SUBGIT, &first, None, Utc.timestamp(9, 0));
+        assert_eq!(vec![second, first], all_translated(&cm));
+
+        cm.set_translated(&source, Location::SUBGIT, &synced, None);
+        assert_eq!(vec![synced, second, first], all_translated(&cm));
+    }
 }
\ No newline at end of file
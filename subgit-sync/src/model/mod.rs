@@ -6,11 +6,27 @@ use std::path::{Path, PathBuf};
 use crate::fs;
 use crate::git;
 
+pub mod backups;
 mod copier;
+pub mod excludes;
+mod gitmodules;
+pub mod mailmap;
 mod map;
+pub mod protection;
+mod read_only;
+pub mod rebuild;
+pub mod ref_filters;
+pub mod ref_names;
 pub mod settings;
+pub mod signing;
+pub mod status;
+mod trailers;
+pub mod uninstall;
+pub mod upgrade;
+pub mod verify;
 
 use crate::action::lock;
+use crate::action::PushListener;
 use crate::action::RecursionDetection;
 use crate::action::RecursionStatus;
 use crate::action::RefFilter;
@@ -25,13 +41,22 @@ use rusqlite::Connection;
 
 use failure::format_err;
 use crate::model::map::ReadOnlyMapper;
+use crate::model::excludes::Excludes;
+use crate::model::mailmap::Identities;
+use crate::model::protection::Protections;
+use crate::model::ref_filters::RefFilters;
+use crate::model::ref_names::RefNames;
+use crate::model::signing::SignedCommitPolicy;
+use crate::model::settings::PathMapping;
+use crate::model::settings::{HookPaths, SyncSettings};
 
 pub struct WrappedSubGit {
     pub location: PathBuf,
     pub map: Connection,
 
     pub recursion_detection: RecursionDetection,
-    pub filters: Vec<String>,
+    pub filters: RefFilters,
+    pub protections: Protections,
 
     pub lock: File,
 
@@ -41,47 +66,95 @@ pub struct WrappedSubGit {
 pub struct Workspace {
     pub upstream_working: Repository,
     pub upstream_bare: Repository,
-    pub upstream_path: String,
     pub local_working: Repository,
     pub local_bare: Repository,
-    pub local_path: String,
+    pub mappings: Vec<PathMapping>,
+    pub excludes: Excludes,
+    pub trailers: bool,
+    pub identities: Identities,
+    pub signed_commits: SignedCommitPolicy,
+    pub ref_names: RefNames,
+    /// The read-only review refs - their renames are part of ref_names as well
+    pub review_refs: RefNames,
 }
 
 impl Workspace {
+    /// Opens the working clones and the repositories in the data directory, to sync with the settings
+    fn open(data_path: &Path, settings: &SyncSettings) -> Result<Workspace, failure::Error> {
+        Ok(Workspace {
+            upstream_working: Repository::open(data_path.join("upstream"))?,
+            upstream_bare: Repository::open(data_path.join("upstream.git"))?,
+            local_working: Repository::open(data_path.join("local"))?,
+            local_bare: Repository::open(data_path.join("local.git"))?,
+            mappings: settings.mappings.clone(),
+            excludes: Excludes::new(&settings.excludes)?,
+            trailers: settings.trailers,
+            identities: Identities::new(&settings.identities)?,
+            signed_commits: settings.signed_commits.clone(),
+            ref_names: RefNames::new(&[settings.ref_names.clone(), settings.review_refs.clone()].concat())?,
+            review_refs: RefNames::new(&settings.review_refs)?,
+        })
+    }
+
+    fn upstream_paths(&self) -> Vec<&Path> {
+        self.mappings.iter().map(|mapping| mapping.upstream.as_ref()).collect()
+    }
+
+    fn local_paths(&self) -> Vec<&Path> {
+        self.mappings.iter().map(|mapping| mapping.subgit.as_ref()).collect()
+    }
+
+    fn map_notes(&self) -> map::MapNotes<'_> {
+        map::MapNotes {
+            upstream: &self.upstream_working,
+            subgit: &self.local_working,
+        }
+    }
+
     fn get_importer<'w>(&'w self, map: &'w mut Connection) -> copier::Copier<'w> {
-        let mapper = map::CommitMapper::new(map);
+        let mapper = map::CommitMapper::with_notes(map, self.map_notes());
         copier::Copier {
             source: copier::GitLocation {
                 name: Location::UPSTREAM,
                 bare: &self.upstream_bare,
                 working: &self.upstream_working,
-                location: &self.upstream_path.as_str().as_ref(),
+                locations: self.upstream_paths(),
             },
             dest: copier::GitLocation {
                 name: Location::SUBGIT,
                 bare: &self.local_bare,
                 working: &self.local_working,
-                location: &self.local_path.as_ref(),
+                locations: self.local_paths(),
             },
+            excludes: &self.excludes,
+            trailers: self.trailers,
+            identities: &self.identities,
+            signed_commits: &self.signed_commits,
+            ref_names: &self.ref_names,
             mapper,
         }
     }
 
     fn get_exporter<'w>(&'w self, map: &'w mut Connection) -> copier::Copier<'w> {
-        let mapper = map::CommitMapper::new(map);
+        let mapper = map::CommitMapper::with_notes(map, self.map_notes());
         copier::Copier {
             dest: copier::GitLocation {
                 name: Location::UPSTREAM,
                 bare: &self.upstream_bare,
                 working: &self.upstream_working,
-                location: &self.upstream_path.as_str().as_ref(),
+                locations: self.upstream_paths(),
             },
             source: copier::GitLocation {
                 name: Location::SUBGIT,
                 bare: &self.local_bare,
                 working: &self.local_working,
-                location: &self.local_path.as_ref(),
+                locations: self.local_paths(),
             },
+            excludes: &self.excludes,
+            trailers: self.trailers,
+            identities: &self.identities,
+            signed_commits: &self.signed_commits,
+            ref_names: &self.ref_names,
             mapper,
         }
     }
@@ -119,6 +192,7 @@ impl Location {
                 source TEXT NOT NULL,
                 dest TEXT NOT NULL,
                 timestamp DATETIME NOT NULL,
+                ref_name TEXT,
                 PRIMARY KEY (source, dest)
             );"#, self.as_source_table())
     }
@@ -131,7 +205,7 @@ impl Display for Location {
 }
 
 impl WrappedSubGit {
-    pub fn open<SP: AsRef<Path>, F: FnOnce(&Vec<String>)>(
+    pub fn open<SP: AsRef<Path>, F: FnOnce(&RefFilters)>(
         subgit_location: SP,
         before_load: Option<F>,
     ) -> Result<Option<WrappedSubGit>, failure::Error> {
@@ -144,8 +218,10 @@ impl WrappedSubGit {
         if git_settings.should_abort_hook() {
             Ok(None)
         } else {
+            let sync_settings = git_settings.sync_settings();
+            let filters = RefFilters::new(&sync_settings.filters)?;
             if let Some(before_load_callback) = before_load {
-                before_load_callback(&git_settings.filters());
+                before_load_callback(&filters);
             }
             let lock = lock(&subgit_top_path).unwrap();
             info!("Locked");
@@ -153,21 +229,18 @@ impl WrappedSubGit {
             info!("Setup logging");
 
 
+            let map = Connection::open(subgit_data_path.join("map.sqlite")).expect("Cannot find map file");
+            map::upgrade_schema(&map)?;
+
             info!("Opened Wrapped");
             Ok(Some(WrappedSubGit {
                 location: subgit_top_path.to_owned(),
-                map: Connection::open(subgit_data_path.join("map.sqlite")).expect("Cannot find map file"),
-                recursion_detection: git_settings.recursion_detection(),
-                filters: git_settings.filters(),
+                map,
+                filters,
+                protections: Protections::new(&sync_settings.protections)?,
                 lock,
-                workspace: Workspace {
-                    upstream_working: Repository::open(subgit_data_path.join("upstream"))?,
-                    upstream_bare: Repository::open(subgit_data_path.join("upstream.git"))?,
-                    upstream_path: git_settings.upstream_path(),
-                    local_working: Repository::open(subgit_data_path.join("local"))?,
-                    local_bare: Repository::open(subgit_data_path.join("local.git"))?,
-                    local_path: git_settings.local_path(),
-                }
+                workspace: Workspace::open(&subgit_data_path, &sync_settings)?,
+                recursion_detection: sync_settings.recursion_detection,
             }))
         }
     }
@@ -183,6 +256,32 @@ impl WrappedSubGit {
         status.is_recursing
     }
 
+    /// Makes the objects of a push that's still being received visible - git only moves them out of
+    /// the quarantine directory once the pre-receive hook accepts the push
+    pub fn use_quarantined_objects(&self) -> Result<(), failure::Error> {
+        if let Some(quarantine) = std::env::var_os("GIT_QUARANTINE_PATH") {
+            info!("Reading objects from the quarantine at {:?}", quarantine);
+            self.workspace
+                .local_bare
+                .odb()?
+                .add_disk_alternate(&quarantine.to_string_lossy())?;
+        }
+        Ok(())
+    }
+
+    /// Rejects the subgit ref updates that the protection rules forbid, and any update to the review refs
+    pub fn check_protections(&self, ref_name: &str, old_sha: Oid, new_sha: Oid) -> Result<(), failure::Error> {
+        if self.workspace.review_refs.is_renamed(ref_name, Location::SUBGIT) {
+            return Err(format_err!("{} is a read-only mirror of an upstream review ref", ref_name));
+        }
+        self.protections.check(
+            &self.workspace.local_bare,
+            ref_name,
+            git::optionify_sha(old_sha),
+            git::optionify_sha(new_sha),
+        )
+    }
+
     pub fn update_self(&self) {
         git::fetch_all_ext(&self.workspace.local_working).unwrap();
         git::fetch_all_ext(&self.workspace.upstream_working).unwrap();
@@ -195,10 +294,66 @@ impl WrappedSubGit {
         new_sha: Oid,
     ) -> Result<(), failure::Error> {
         info!("Starting on hook!");
-        if !self.filters.matches(&ref_name) {
-            info!("Skipping non-applicable ref: {}", ref_name.as_ref());
+        if let Some((ref_name, old, new)) = self.prepare_export(ref_name.as_ref(), old_sha, new_sha)? {
+            self.export_local_commits(&ref_name, old, new)?;
+            if new.is_some() {
+                println!("Exported commits from {} upstream", ref_name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Exports the updates of a whole push at once - the upstream gets all of them or none
+    pub fn push_ref_changes_upstream_atomically(
+        &mut self,
+        updates: Vec<(String, Oid, Oid)>,
+    ) -> Result<(), failure::Error> {
+        info!("Starting on atomic hook!");
+        let mut exports = vec![];
+        for (ref_name, old_sha, new_sha) in updates {
+            if let Some(export) = self.prepare_export(&ref_name, old_sha, new_sha)? {
+                exports.push(export);
+            }
+        }
+        if exports.is_empty() {
             return Ok(());
         }
+
+        info!("About to export {} refs atomically", exports.len());
+        let push_opts = self.recursion_detection.get_push_opts();
+        self.workspace
+            .get_exporter(&mut self.map)
+            .copy_refs_atomically(&exports, false, push_opts)?;
+
+        let ref_names: Vec<&str> = exports.iter().map(|(ref_name, _, _)| ref_name.as_str()).collect();
+        println!("Exported commits from {} upstream", ref_names.join(", "));
+
+        Ok(())
+    }
+
+    /// Checks a subgit ref update and brings the subgit up to date with the upstream first if
+    /// needed. Returns the ref change to export, or nothing if the ref isn't synchronized
+    fn prepare_export(
+        &mut self,
+        ref_name: &str,
+        old_sha: Oid,
+        new_sha: Oid,
+    ) -> Result<Option<copier::RefChange>, failure::Error> {
+        if backups::is_backup_ref(ref_name) {
+            info!("Skipping backup ref: {}", ref_name);
+            return Ok(None);
+        }
+        let upstream_ref_name = match self.workspace.ref_names.translate(ref_name, Location::UPSTREAM) {
+            Some(name) => name,
+            None => {
+                info!("Skipping ref without an upstream name: {}", ref_name);
+                return Ok(None);
+            }
+        };
+        if !self.filters.matches(&upstream_ref_name) {
+            info!("Skipping non-applicable ref: {}", ref_name);
+            return Ok(None);
+        }
         let old = if old_sha == git::no_sha() {
             None
         } else {
@@ -215,58 +370,110 @@ impl WrappedSubGit {
         if new == None {
             //git::delete_remote_branch(&self.local_working, &ref_name, None)?;
             info!("Deleting remote branch");
-            self.export_local_commits(ref_name.as_ref(), old, None);
-            return Ok(());
+            return Ok(Some((ref_name.to_owned(), old, None)));
         }
 
         info!(
             "Updating ref: {} from {:?} -> {:?}",
-            ref_name.as_ref(),
+            ref_name,
             old,
             new
         );
-        let mapper = map::ReadOnlyMapper::new(&mut self.map);
-
-        let old_upstream = mapper.get_translated(old.as_ref(), Location::SUBGIT);
         let real_upstream = self
             .workspace
             .upstream_bare
-            .find_reference(ref_name.as_ref())
-            .map(|reference| {
-                Some(
-                    reference
-                        .target()
-                        .expect("Reference is not direct - need to check for that"),
-                )
-            })
-            .unwrap_or(None);
+            .find_reference(&upstream_ref_name)
+            .and_then(|reference| reference.resolve())
+            .ok()
+            .and_then(|reference| reference.target());
+        let old_upstream = map::ReadOnlyMapper::new(&self.map).get_translated_for_ref(
+            old.as_ref(),
+            Location::SUBGIT,
+            ref_name,
+            &self.workspace.upstream_bare,
+            real_upstream,
+        );
 
         info!("Found upstream commits");
 
         if old_upstream != real_upstream && real_upstream != None {
             info!("Importing new upstream commits first. Expected old upstream was {:?}, but real one is {:?}", old_upstream, real_upstream);
             let new_old_local_sha =
-                self.import_upstream_commits(ref_name.as_ref(), old_upstream, real_upstream);
+                self.import_upstream_commits(&upstream_ref_name, old_upstream, real_upstream)?;
             if old != new_old_local_sha {
                 return Err(format_err!("Out of sync with the upstream repo!"));
             }
         }
 
-        info!("About to export commits");
+        let new_commits = self.new_local_commits(new_sha)?;
+        self.check_excludes(&new_commits)?;
+        self.check_signed_commits(&new_commits)?;
 
-        self.export_local_commits(&ref_name.as_ref(), old, Some(new_sha));
+        Ok(Some((ref_name.to_owned(), old, Some(new_sha))))
+    }
 
-        println!("Exported commits from {} upstream", ref_name.as_ref());
+    /// The commits being pushed to the subgit that it didn't have yet
+    fn new_local_commits(&self, new_sha: Oid) -> Result<Vec<Oid>, failure::Error> {
+        let repo = &self.workspace.local_bare;
+        let new_sha = match git::peel_to_commit(repo, new_sha) {
+            Some(sha) => sha,
+            None => return Ok(vec![]),
+        };
+        let mut walk = repo.revwalk()?;
+        walk.push(new_sha)?;
+        for (_, target) in git::get_refs(repo, "**")? {
+            // Anything already in the subgit has been through here (or was imported) already
+            walk.hide(target).ok();
+        }
+        Ok(walk.collect::<Result<Vec<Oid>, _>>()?)
+    }
 
+    /// Rejects new subgit commits that add files the upstream keeps to itself
+    fn check_excludes(&self, new_commits: &[Oid]) -> Result<(), failure::Error> {
+        let excludes = &self.workspace.excludes;
+        if excludes.is_empty() {
+            return Ok(());
+        }
+        let repo = &self.workspace.local_bare;
+        for oid in new_commits {
+            let commit = repo.find_commit(*oid)?;
+            let tree = commit.tree()?;
+            for location in self.workspace.local_paths() {
+                let subtree = match git::find_subtree(&tree, location) {
+                    Some(subtree) => repo.find_tree(subtree)?,
+                    None => continue,
+                };
+                let found = git::find_matching_entries(repo, &subtree, &|path, is_dir| {
+                    excludes.is_excluded(path, is_dir)
+                })?;
+                if let Some((path, _, _)) = found.first() {
+                    return Err(format_err!(
+                        "Commit {} adds {}, which is excluded from the subgit",
+                        commit.id(),
+                        location.join(path).to_string_lossy()
+                    ));
+                }
+            }
+        }
         Ok(())
     }
 
+    fn check_signed_commits(&self, new_commits: &[Oid]) -> Result<(), failure::Error> {
+        if self.workspace.signed_commits != SignedCommitPolicy::Refuse {
+            return Ok(());
+        }
+        match new_commits.iter().find(|oid| signing::is_signed(&self.workspace.local_bare, oid)) {
+            Some(oid) => Err(format_err!("Commit {} is signed, and signed commits are not synchronized", oid)),
+            None => Ok(()),
+        }
+    }
+
     fn export_local_commits(
         &mut self,
         ref_name: &str,
         old_local_sha: Option<Oid>,
         new_local_sha: Option<Oid>,
-    ) -> Option<Oid> {
+    ) -> Result<Option<Oid>, failure::Error> {
         let sha_copier = self.workspace.get_exporter(&mut self.map);
 
         sha_copier.copy_ref_unchecked(
@@ -284,7 +491,7 @@ impl WrappedSubGit {
         ref_name: &str,
         old_upstream_sha: Option<Oid>,
         new_upstream_sha: Option<Oid>,
-    ) -> Option<Oid> {
+    ) -> Result<Option<Oid>, failure::Error> {
         let sha_copier = self.workspace.get_importer(&mut self.map);
 
         sha_copier.copy_ref_unchecked(
@@ -297,6 +504,84 @@ impl WrappedSubGit {
         )
     }
 
+    /// Whether the upstream ref is imported into the subgit - either the filters match it, or it's a review ref
+    pub fn syncs_upstream_ref<S: AsRef<str>>(&self, upstream_name: S) -> bool {
+        self.filters.matches(&upstream_name)
+            || self.workspace.review_refs.is_renamed(&upstream_name, Location::UPSTREAM)
+    }
+
+    /// Imports an upstream ref update. Review refs are only mirrored while they change the mapped
+    /// directories, and are removed from the subgit once they don't anymore
+    pub fn import_upstream_ref(
+        &mut self,
+        ref_name: &str,
+        old_upstream_sha: Option<Oid>,
+        new_upstream_sha: Option<Oid>,
+    ) -> Option<Oid> {
+        let new_upstream_sha = match new_upstream_sha {
+            Some(sha) if self.workspace.review_refs.is_renamed(ref_name, Location::UPSTREAM) => {
+                match self.review_touches_mapped_paths(sha) {
+                    Ok(true) => Some(sha),
+                    Ok(false) => {
+                        info!("Not mirroring {}, since it doesn't change the mapped paths", ref_name);
+                        None
+                    }
+                    Err(err) => {
+                        error!("Skipping {}, since it could not be inspected: {}", ref_name, err);
+                        return None;
+                    }
+                }
+            }
+            new_upstream_sha => new_upstream_sha,
+        };
+        let in_subgit = self
+            .workspace
+            .ref_names
+            .translate(ref_name, Location::SUBGIT)
+            .map(|local_name| self.workspace.local_bare.find_reference(&local_name).is_ok())
+            .unwrap_or(false);
+        if new_upstream_sha.is_none() && !in_subgit {
+            info!("Nothing to remove for {}", ref_name);
+            return None;
+        }
+        match self.import_upstream_commits(ref_name, old_upstream_sha, new_upstream_sha) {
+            Ok(new_sha) => new_sha,
+            Err(err) => {
+                error!("Could not import {}: {}", ref_name, err);
+                None
+            }
+        }
+    }
+
+    /// Whether the commits of a review ref that no upstream branch has yet change the mapped directories
+    fn review_touches_mapped_paths(&self, upstream_sha: Oid) -> Result<bool, failure::Error> {
+        let repo = &self.workspace.upstream_bare;
+        let head = match git::peel_to_commit(repo, upstream_sha) {
+            Some(sha) => sha,
+            None => return Ok(false),
+        };
+        let mut walk = repo.revwalk()?;
+        walk.push(head)?;
+        for (_, target) in git::get_refs(repo, "refs/heads/*")? {
+            walk.hide(target).ok();
+        }
+        for oid in walk {
+            let commit = repo.find_commit(oid?)?;
+            let tree = commit.tree()?;
+            let parent_trees = commit.parents().map(|parent| parent.tree()).collect::<Result<Vec<_>, _>>()?;
+            for location in self.workspace.upstream_paths() {
+                let subtree = git::find_subtree(&tree, location);
+                // A merge only counts when the mapped directory differs from all of its parents
+                if parent_trees.iter().all(|parent| git::find_subtree(parent, location) != subtree)
+                    && (subtree.is_some() || !parent_trees.is_empty())
+                {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
     pub fn import_initial_empty_commits(&mut self) {
         let sha_copier = self.workspace.get_importer(&mut self.map);
 
@@ -307,44 +592,148 @@ impl WrappedSubGit {
         let mut local_refs: std::collections::HashMap<String, git2::Oid> =
             git::get_refs(&self.workspace.local_bare, "**")?
                 .into_iter()
-                .filter(|&(ref name, ref _target)| self.filters.matches(&name))
+                .filter(|&(ref name, ref _target)| {
+                    self.workspace
+                        .ref_names
+                        .translate(name, Location::UPSTREAM)
+                        .map(|upstream_name| self.syncs_upstream_ref(upstream_name))
+                        .unwrap_or(false)
+                })
                 .collect();
 
         git::get_refs(&self.workspace.upstream_bare, "**")?
             .into_iter()
             .for_each(|(ref_name, upstream_sha)| {
-                if self.filters.matches(&ref_name) {
+                if self.syncs_upstream_ref(&ref_name) {
                     info!("Importing {}", ref_name);
-                    let local_sha = local_refs.remove(&ref_name);
+                    let local_name = self.workspace.ref_names.translate(&ref_name, Location::SUBGIT);
+                    let local_sha = local_name.as_ref().and_then(|local_name| local_refs.remove(local_name));
                     info!(
                         "Importing {} to point to {} (Was {:?} in the local)",
                         ref_name, upstream_sha, local_sha
                     );
-                    let old_upstream_sha = ReadOnlyMapper::new(&self.map).get_translated(local_sha.as_ref(), Location::UPSTREAM);
-
-                    self.import_upstream_commits(&ref_name, old_upstream_sha, Some(upstream_sha));
+                    let old_upstream_sha = local_name.and_then(|local_name| {
+                        ReadOnlyMapper::new(&self.map).get_translated_for_ref(
+                            local_sha.as_ref(),
+                            Location::SUBGIT,
+                            &local_name,
+                            &self.workspace.upstream_bare,
+                            Some(upstream_sha),
+                        )
+                    });
+
+                    self.import_upstream_ref(&ref_name, old_upstream_sha, Some(upstream_sha));
                 }
             });
 
-        // TODO: iterate over the leftover keys
+        // The subgit refs left over in local_refs are removed by sync-all, see stale_refs
 
         Ok(())
     }
 
+    /// The matching subgit refs that no upstream ref corresponds to anymore, except for the ones to keep
+    pub fn stale_refs(&self, keep: &RefFilters) -> Result<Vec<String>, failure::Error> {
+        let upstream_refs: std::collections::HashSet<String> = git::get_refs(&self.workspace.upstream_bare, "**")?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        Ok(git::get_refs(&self.workspace.local_bare, "**")?
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name != map::NOTES_REF && !backups::is_backup_ref(name) && !keep.matches(name))
+            .filter(|name| match self.workspace.ref_names.translate(name, Location::UPSTREAM) {
+                Some(upstream_name) => self.syncs_upstream_ref(&upstream_name) && !upstream_refs.contains(&upstream_name),
+                None => false,
+            })
+            .collect())
+    }
+
+    pub fn prune_refs(&mut self, ref_names: &[String]) -> Result<(), failure::Error> {
+        for ref_name in ref_names {
+            println!("Removing {}", ref_name);
+            let upstream_name = self
+                .workspace
+                .ref_names
+                .translate(ref_name, Location::UPSTREAM)
+                .expect("Stale refs have an upstream name");
+            self.import_upstream_commits(&upstream_name, None, None)?;
+        }
+        Ok(())
+    }
+
+    /// Points the subgit HEAD (and the copy of it in local.git) at the branch the upstream HEAD
+    /// points to, translated to its subgit name. Does nothing if the upstream HEAD is detached or
+    /// its branch isn't synced.
+    pub fn sync_head(&self) -> Result<(), failure::Error> {
+        let upstream_head = self.workspace.upstream_bare.find_reference("HEAD")?;
+        let upstream_target = match upstream_head.symbolic_target() {
+            Some(target) => target.to_owned(),
+            None => return Ok(()),
+        };
+        if !self.filters.matches(&upstream_target) {
+            info!("Not syncing HEAD - {} isn't synced", upstream_target);
+            return Ok(());
+        }
+        let subgit_target = match self.workspace.ref_names.translate(&upstream_target, Location::SUBGIT) {
+            Some(target) => target,
+            None => {
+                info!("Not syncing HEAD - {} has no subgit name", upstream_target);
+                return Ok(());
+            }
+        };
+
+        let subgit = Repository::open_bare(&self.location)?;
+        for repo in [&subgit, &self.workspace.local_bare].iter() {
+            let current = repo.find_reference("HEAD")?.symbolic_target().map(|target| target.to_owned());
+            if current.as_ref() != Some(&subgit_target) {
+                info!("Pointing HEAD of {:?} at {} (was {:?})", repo.path(), subgit_target, current);
+                repo.reference_symbolic("HEAD", &subgit_target, true, "subgit: sync HEAD with the upstream")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn list_backups(&self) -> Result<Vec<backups::Backup>, failure::Error> {
+        backups::list(&self.workspace.local_bare)
+    }
+
+    /// Publishes a backed up subgit tip again as a new ref (the ref it was backed up from by default),
+    /// exporting it to the upstream like a push would. Returns the restored ref
+    pub fn restore_backup(&mut self, name: &str, ref_name: Option<String>) -> Result<String, failure::Error> {
+        let backup = backups::find(&self.workspace.local_bare, name)?;
+        let ref_name = ref_name.unwrap_or_else(|| backup.ref_name.clone());
+        if self.workspace.local_bare.find_reference(&ref_name).is_ok() {
+            return Err(format_err!(
+                "{} already exists - pass another ref to restore {} to",
+                ref_name,
+                backup.name
+            ));
+        }
+
+        info!("Restoring {} ({}) as {}", backup.name, backup.sha, ref_name);
+        self.push_ref_change_upstream(&ref_name, git::no_sha(), backup.sha)?;
+        let detection = &self.recursion_detection;
+        detection.pre_push(&ref_name, backup.sha);
+        git::push_sha_ext(
+            &self.workspace.local_working,
+            backup.sha,
+            &ref_name,
+            false,
+            detection.get_push_opts(),
+        )?;
+        detection.post_push(&ref_name, backup.sha);
+        Ok(ref_name)
+    }
+
     pub fn run_creation<SP: AsRef<Path>, UP: AsRef<Path>>(
         subgit_location: SP,
         upstream_location: UP,
-        upstream_map_path: &str,
-        subgit_map_path: Option<&str>,
-        log_level: LevelFilter,
+        settings: SyncSettings,
         log_file: PathBuf,
         bin_loc: BinSource,
-        subgit_hook_path: PathBuf,
+        hooks: HookPaths,
         subgit_working_clone_url: Option<String>,
-        upstream_hook_path: PathBuf,
         upstream_working_clone_url: Option<String>,
-        recursion_detection: RecursionDetection,
-        filters: Vec<String>,
     ) -> Result<WrappedSubGit, failure::Error> {
         WriteLogger::init(
             LevelFilter::Debug,
@@ -431,7 +820,7 @@ impl WrappedSubGit {
                 &vec![],
             )?;
             mirror_working.set_head("refs/sync/empty")?;
-            git::push_sha_ext(&mirror_working, "refs/sync/empty", false, None)?;
+            git::push_sha_ext(&mirror_working, subgit_empty_sha, "refs/sync/empty", false, None)?;
             //
             //            if let Ok(reference) = upstream_bare.find_reference("refs/sync/empty") {
             //                git::delete_remote_branch(upstream_working, "refs/sync/empty", None)?;
@@ -446,26 +835,19 @@ impl WrappedSubGit {
                 &vec![],
             )?;
             upstream_working.set_head("refs/sync/empty")?;
-            git::push_sha_ext(&upstream_working, "refs/sync/empty", false, None)?;
+            git::push_sha_ext(&upstream_working, upstream_empty_sha, "refs/sync/empty", false, None)?;
             info!("Created {} as the empty upstream ref", &upstream_empty_sha);
 
             let mapper = CommitMapper::new(&mut map);
 
-            mapper.set_translated(&upstream_empty_sha, Location::UPSTREAM, &subgit_empty_sha);
-            mapper.set_translated(&subgit_empty_sha, Location::SUBGIT, &upstream_empty_sha);
+            mapper.set_translated(&upstream_empty_sha, Location::UPSTREAM, &subgit_empty_sha, None);
+            mapper.set_translated(&subgit_empty_sha, Location::SUBGIT, &upstream_empty_sha, None);
 
             mapper.save_changes();
         }
 
         info!("Generating settings file");
-        settings::Settings::generate(
-            &subgit_data_path,
-            upstream_map_path.to_string(),
-            subgit_map_path.unwrap_or("").to_owned(),
-            log_level,
-            recursion_detection.clone(),
-            filters.clone(),
-        );
+        settings::Settings::generate(&subgit_data_path, settings.clone(), hooks.clone());
 
         info!("Generating whitelist directory");
         fs::create_dir_all(&subgit_data_path.join("whitelist"))
@@ -496,29 +878,23 @@ impl WrappedSubGit {
         info!("Adding subgit hook");
         std::os::unix::fs::symlink(
             fs::make_absolute(&hook_path)?,
-            subgit_location.as_ref().join(subgit_hook_path),
+            subgit_location.as_ref().join(hooks.subgit),
         )?;
 
         info!("Adding upstream hook");
         std::os::unix::fs::symlink(
             fs::make_absolute(&hook_path)?,
-            upstream_location.as_ref().join(upstream_hook_path),
+            upstream_location.as_ref().join(hooks.upstream),
         )?;
 
         Ok(WrappedSubGit {
             location: subgit_location.as_ref().to_owned(),
             map,
-            recursion_detection,
-            filters,
+            filters: RefFilters::new(&settings.filters)?,
+            protections: Protections::new(&settings.protections)?,
             lock,
-            workspace: Workspace {
-                upstream_working,
-                upstream_bare,
-                upstream_path: upstream_map_path.to_owned(),
-                local_working: mirror_working,
-                local_bare: Repository::open_bare(subgit_data_path.join("local.git"))?,
-                local_path: subgit_map_path.unwrap_or("").to_owned(),
-            }
+            workspace: Workspace::open(&subgit_data_path, &settings)?,
+            recursion_detection: settings.recursion_detection,
         })
     }
 }
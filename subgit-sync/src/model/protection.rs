@@ -0,0 +1,157 @@
+use super::ref_filters::RefFilters;
+use crate::action::RefFilter;
+use crate::git;
+use failure::format_err;
+use git2::{Oid, Repository};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// A kind of subgit ref update that a protection rule can forbid
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefUpdate {
+    Create,
+    Delete,
+    NonFastForward,
+}
+
+/// Forbids some kinds of updates to the subgit refs matching the pattern (a ref prefix, glob or
+/// anchored regex, like the ref filters)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RefProtection {
+    pub pattern: String,
+    pub deny: Vec<RefUpdate>,
+}
+
+impl FromStr for RefUpdate {
+    type Err = failure::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "deny-create" => Ok(RefUpdate::Create),
+            "deny-delete" => Ok(RefUpdate::Delete),
+            "deny-non-ff" => Ok(RefUpdate::NonFastForward),
+            _ => Err(format_err!(
+                "Expected one of deny-create, deny-delete or deny-non-ff, got '{}'",
+                input
+            )),
+        }
+    }
+}
+
+impl Display for RefUpdate {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            RefUpdate::Create => write!(f, "deny-create"),
+            RefUpdate::Delete => write!(f, "deny-delete"),
+            RefUpdate::NonFastForward => write!(f, "deny-non-ff"),
+        }
+    }
+}
+
+/// Parses rules in the form of RESTRICTION[,RESTRICTION]:PATTERN, e.g. 'deny-delete,deny-non-ff:refs/heads/master'
+impl FromStr for RefProtection {
+    type Err = failure::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut iter = input.splitn(2, ':');
+        match (iter.next(), iter.next()) {
+            (Some(restrictions), Some(pattern)) if !pattern.is_empty() => Ok(RefProtection {
+                pattern: pattern.to_owned(),
+                deny: restrictions
+                    .split(',')
+                    .map(|restriction| restriction.parse())
+                    .collect::<Result<Vec<RefUpdate>, failure::Error>>()?,
+            }),
+            _ => Err(format_err!(
+                "Bad protection rule '{}' - expected RESTRICTION[,RESTRICTION]:REF_PATTERN",
+                input
+            )),
+        }
+    }
+}
+
+impl Display for RefProtection {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        let deny: Vec<String> = self.deny.iter().map(|restriction| restriction.to_string()).collect();
+        write!(f, "{}:{}", deny.join(","), self.pattern)
+    }
+}
+
+/// The protection rules, checked in the subgit's update hook before anything is exported.
+/// Every rule matching a ref applies.
+pub struct Protections {
+    rules: Vec<(RefFilters, RefProtection)>,
+}
+
+impl Protections {
+    pub fn new(protections: &[RefProtection]) -> Result<Protections, failure::Error> {
+        let rules = protections
+            .iter()
+            .map(|protection| Ok((RefFilters::new(std::slice::from_ref(&protection.pattern))?, protection.clone())))
+            .collect::<Result<Vec<_>, failure::Error>>()?;
+        Ok(Protections { rules })
+    }
+
+    pub fn check(
+        &self,
+        repo: &Repository,
+        ref_name: &str,
+        old_sha: Option<Oid>,
+        new_sha: Option<Oid>,
+    ) -> Result<(), failure::Error> {
+        let update = match (old_sha, new_sha) {
+            (None, _) => RefUpdate::Create,
+            (_, None) => RefUpdate::Delete,
+            (Some(old), Some(new)) => {
+                let fast_forward = match (git::peel_to_commit(repo, old), git::peel_to_commit(repo, new)) {
+                    (Some(old), Some(new)) => old == new || repo.graph_descendant_of(new, old).unwrap_or(false),
+                    _ => false,
+                };
+                if fast_forward {
+                    return Ok(());
+                }
+                RefUpdate::NonFastForward
+            }
+        };
+        match self
+            .rules
+            .iter()
+            .find(|(filter, protection)| protection.deny.contains(&update) && filter.matches(ref_name))
+        {
+            Some((_, protection)) => {
+                let action = match update {
+                    RefUpdate::Create => "Creating",
+                    RefUpdate::Delete => "Deleting",
+                    RefUpdate::NonFastForward => "Force pushing",
+                };
+                Err(format_err!(
+                    "{} {} is not allowed by the protection rule '{}'",
+                    action,
+                    ref_name,
+                    protection
+                ))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RefProtection, RefUpdate};
+
+    #[test]
+    fn test_parse_protection() {
+        let protection: RefProtection = "deny-delete,deny-non-ff:refs/heads/release/*".parse().unwrap();
+        assert_eq!(
+            RefProtection {
+                pattern: "refs/heads/release/*".to_owned(),
+                deny: vec![RefUpdate::Delete, RefUpdate::NonFastForward],
+            },
+            protection
+        );
+        assert_eq!("deny-delete,deny-non-ff:refs/heads/release/*", protection.to_string());
+        assert!("deny-delete".parse::<RefProtection>().is_err());
+        assert!("deny-rename:refs/heads/master".parse::<RefProtection>().is_err());
+    }
+}
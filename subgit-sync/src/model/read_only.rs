@@ -0,0 +1,106 @@
+use super::map;
+use super::ref_filters::RefFilters;
+use super::ref_names::RefNames;
+use super::settings::Settings;
+use super::Location;
+use crate::action::RefFilter;
+use crate::git;
+use failure::format_err;
+use git2::{Oid, Repository};
+use rusqlite::{Connection, OpenFlags};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The parts of a subgit that the commands which only inspect it need, opened without taking the lock -
+/// whatever is being synchronized at the same time can show up half done
+pub struct ReadOnlySubGit {
+    pub settings: Settings,
+    pub filters: RefFilters,
+    /// The regular renames followed by the review ref renames
+    pub ref_names: RefNames,
+    pub upstream: Repository,
+    pub subgit: Repository,
+    data_path: PathBuf,
+}
+
+/// A synced ref, with the commits it points to on either side
+pub struct RefPair {
+    pub ref_name: String,
+    pub subgit_ref_name: String,
+    pub upstream_sha: Option<Oid>,
+    pub subgit_sha: Option<Oid>,
+}
+
+fn commit_refs(repo: &Repository) -> Result<BTreeMap<String, Oid>, failure::Error> {
+    Ok(git::get_refs(repo, "**")?
+        .into_iter()
+        .filter_map(|(name, sha)| git::peel_to_commit(repo, sha).map(|commit| (name, commit)))
+        .collect())
+}
+
+impl ReadOnlySubGit {
+    pub fn open(subgit_location: &Path) -> Result<ReadOnlySubGit, failure::Error> {
+        let data_path = subgit_location.join("data");
+        let settings = Settings::try_load(&data_path)?;
+        let filters = RefFilters::new(&settings.filters())?;
+        let ref_names = RefNames::new(&[settings.ref_names(), settings.review_refs()].concat())?;
+        Ok(ReadOnlySubGit {
+            settings,
+            filters,
+            ref_names,
+            upstream: Repository::open_bare(data_path.join("upstream.git"))?,
+            subgit: Repository::open_bare(data_path.join("local.git"))?,
+            data_path,
+        })
+    }
+
+    /// Opens the map separately, since rebuilding it mustn't depend on it. It's opened read only, so a
+    /// map in an older format is refused rather than migrated - that's up to the upgrade command.
+    pub fn open_map(&self) -> Result<Connection, failure::Error> {
+        let conn = Connection::open_with_flags(self.data_path.join("map.sqlite"), OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        if !map::tables_without_ref_name(&conn)?.is_empty() {
+            return Err(format_err!("The map is in an older format - run 'subgit-sync upgrade' first"));
+        }
+        Ok(conn)
+    }
+
+    /// The refs matching the filters, the upstream ones first (sorted by name) and then the ones only
+    /// the subgit has
+    pub fn ref_pairs(&self) -> Result<Vec<RefPair>, failure::Error> {
+        let upstream_refs = commit_refs(&self.upstream)?;
+        let mut subgit_refs = commit_refs(&self.subgit)?;
+
+        let mut pairs = vec![];
+        for (ref_name, upstream_sha) in upstream_refs {
+            if !self.filters.matches(&ref_name) {
+                continue;
+            }
+            let subgit_ref_name = match self.ref_names.translate(&ref_name, Location::SUBGIT) {
+                Some(name) => name,
+                None => continue,
+            };
+            pairs.push(RefPair {
+                ref_name,
+                subgit_sha: subgit_refs.remove(&subgit_ref_name),
+                subgit_ref_name,
+                upstream_sha: Some(upstream_sha),
+            });
+        }
+        for (subgit_ref_name, subgit_sha) in subgit_refs {
+            let ref_name = match self.ref_names.translate(&subgit_ref_name, Location::UPSTREAM) {
+                Some(name) => name,
+                None => continue,
+            };
+            if subgit_ref_name == map::NOTES_REF || !self.filters.matches(&ref_name) {
+                continue;
+            }
+            pairs.push(RefPair {
+                ref_name,
+                subgit_ref_name,
+                upstream_sha: None,
+                subgit_sha: Some(subgit_sha),
+            });
+        }
+        Ok(pairs)
+    }
+}
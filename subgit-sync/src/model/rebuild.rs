@@ -0,0 +1,300 @@
+use super::excludes::Excludes;
+use super::mailmap::Identities;
+use super::map::{CommitMapper, NOTES_REF};
+use super::read_only::ReadOnlySubGit;
+use super::trailers;
+use super::verify::mismatched_mappings;
+use super::Location;
+use crate::git;
+use chrono::{TimeZone, Utc};
+use failure::format_err;
+use git2::{Commit, Oid, Repository};
+use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+/// A commit that couldn't be paired up, and has to be resolved by hand
+pub enum Unresolved {
+    /// Several subgit commits fit the upstream commit equally well
+    Ambiguous { upstream: Oid, candidates: Vec<Oid> },
+    /// No commit on the other side fits the commit
+    Unmatched { location: Location, sha: Oid },
+}
+
+impl Display for Unresolved {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Unresolved::Ambiguous { upstream, candidates } => {
+                let candidates: Vec<String> = candidates.iter().map(|sha| sha.to_string()).collect();
+                write!(f, "ambiguous: upstream {} could be subgit {}", upstream, candidates.join(", "))
+            }
+            Unresolved::Unmatched { location, sha } => write!(f, "unmatched: {} {}", location, sha),
+        }
+    }
+}
+
+/// What was written to the new map, and what's missing from it
+pub struct Rebuilt {
+    /// The (upstream, subgit) commit pairs, parents first
+    pub pairs: Vec<(Oid, Oid)>,
+    pub unresolved: Vec<Unresolved>,
+}
+
+/// What the copies of a commit have in common: the author and commit times, and the message
+type CommitKey = (i64, i64, String);
+
+fn commit_key(commit: &Commit) -> CommitKey {
+    (
+        commit.author().when().seconds(),
+        commit.committer().when().seconds(),
+        trailers::without_sync_trailers(&String::from_utf8_lossy(commit.message_bytes())),
+    )
+}
+
+/// The commits reachable from the tips, parents first
+fn walk(repo: &Repository, tips: &[Oid]) -> Result<Vec<Oid>, failure::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git::reverse_topological());
+    for tip in tips {
+        revwalk.push(*tip)?;
+    }
+    Ok(revwalk.collect::<Result<Vec<Oid>, _>>()?)
+}
+
+/// The counterpart a commit names in its mapping note, or in the trailer its message got when it was copied
+fn named_counterpart(repo: &Repository, commit: &Commit, counterpart: Location) -> Option<Oid> {
+    let key = trailers::trailer_key(counterpart);
+    let from_note = repo
+        .find_note(Some(NOTES_REF), commit.id())
+        .ok()
+        .and_then(|note| {
+            note.message()?
+                .lines()
+                .filter_map(|line| line.strip_prefix(key)?.strip_prefix(": "))
+                .next()
+                .and_then(|value| Oid::from_str(value.trim()).ok())
+        });
+    from_note.or_else(|| {
+        trailers::value(&String::from_utf8_lossy(commit.message_bytes()), key).and_then(|value| Oid::from_str(value).ok())
+    })
+}
+
+fn same_person(a: &git2::Signature, b: &git2::Signature) -> bool {
+    a.name_bytes() == b.name_bytes() && a.email_bytes() == b.email_bytes()
+}
+
+struct Pairing<'r> {
+    read_only: &'r ReadOnlySubGit,
+    excludes: Excludes,
+    identities: Identities,
+    upstream_empty: Option<Oid>,
+    subgit_empty: Option<Oid>,
+    /// In the order they were found, which becomes the order of the map
+    pairs: Vec<(Oid, Oid)>,
+    to_subgit: HashMap<Oid, Vec<Oid>>,
+    to_upstream: HashMap<Oid, Vec<Oid>>,
+}
+
+impl<'r> Pairing<'r> {
+    fn pair(&mut self, upstream: Oid, subgit: Oid) {
+        self.pairs.push((upstream, subgit));
+        self.to_subgit.entry(upstream).or_default().push(subgit);
+        self.to_upstream.entry(subgit).or_default().push(upstream);
+    }
+
+    /// The counterpart an import of a child would build on - the last one found
+    fn newest_subgit(&self, upstream: &Oid) -> Option<Oid> {
+        self.to_subgit.get(upstream).and_then(|counterparts| counterparts.last().cloned())
+    }
+
+    fn same_content(&self, upstream: &Commit, subgit: &Commit) -> Result<bool, failure::Error> {
+        Ok(mismatched_mappings(self.read_only, &self.excludes, &upstream.tree()?, &subgit.tree()?)?.is_empty())
+    }
+
+    fn same_author(&self, upstream: &Commit, subgit: &Commit) -> Result<bool, failure::Error> {
+        Ok(same_person(&self.identities.author(&upstream.author(), Location::SUBGIT)?, &subgit.author())
+            || same_person(&self.identities.author(&subgit.author(), Location::UPSTREAM)?, &upstream.author()))
+    }
+
+    /// The parents an import of the upstream commit would get, like the copier picks them - None if
+    /// some of the upstream parents aren't paired up
+    fn imported_parents(&self, upstream: &Commit) -> Option<Vec<Oid>> {
+        if upstream.parent_ids().count() == 0 {
+            return self.subgit_empty.map(|empty| vec![empty]);
+        }
+        let mut parents: Vec<Oid> = vec![];
+        for parent in upstream.parent_ids() {
+            let counterpart = self.newest_subgit(&parent)?;
+            if !parents.contains(&counterpart) {
+                parents.push(counterpart);
+            }
+        }
+        if let [first, second] = parents[..] {
+            let subgit = &self.read_only.subgit;
+            if subgit.graph_descendant_of(first, second).unwrap_or(false) {
+                parents = vec![first];
+            } else if subgit.graph_descendant_of(second, first).unwrap_or(false) {
+                parents = vec![second];
+            }
+        }
+        Some(parents)
+    }
+
+    /// Whether the parents of the commits are counterparts of each other, whichever side the
+    /// commit was copied from
+    fn same_topology(&self, upstream: &Commit, subgit: &Commit) -> bool {
+        let subgit_parents: HashSet<Oid> = subgit.parent_ids().collect();
+        let imported = self
+            .imported_parents(upstream)
+            .map(|parents| parents.into_iter().collect::<HashSet<Oid>>() == subgit_parents)
+            .unwrap_or(false);
+        let exported = if subgit.parent_ids().count() == 0 {
+            upstream.parent_ids().eq(self.upstream_empty)
+        } else {
+            upstream.parent_ids().all(|parent| {
+                self.to_subgit
+                    .get(&parent)
+                    .map(|counterparts| counterparts.iter().any(|counterpart| subgit_parents.contains(counterpart)))
+                    .unwrap_or(false)
+            })
+        };
+        imported || exported
+    }
+}
+
+/// Pairs up the commits of the upstream and the subgit again, from their history alone. A commit is
+/// paired with the counterpart named by its mapping note or sync trailer if there is one, with the
+/// counterpart of its parent if it doesn't change the mapped directories, and otherwise with the subgit
+/// commit that has the same times, message, (mapped) author, mapped content and parents.
+fn rebuild(subgit_location: &Path) -> Result<Rebuilt, failure::Error> {
+    let read_only = ReadOnlySubGit::open(subgit_location)?;
+    let empty = |repo: &Repository| repo.refname_to_id("refs/sync/empty").ok();
+    let mut pairing = Pairing {
+        read_only: &read_only,
+        excludes: Excludes::new(&read_only.settings.excludes())?,
+        identities: Identities::new(&read_only.settings.identities())?,
+        upstream_empty: empty(&read_only.upstream),
+        subgit_empty: empty(&read_only.subgit),
+        pairs: vec![],
+        to_subgit: HashMap::new(),
+        to_upstream: HashMap::new(),
+    };
+
+    let ref_pairs = read_only.ref_pairs()?;
+    let upstream_tips: Vec<Oid> = ref_pairs.iter().filter_map(|pair| pair.upstream_sha).chain(pairing.upstream_empty).collect();
+    let subgit_tips: Vec<Oid> = ref_pairs.iter().filter_map(|pair| pair.subgit_sha).chain(pairing.subgit_empty).collect();
+    let upstream_commits = walk(&read_only.upstream, &upstream_tips)?;
+    let subgit_commits = walk(&read_only.subgit, &subgit_tips)?;
+
+    if let (Some(upstream_empty), Some(subgit_empty)) = (pairing.upstream_empty, pairing.subgit_empty) {
+        pairing.pair(upstream_empty, subgit_empty);
+    }
+
+    // What the subgit commits say about their counterparts, and the subgit commits by what copies share
+    let mut named_by_subgit: HashMap<Oid, Vec<Oid>> = HashMap::new();
+    let mut by_key: HashMap<CommitKey, Vec<Oid>> = HashMap::new();
+    for sha in &subgit_commits {
+        let commit = read_only.subgit.find_commit(*sha)?;
+        if let Some(upstream) = named_counterpart(&read_only.subgit, &commit, Location::UPSTREAM) {
+            named_by_subgit.entry(upstream).or_default().push(*sha);
+        }
+        by_key.entry(commit_key(&commit)).or_default().push(*sha);
+    }
+
+    let mut unresolved = vec![];
+    for sha in &upstream_commits {
+        if pairing.to_subgit.contains_key(sha) {
+            continue;
+        }
+        let commit = read_only.upstream.find_commit(*sha)?;
+
+        // An upstream commit synced to several branches can have a copy on each, which all name it
+        let mut named: Vec<Oid> = named_counterpart(&read_only.upstream, &commit, Location::SUBGIT).into_iter().collect();
+        for subgit in named_by_subgit.get(sha).map(Vec::as_slice).unwrap_or(&[]) {
+            if !named.contains(subgit) {
+                named.push(*subgit);
+            }
+        }
+        let mut paired = false;
+        for subgit_commit in named.into_iter().filter_map(|named| read_only.subgit.find_commit(named).ok()) {
+            if pairing.same_content(&commit, &subgit_commit)? {
+                pairing.pair(*sha, subgit_commit.id());
+                paired = true;
+            }
+        }
+        if paired {
+            continue;
+        }
+
+        // A commit that doesn't change the mapped directories was folded into its parent's counterpart
+        if let Some([parent]) = pairing.imported_parents(&commit).as_deref() {
+            if pairing.same_content(&commit, &read_only.subgit.find_commit(*parent)?)? {
+                pairing.pair(*sha, *parent);
+                continue;
+            }
+        }
+
+        let mut candidates = vec![];
+        // A subgit commit pushed to several branches can have a copy on each of them upstream, so the
+        // candidates include subgit commits which are already paired
+        for candidate in by_key.get(&commit_key(&commit)).map(Vec::as_slice).unwrap_or(&[]) {
+            let subgit_commit = read_only.subgit.find_commit(*candidate)?;
+            if pairing.same_author(&commit, &subgit_commit)?
+                && pairing.same_content(&commit, &subgit_commit)?
+                && pairing.same_topology(&commit, &subgit_commit)
+            {
+                candidates.push(*candidate);
+            }
+        }
+        match candidates.as_slice() {
+            [] => unresolved.push(Unresolved::Unmatched {
+                location: Location::UPSTREAM,
+                sha: *sha,
+            }),
+            [subgit] => pairing.pair(*sha, *subgit),
+            _ => unresolved.push(Unresolved::Ambiguous {
+                upstream: *sha,
+                candidates,
+            }),
+        }
+    }
+
+    for sha in subgit_commits {
+        if !pairing.to_upstream.contains_key(&sha) {
+            unresolved.push(Unresolved::Unmatched {
+                location: Location::SUBGIT,
+                sha,
+            });
+        }
+    }
+    Ok(Rebuilt {
+        pairs: pairing.pairs,
+        unresolved,
+    })
+}
+
+/// Rebuilds the map into a new database at the given path, which mustn't exist yet
+pub fn rebuild_into(subgit_location: &Path, output: &Path) -> Result<Rebuilt, failure::Error> {
+    if output.exists() {
+        return Err(format_err!("{:?} already exists", output));
+    }
+    let rebuilt = rebuild(subgit_location)?;
+
+    let mut conn = Connection::open(output)?;
+    #[allow(non_snake_case)]
+    let EMPTY: Vec<String> = vec![];
+    conn.execute(&Location::UPSTREAM.create_statement(), &EMPTY)?;
+    conn.execute(&Location::SUBGIT.create_statement(), &EMPTY)?;
+    let mapper = CommitMapper::new(&mut conn);
+    // The pairs are parents first, so counting up from the epoch makes the topologically last
+    // counterpart of a commit the newest one - and anything synced after the rebuild newer still
+    for (index, (upstream, subgit)) in rebuilt.pairs.iter().enumerate() {
+        let timestamp = Utc.timestamp(index as i64, 0);
+        mapper.set_translated_at(upstream, Location::UPSTREAM, subgit, None, timestamp);
+        mapper.set_translated_at(subgit, Location::SUBGIT, upstream, None, timestamp);
+    }
+    mapper.save_changes();
+
+    Ok(rebuilt)
+}
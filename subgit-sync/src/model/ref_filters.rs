@@ -0,0 +1,138 @@
+use crate::action::RefFilter;
+use glob::{MatchOptions, Pattern};
+use regex::Regex;
+
+/// How a single `--match_ref` entry is matched against a ref name
+///  * `^refs/heads/(feature|bugfix)/.*` - a regex, anchored at both ends
+///  * `refs/heads/release/*` - a glob; `*` stops at slashes, `**` doesn't. Ref names can't contain
+///    `*`, `?` or `[`, so anything with one of those is taken to be a glob
+///  * `refs/heads/` - anything else is a prefix, like it's always been
+enum Matcher {
+    Prefix(String),
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+struct RefPattern {
+    matcher: Matcher,
+    negated: bool,
+}
+
+/// The ordered list of ref filters. The last entry that matches a ref decides whether it's synced,
+/// so `!` entries carve exceptions out of the entries before them. A ref that no entry matches isn't synced.
+pub struct RefFilters {
+    patterns: Vec<RefPattern>,
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+impl Matcher {
+    fn parse(raw: &str) -> Result<Matcher, failure::Error> {
+        if raw.starts_with('^') {
+            let body = raw.trim_start_matches('^');
+            let body = if body.ends_with('$') && !body.ends_with("\\$") {
+                &body[..body.len() - 1]
+            } else {
+                body
+            };
+            Regex::new(&format!("^(?:{})$", body))
+                .map(Matcher::Regex)
+                .map_err(|err| failure::format_err!("Bad ref filter regex '{}': {}", raw, err))
+        } else if raw.contains(['*', '?', '[']) {
+            Pattern::new(raw)
+                .map(Matcher::Glob)
+                .map_err(|err| failure::format_err!("Bad ref filter glob '{}': {}", raw, err))
+        } else {
+            Ok(Matcher::Prefix(raw.to_owned()))
+        }
+    }
+
+    fn matches(&self, ref_name: &str) -> bool {
+        match self {
+            Matcher::Prefix(prefix) => ref_name.starts_with(prefix.as_str()),
+            Matcher::Glob(pattern) => pattern.matches_with(ref_name, MATCH_OPTIONS),
+            Matcher::Regex(regex) => regex.is_match(ref_name),
+        }
+    }
+}
+
+impl RefFilters {
+    pub fn new(filters: &[String]) -> Result<RefFilters, failure::Error> {
+        let patterns = filters
+            .iter()
+            .map(|raw| {
+                let negated = raw.starts_with('!');
+                let matcher = Matcher::parse(if negated { &raw[1..] } else { raw })?;
+                Ok(RefPattern { matcher, negated })
+            })
+            .collect::<Result<Vec<RefPattern>, failure::Error>>()?;
+        Ok(RefFilters { patterns })
+    }
+}
+
+impl RefFilter for RefFilters {
+    fn matches<R: AsRef<str>>(&self, ref_name: R) -> bool {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matcher.matches(ref_name.as_ref()))
+            .map(|pattern| !pattern.negated)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RefFilters;
+    use crate::action::RefFilter;
+
+    fn filters(raw: &[&str]) -> RefFilters {
+        RefFilters::new(&raw.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_prefix_filters() {
+        let filters = filters(&["refs/heads/", "refs/tags/", "HEAD"]);
+        assert!(filters.matches("refs/heads/master"));
+        assert!(filters.matches("refs/heads/feature/a"));
+        assert!(filters.matches("HEAD"));
+        assert!(!filters.matches("refs/notes/subgit"));
+    }
+
+    #[test]
+    fn test_glob_filters() {
+        let filters = filters(&["refs/heads/release/*", "refs/tags/**"]);
+        assert!(filters.matches("refs/heads/release/1.0"));
+        assert!(!filters.matches("refs/heads/release/1.0/hotfix"));
+        assert!(!filters.matches("refs/heads/master"));
+        assert!(filters.matches("refs/tags/v1/rc1"));
+    }
+
+    #[test]
+    fn test_regex_filters() {
+        let filters = filters(&["^refs/heads/(feature|bugfix)/[0-9]+", "^refs/tags/v[0-9.]+$"]);
+        assert!(filters.matches("refs/heads/feature/123"));
+        assert!(!filters.matches("refs/heads/feature/123-name"));
+        assert!(!filters.matches("refs/remotes/origin/refs/heads/feature/123"));
+        assert!(filters.matches("refs/tags/v1.2"));
+    }
+
+    #[test]
+    fn test_negated_filters() {
+        let filters = filters(&["refs/heads/release/*", "!refs/heads/release/internal-*", "refs/heads/release/internal-ok"]);
+        assert!(filters.matches("refs/heads/release/1.0"));
+        assert!(!filters.matches("refs/heads/release/internal-1.0"));
+        assert!(filters.matches("refs/heads/release/internal-ok"));
+        assert!(!filters.matches("refs/heads/master"));
+    }
+
+    #[test]
+    fn test_bad_filters() {
+        assert!(RefFilters::new(&["^refs/heads/(".to_owned()]).is_err());
+        assert!(RefFilters::new(&["refs/heads/[".to_owned()]).is_err());
+    }
+}
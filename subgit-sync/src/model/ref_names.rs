@@ -0,0 +1,154 @@
+use super::settings::RefRename;
+use super::Location;
+use failure::format_err;
+
+/// One side of a ref rename - either a full ref name, or a ref name with a single `*` in it,
+/// which matches any (non-empty) run of characters, slashes included, like in a refspec
+struct RefPattern {
+    prefix: String,
+    suffix: Option<String>,
+}
+
+impl RefPattern {
+    fn parse(raw: &str) -> Result<RefPattern, failure::Error> {
+        let mut iter = raw.splitn(2, '*');
+        let prefix = iter.next().unwrap_or("").to_owned();
+        let suffix = iter.next().map(|suffix| suffix.to_owned());
+        if suffix.as_ref().map(|suffix| suffix.contains('*')).unwrap_or(false) {
+            return Err(format_err!("Ref name pattern '{}' can only have one '*'", raw));
+        }
+        Ok(RefPattern { prefix, suffix })
+    }
+
+    /// The part of the ref name that the `*` matched, or the empty string for an exact match
+    fn capture<'n>(&self, ref_name: &'n str) -> Option<&'n str> {
+        match &self.suffix {
+            None if ref_name == self.prefix => Some(""),
+            None => None,
+            Some(suffix) => ref_name
+                .strip_prefix(self.prefix.as_str())
+                .and_then(|rest| rest.strip_suffix(suffix.as_str()))
+                .filter(|captured| !captured.is_empty()),
+        }
+    }
+
+    fn fill(&self, captured: &str) -> String {
+        match &self.suffix {
+            None => self.prefix.clone(),
+            Some(suffix) => format!("{}{}{}", self.prefix, captured, suffix),
+        }
+    }
+}
+
+struct Rename {
+    upstream: RefPattern,
+    subgit: RefPattern,
+}
+
+impl Rename {
+    fn side(&self, location: Location) -> &RefPattern {
+        match location {
+            Location::UPSTREAM => &self.upstream,
+            Location::SUBGIT => &self.subgit,
+        }
+    }
+}
+
+/// The table of refs published under a different name in the subgit. The first rename that matches
+/// a ref decides its name on the other side; refs that no rename matches keep their name, unless
+/// that name is claimed by a rename, in which case they aren't synchronized at all.
+pub struct RefNames {
+    renames: Vec<Rename>,
+}
+
+impl RefNames {
+    pub fn new(renames: &[RefRename]) -> Result<RefNames, failure::Error> {
+        let renames = renames
+            .iter()
+            .map(|rename| {
+                let upstream = RefPattern::parse(&rename.upstream)?;
+                let subgit = RefPattern::parse(&rename.subgit)?;
+                if upstream.suffix.is_some() != subgit.suffix.is_some() {
+                    return Err(format_err!(
+                        "Either both or neither of '{}' and '{}' need a '*'",
+                        rename.upstream,
+                        rename.subgit
+                    ));
+                }
+                Ok(Rename { upstream, subgit })
+            })
+            .collect::<Result<Vec<Rename>, failure::Error>>()?;
+        Ok(RefNames { renames })
+    }
+
+    /// Whether a rename matches the ref, named like in the given location
+    pub fn is_renamed<S: AsRef<str>>(&self, ref_name: S, location: Location) -> bool {
+        self.renames
+            .iter()
+            .any(|rename| rename.side(location).capture(ref_name.as_ref()).is_some())
+    }
+
+    /// The name of the ref in the given location, from its name in the other one
+    pub fn translate<S: AsRef<str>>(&self, ref_name: S, dest: Location) -> Option<String> {
+        let ref_name = ref_name.as_ref();
+        let source = match dest {
+            Location::UPSTREAM => Location::SUBGIT,
+            Location::SUBGIT => Location::UPSTREAM,
+        };
+        for rename in &self.renames {
+            if let Some(captured) = rename.side(source).capture(ref_name) {
+                return Some(rename.side(dest).fill(captured));
+            }
+        }
+        if self.renames.iter().any(|rename| rename.side(dest).capture(ref_name).is_some()) {
+            None
+        } else {
+            Some(ref_name.to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RefNames;
+    use crate::model::settings::RefRename;
+    use crate::model::Location;
+
+    fn rename(upstream: &str, subgit: &str) -> RefRename {
+        RefRename {
+            upstream: upstream.to_owned(),
+            subgit: subgit.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_translate() {
+        let names = RefNames::new(&[
+            rename("refs/heads/component-x/main", "refs/heads/main"),
+            rename("refs/tags/component-x-v*", "refs/tags/v*"),
+        ])
+        .unwrap();
+
+        let to_subgit = |name| names.translate(name, Location::SUBGIT);
+        let to_upstream = |name| names.translate(name, Location::UPSTREAM);
+
+        assert_eq!(Some("refs/heads/main".to_owned()), to_subgit("refs/heads/component-x/main"));
+        assert_eq!(Some("refs/heads/component-x/main".to_owned()), to_upstream("refs/heads/main"));
+        assert_eq!(Some("refs/tags/v1.0".to_owned()), to_subgit("refs/tags/component-x-v1.0"));
+        assert_eq!(Some("refs/tags/component-x-v1.0".to_owned()), to_upstream("refs/tags/v1.0"));
+
+        assert_eq!(Some("refs/heads/feature".to_owned()), to_subgit("refs/heads/feature"));
+        assert_eq!(Some("refs/heads/feature".to_owned()), to_upstream("refs/heads/feature"));
+
+        // The upstream's own main and v* tags would collide with the renamed ones
+        assert_eq!(None, to_subgit("refs/heads/main"));
+        assert_eq!(None, to_subgit("refs/tags/v1.0"));
+        assert_eq!(None, to_upstream("refs/heads/component-x/main"));
+    }
+
+    #[test]
+    fn test_bad_renames() {
+        assert!(RefNames::new(&[rename("refs/heads/*", "refs/heads/main")]).is_err());
+        assert!(RefNames::new(&[rename("refs/*/x-*", "refs/*/*")]).is_err());
+    }
+}
@@ -2,6 +2,9 @@ use crate::action::RecursionDetection;
 use crate::action::RecursionStatus;
 use crate::fs;
 use crate::logging;
+use crate::model::protection::RefProtection;
+use crate::model::signing::SignedCommitPolicy;
+use failure::format_err;
 use log::LevelFilter;
 use log_panics;
 use serde_json;
@@ -9,13 +12,91 @@ use std::path::{Path, PathBuf};
 
 pub const SETTINGS_FILE: &str = "settings.json";
 
+/// A directory in the upstream and where it's republished in the subgit
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct PathMapping {
+    pub upstream: String,
+    pub subgit: String,
+}
+
+/// A ref in the upstream and the name it's published under in the subgit. Either both names
+/// contain a single '*', or neither does
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct RefRename {
+    pub upstream: String,
+    pub subgit: String,
+}
+
+/// Where the hook was installed in each repository, relative to the repository
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct HookPaths {
+    pub subgit: PathBuf,
+    pub upstream: PathBuf,
+}
+
+/// The rewrites applied to the authors and committers of the commits crossing over
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct IdentitySettings {
+    /// Mailmap lines applied to commits imported into the subgit
+    #[serde(default)]
+    pub import: Vec<String>,
+    /// Mailmap lines applied to commits exported to the upstream
+    #[serde(default)]
+    pub export: Vec<String>,
+    /// Replaces the committer of every copied commit, in the form of 'Name <email>'
+    #[serde(default)]
+    pub committer: Option<String>,
+}
+
+/// Everything chosen at setup that the sync runs with - what the settings file holds, besides the hooks
+#[derive(Debug, Clone)]
+pub struct SyncSettings {
+    pub mappings: Vec<PathMapping>,
+    pub excludes: Vec<String>,
+    pub trailers: bool,
+    pub identities: IdentitySettings,
+    pub signed_commits: SignedCommitPolicy,
+    pub ref_names: Vec<RefRename>,
+    pub review_refs: Vec<RefRename>,
+    pub file_log_level: LevelFilter,
+    pub recursion_detection: RecursionDetection,
+    pub filters: Vec<String>,
+    pub protections: Vec<RefProtection>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct SettingsFile {
-    upstream_path: String,
-    subgit_path: String,
+    #[serde(default)]
+    mappings: Vec<PathMapping>,
+    // Settings files from before multiple mappings were supported only have a single pair
+    #[serde(default, skip_serializing)]
+    upstream_path: Option<String>,
+    #[serde(default, skip_serializing)]
+    subgit_path: Option<String>,
+    /// Patterns for paths inside the mapped directories that are never published to the subgit
+    #[serde(default)]
+    excludes: Vec<String>,
+    /// Whether mirrored commits get an Upstream-Commit or Subgit-Commit trailer
+    #[serde(default)]
+    trailers: bool,
+    #[serde(default)]
+    identities: IdentitySettings,
+    #[serde(default)]
+    signed_commits: SignedCommitPolicy,
+    #[serde(default)]
+    ref_names: Vec<RefRename>,
+    /// Upstream review refs (like merge request heads) mirrored read-only into the subgit
+    #[serde(default)]
+    review_refs: Vec<RefRename>,
     file_log_level: LevelFilter,
     recursion_detection: RecursionDetection,
     filters: Vec<String>,
+    /// Restrictions on the updates pushed to the subgit
+    #[serde(default)]
+    protections: Vec<RefProtection>,
+    /// Settings files from before the hooks were recorded don't have them
+    #[serde(default)]
+    hooks: Option<HookPaths>,
 }
 
 pub struct Settings {
@@ -24,28 +105,46 @@ pub struct Settings {
 }
 
 impl Settings {
-    pub fn generate<P: AsRef<Path>>(
-        path: P,
-        upstream_path: String,
-        subgit_path: String,
-        file_log_level: LevelFilter,
-        recursion_detection: RecursionDetection,
-        filters: Vec<String>,
-    ) {
+    pub fn generate<P: AsRef<Path>>(path: P, settings: SyncSettings, hooks: HookPaths) {
         let data_dir = path.as_ref();
         fs::write_content_to_file(
             &data_dir.join(SETTINGS_FILE),
             &serde_json::to_string_pretty(&SettingsFile {
-                upstream_path,
-                subgit_path,
-                file_log_level,
-                recursion_detection,
-                filters,
+                mappings: settings.mappings,
+                upstream_path: None,
+                subgit_path: None,
+                excludes: settings.excludes,
+                trailers: settings.trailers,
+                identities: settings.identities,
+                signed_commits: settings.signed_commits,
+                ref_names: settings.ref_names,
+                review_refs: settings.review_refs,
+                file_log_level: settings.file_log_level,
+                recursion_detection: settings.recursion_detection,
+                filters: settings.filters,
+                protections: settings.protections,
+                hooks: Some(hooks),
             })
             .unwrap(),
         );
     }
 
+    pub fn sync_settings(&self) -> SyncSettings {
+        SyncSettings {
+            mappings: self.mappings(),
+            excludes: self.excludes(),
+            trailers: self.trailers(),
+            identities: self.identities(),
+            signed_commits: self.signed_commits(),
+            ref_names: self.ref_names(),
+            review_refs: self.review_refs(),
+            file_log_level: self.internal.file_log_level,
+            recursion_detection: self.recursion_detection(),
+            filters: self.filters(),
+            protections: self.protections(),
+        }
+    }
+
     pub fn should_abort_hook(&self) -> bool {
         let status: RecursionStatus = self.recursion_detection().detect_recursion();
         let status_str = if status.is_recursing {
@@ -61,25 +160,80 @@ impl Settings {
         self.internal.recursion_detection.clone()
     }
 
-    pub fn upstream_path(&self) -> String {
-        self.internal.upstream_path.clone()
+    pub fn mappings(&self) -> Vec<PathMapping> {
+        match (&self.internal.upstream_path, &self.internal.subgit_path) {
+            (Some(upstream), subgit) if self.internal.mappings.is_empty() => vec![PathMapping {
+                upstream: upstream.clone(),
+                subgit: subgit.clone().unwrap_or_default(),
+            }],
+            _ => self.internal.mappings.clone(),
+        }
+    }
+
+    pub fn excludes(&self) -> Vec<String> {
+        self.internal.excludes.clone()
     }
 
-    pub fn local_path(&self) -> String {
-        self.internal.subgit_path.clone()
+    pub fn trailers(&self) -> bool {
+        self.internal.trailers
+    }
+
+    pub fn identities(&self) -> IdentitySettings {
+        self.internal.identities.clone()
+    }
+
+    pub fn signed_commits(&self) -> SignedCommitPolicy {
+        self.internal.signed_commits.clone()
+    }
+
+    pub fn ref_names(&self) -> Vec<RefRename> {
+        self.internal.ref_names.clone()
+    }
+
+    pub fn review_refs(&self) -> Vec<RefRename> {
+        self.internal.review_refs.clone()
     }
 
     pub fn filters(&self) -> Vec<String> {
         self.internal.filters.clone()
     }
 
+    pub fn protections(&self) -> Vec<RefProtection> {
+        self.internal.protections.clone()
+    }
+
+    pub fn hooks(&self) -> Option<HookPaths> {
+        self.internal.hooks.clone()
+    }
+
     pub fn load<P: AsRef<Path>>(path: P) -> Settings {
+        Settings::try_load(path).unwrap()
+    }
+
+    /// Like load, but reports a missing or unreadable settings file instead of panicking
+    pub fn try_load<P: AsRef<Path>>(path: P) -> Result<Settings, failure::Error> {
         let data_dir = path.as_ref();
-        let contents = fs::content_of_file_if_exists(&path.as_ref().join(SETTINGS_FILE)).unwrap();
-        Settings {
-            internal: serde_json::from_str(contents.as_str()).unwrap(),
+        let settings_path = data_dir.join(SETTINGS_FILE);
+        let contents = std::fs::read_to_string(&settings_path)
+            .map_err(|err| format_err!("Could not read {:?}: {}", settings_path, err))?;
+        Ok(Settings {
+            internal: serde_json::from_str(contents.as_str())
+                .map_err(|err| format_err!("Could not parse {:?}: {}", settings_path, err))?,
             data_dir: data_dir.to_owned(),
-        }
+        })
+    }
+
+    /// Rewrites the settings file in the current format: the single path pair of an old settings file
+    /// becomes a mapping, and the settings added since are written out with their defaults. The new file
+    /// is renamed over the old one, so a failed upgrade leaves the old one in place.
+    pub fn upgrade<P: AsRef<Path>>(path: P) -> Result<(), failure::Error> {
+        let mut settings = Settings::try_load(&path)?;
+        settings.internal.mappings = settings.mappings();
+        let settings_path = path.as_ref().join(SETTINGS_FILE);
+        let staged = settings_path.with_extension("json.new");
+        std::fs::write(&staged, serde_json::to_string_pretty(&settings.internal)?)?;
+        std::fs::rename(&staged, &settings_path)?;
+        Ok(())
     }
 
     pub fn setup_logging(&self) {
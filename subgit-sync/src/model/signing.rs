@@ -0,0 +1,161 @@
+use failure::format_err;
+use git2::{Oid, Repository};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SigningFormat {
+    Gpg,
+    Ssh,
+}
+
+/// A key held on the server to sign the synchronized commits with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SigningKey {
+    pub format: SigningFormat,
+    /// The gpg key id, or the path to the ssh private key
+    pub key: String,
+}
+
+/// What to do when a commit being synchronized is signed - the signature can't be carried over since the
+/// copied commit has different content
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub enum SignedCommitPolicy {
+    /// Drop the signature, adding a trailer naming the signed commit
+    #[default]
+    Strip,
+    /// Sign the copy with a key held on the server
+    Resign(SigningKey),
+    /// Don't synchronize signed commits at all
+    Refuse,
+}
+
+impl FromStr for SignedCommitPolicy {
+    type Err = failure::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut iter = input.splitn(2, ":");
+        match (iter.next(), iter.next()) {
+            (Some("strip"), None) => Ok(SignedCommitPolicy::Strip),
+            (Some("refuse"), None) => Ok(SignedCommitPolicy::Refuse),
+            (Some("resign-gpg"), Some(key)) if !key.is_empty() => Ok(SignedCommitPolicy::Resign(SigningKey {
+                format: SigningFormat::Gpg,
+                key: key.to_owned(),
+            })),
+            (Some("resign-ssh"), Some(key)) if !key.is_empty() => Ok(SignedCommitPolicy::Resign(SigningKey {
+                format: SigningFormat::Ssh,
+                key: key.to_owned(),
+            })),
+            _ => Err(format_err!(
+                "Expected one of strip, refuse, resign-gpg:KEY_ID or resign-ssh:KEY_FILE, got '{}'",
+                input
+            )),
+        }
+    }
+}
+
+impl Display for SignedCommitPolicy {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            SignedCommitPolicy::Strip => write!(f, "strip"),
+            SignedCommitPolicy::Refuse => write!(f, "refuse"),
+            SignedCommitPolicy::Resign(SigningKey {
+                format: SigningFormat::Gpg,
+                key,
+            }) => write!(f, "resign-gpg:{}", key),
+            SignedCommitPolicy::Resign(SigningKey {
+                format: SigningFormat::Ssh,
+                key,
+            }) => write!(f, "resign-ssh:{}", key),
+        }
+    }
+}
+
+pub fn is_signed(repo: &Repository, oid: &Oid) -> bool {
+    repo.extract_signature(oid, None).is_ok()
+}
+
+/// The lines that start the signature git appends to the message of a signed tag
+const TAG_SIGNATURE_STARTS: &[&str] = &[
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN SSH SIGNATURE-----",
+    "-----BEGIN SIGNED MESSAGE-----",
+];
+
+/// Splits the message of a tag into the message itself and the signature appended to it, if there is one
+pub fn split_tag_signature(message: &str) -> (&str, Option<&str>) {
+    let mut offset = 0;
+    for line in message.split_inclusive('\n') {
+        if TAG_SIGNATURE_STARTS.contains(&line.trim_end()) {
+            return (&message[..offset], Some(&message[offset..]));
+        }
+        offset += line.len();
+    }
+    (message, None)
+}
+
+impl SigningKey {
+    /// Makes a detached signature of the commit content, the same way git would
+    pub fn sign(&self, content: &[u8]) -> Result<String, failure::Error> {
+        let mut command = match self.format {
+            SigningFormat::Gpg => {
+                let mut command = Command::new("gpg");
+                command.args(["--status-fd=2", "-bsau", &self.key]);
+                command
+            }
+            SigningFormat::Ssh => {
+                let mut command = Command::new("ssh-keygen");
+                command.args(["-Y", "sign", "-n", "git", "-f", &self.key]);
+                command
+            }
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(content)?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(format_err!(
+                "Could not sign the commit: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{split_tag_signature, SignedCommitPolicy, SigningFormat, SigningKey};
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!(SignedCommitPolicy::Strip, "strip".parse().unwrap());
+        assert_eq!(SignedCommitPolicy::Refuse, "refuse".parse().unwrap());
+        assert_eq!(
+            SignedCommitPolicy::Resign(SigningKey {
+                format: SigningFormat::Ssh,
+                key: "/srv/keys/sync:key".to_owned()
+            }),
+            "resign-ssh:/srv/keys/sync:key".parse().unwrap()
+        );
+        assert!("resign-gpg:".parse::<SignedCommitPolicy>().is_err());
+        assert!("keep".parse::<SignedCommitPolicy>().is_err());
+        assert_eq!("resign-gpg:ABCD", "resign-gpg:ABCD".parse::<SignedCommitPolicy>().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_split_tag_signature() {
+        assert_eq!(("Release v1\n", None), split_tag_signature("Release v1\n"));
+        let signature = "-----BEGIN SSH SIGNATURE-----\nU1NIU0lH\n-----END SSH SIGNATURE-----\n";
+        assert_eq!(
+            ("Release v1\n", Some(signature)),
+            split_tag_signature(&format!("Release v1\n{}", signature))
+        );
+        assert_eq!(("", Some(signature)), split_tag_signature(signature));
+    }
+}
@@ -0,0 +1,82 @@
+use super::map::ReadOnlyMapper;
+use super::read_only::ReadOnlySubGit;
+use super::Location;
+use std::path::Path;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncState {
+    /// The subgit ref points to the copy of the upstream ref
+    InSync,
+    /// The subgit ref is behind the copy of the upstream ref, or the upstream commits aren't imported yet
+    Behind,
+    /// The subgit ref isn't in the history of the copy of the upstream ref
+    Diverged,
+    /// Only one side has the ref
+    Missing,
+}
+
+impl SyncState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncState::InSync => "in-sync",
+            SyncState::Behind => "behind",
+            SyncState::Diverged => "diverged",
+            SyncState::Missing => "missing",
+        }
+    }
+}
+
+/// The synchronization state of a ref matching the filters. The shas are the commits the refs point to
+#[derive(Serialize, Debug, Clone)]
+pub struct RefStatus {
+    pub ref_name: String,
+    pub subgit_ref_name: String,
+    pub upstream_sha: Option<String>,
+    /// What the upstream sha translates to in the subgit
+    pub expected_subgit_sha: Option<String>,
+    pub subgit_sha: Option<String>,
+    pub state: SyncState,
+}
+
+/// Compares the refs of the upstream and the subgit
+pub fn ref_statuses(subgit_location: &Path) -> Result<Vec<RefStatus>, failure::Error> {
+    let read_only = ReadOnlySubGit::open(subgit_location)?;
+    let conn = read_only.open_map()?;
+    let mapper = ReadOnlyMapper::new(&conn);
+    let subgit = &read_only.subgit;
+
+    Ok(read_only
+        .ref_pairs()?
+        .into_iter()
+        .map(|pair| {
+            let expected = mapper.get_translated_for_ref(
+                pair.upstream_sha.as_ref(),
+                Location::UPSTREAM,
+                &pair.ref_name,
+                subgit,
+                pair.subgit_sha,
+            );
+            let state = match (pair.upstream_sha, expected, pair.subgit_sha) {
+                (None, _, _) | (_, _, None) => SyncState::Missing,
+                (_, Some(expected), Some(actual)) if expected == actual => SyncState::InSync,
+                (_, None, Some(_)) => SyncState::Behind,
+                (_, Some(expected), Some(actual)) => {
+                    if subgit.graph_descendant_of(expected, actual).unwrap_or(false) {
+                        SyncState::Behind
+                    } else {
+                        SyncState::Diverged
+                    }
+                }
+            };
+            RefStatus {
+                ref_name: pair.ref_name,
+                subgit_ref_name: pair.subgit_ref_name,
+                upstream_sha: pair.upstream_sha.map(|sha| sha.to_string()),
+                expected_subgit_sha: expected.map(|sha| sha.to_string()),
+                subgit_sha: pair.subgit_sha.map(|sha| sha.to_string()),
+                state,
+            }
+        })
+        .collect())
+}
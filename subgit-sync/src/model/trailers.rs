@@ -0,0 +1,144 @@
+use super::Location;
+use git2::Oid;
+
+/// The trailer naming the commit in the given repository that a mirrored commit came from
+pub fn trailer_key(location: Location) -> &'static str {
+    match location {
+        Location::UPSTREAM => "Upstream-Commit",
+        Location::SUBGIT => "Subgit-Commit",
+    }
+}
+
+/// The trailer naming the signed commit that a copy was made from without its signature
+pub const SIGNATURE_STRIPPED: &str = "Signature-Stripped";
+
+fn is_trailer(line: &str) -> bool {
+    match line.find(": ") {
+        Some(idx) if idx > 0 => line[..idx].chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+        _ => false,
+    }
+}
+
+/// Splits off the trailer block - the last paragraph, if every line of it is a trailer.
+/// A message that's a single paragraph has no trailers, like in git.
+fn split_trailers(message: &str) -> (&str, Vec<&str>) {
+    let trimmed = message.trim_end();
+    if let Some(idx) = trimmed.rfind("\n\n") {
+        let last = &trimmed[idx + 2..];
+        if !last.is_empty() && last.lines().all(is_trailer) {
+            return (trimmed[..idx].trim_end(), last.lines().collect());
+        }
+    }
+    (trimmed, vec![])
+}
+
+fn render(body: &str, trailers: &[&str]) -> String {
+    let mut message = body.to_owned();
+    if !trailers.is_empty() {
+        if !message.is_empty() {
+            message.push_str("\n\n");
+        }
+        message.push_str(&trailers.join("\n"));
+    }
+    message.push('\n');
+    message
+}
+
+fn is_key(line: &str, key: &str) -> bool {
+    line.split(": ").next().map(|k| k.eq_ignore_ascii_case(key)).unwrap_or(false)
+}
+
+/// Adds a trailer to the message, after any that it already has
+pub fn append(message: &str, key: &str, value: &str) -> String {
+    let (body, mut trailers) = split_trailers(message);
+    let trailer = format!("{}: {}", key, value);
+    trailers.push(&trailer);
+    render(body, &trailers)
+}
+
+/// Builds the message for a commit copied out of the source repository: the trailer pointing into the
+/// destination is dropped (it's left over from an earlier trip the other way), and one pointing back to
+/// the source commit is added.
+pub fn translate(message: &str, source: Location, dest: Location, source_sha: &Oid) -> String {
+    let (body, trailers) = split_trailers(message);
+    let source_trailer = format!("{}: {}", trailer_key(source), source_sha);
+    let mut trailers: Vec<&str> = trailers
+        .into_iter()
+        .filter(|line| !is_key(line, trailer_key(dest)) && !is_key(line, trailer_key(source)))
+        .collect();
+    trailers.push(&source_trailer);
+    render(body, &trailers)
+}
+
+/// The value of the trailer with the given key, if the message has one
+pub fn value<'m>(message: &'m str, key: &str) -> Option<&'m str> {
+    split_trailers(message)
+        .1
+        .into_iter()
+        .find(|line| is_key(line, key))
+        .map(|line| line[key.len() + 2..].trim())
+}
+
+/// The message without the trailers that copying a commit adds, which is what the copies of a commit
+/// have in common
+pub fn without_sync_trailers(message: &str) -> String {
+    let (body, trailers) = split_trailers(message);
+    let trailers: Vec<&str> = trailers
+        .into_iter()
+        .filter(|line| {
+            !is_key(line, trailer_key(Location::UPSTREAM))
+                && !is_key(line, trailer_key(Location::SUBGIT))
+                && !is_key(line, SIGNATURE_STRIPPED)
+        })
+        .collect();
+    render(body, &trailers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{translate, value, without_sync_trailers};
+    use crate::model::Location;
+    use git2::Oid;
+
+    #[test]
+    fn test_trailers() {
+        let upstream_sha = Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        let subgit_sha = Oid::from_str("2222222222222222222222222222222222222222").unwrap();
+
+        let imported = translate("Fix: the thing\n", Location::UPSTREAM, Location::SUBGIT, &upstream_sha);
+        assert_eq!(
+            "Fix: the thing\n\nUpstream-Commit: 1111111111111111111111111111111111111111\n",
+            imported
+        );
+
+        let exported = translate(&imported, Location::SUBGIT, Location::UPSTREAM, &subgit_sha);
+        assert_eq!(
+            "Fix: the thing\n\nSubgit-Commit: 2222222222222222222222222222222222222222\n",
+            exported
+        );
+
+        let signed_off = translate(
+            "Subject\n\nBody\n\nSigned-off-by: A <a@example.com>\nSubgit-Commit: 2222222222222222222222222222222222222222\n",
+            Location::UPSTREAM,
+            Location::SUBGIT,
+            &upstream_sha,
+        );
+        assert_eq!(
+            "Subject\n\nBody\n\nSigned-off-by: A <a@example.com>\nUpstream-Commit: 1111111111111111111111111111111111111111\n",
+            signed_off
+        );
+    }
+
+    #[test]
+    fn test_sync_trailers() {
+        let message = "Subject\n\nSigned-off-by: A <a@example.com>\nUpstream-Commit: 1111111111111111111111111111111111111111\n";
+        assert_eq!(Some("1111111111111111111111111111111111111111"), value(message, "upstream-commit"));
+        assert_eq!(None, value(message, "Subgit-Commit"));
+        assert_eq!(None, value("Upstream-Commit: 1111111111111111111111111111111111111111\n", "Upstream-Commit"));
+        assert_eq!("Subject\n\nSigned-off-by: A <a@example.com>\n", without_sync_trailers(message));
+        assert_eq!(
+            "Subject\n",
+            without_sync_trailers("Subject\n\nSubgit-Commit: 2222222222222222222222222222222222222222\nSignature-Stripped: 3333333333333333333333333333333333333333\n")
+        );
+    }
+}
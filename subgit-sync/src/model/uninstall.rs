@@ -0,0 +1,141 @@
+use super::settings::Settings;
+use super::Location;
+use crate::git;
+use chrono::Utc;
+use git2::Repository;
+use std::fs::{canonicalize, read_dir, read_link};
+use std::path::{Path, PathBuf};
+
+/// The directories of a repository that hooks get installed in - GitLab uses custom_hooks
+const HOOK_DIRS: &[&str] = &["hooks", "custom_hooks"];
+
+/// What happens to the data directory
+#[derive(Debug)]
+pub enum DataDisposal {
+    Archive(Option<PathBuf>),
+    Delete,
+}
+
+/// One step of taking subgit-sync out of the two repositories
+pub enum Step {
+    RemoveHook(PathBuf),
+    DeleteRef {
+        location: Location,
+        repo_path: PathBuf,
+        ref_name: String,
+    },
+    ArchiveData { from: PathBuf, to: PathBuf },
+    DeleteData(PathBuf),
+}
+
+impl Step {
+    pub fn describe(&self, dry_run: bool) -> String {
+        let verb = |would: &str, done: &str| if dry_run { format!("Would {}", would) } else { done.to_owned() };
+        match self {
+            Step::RemoveHook(path) => format!("{} the hook {}", verb("remove", "Removed"), path.to_string_lossy()),
+            Step::DeleteRef { location, ref_name, .. } => {
+                format!("{} {} in the {}", verb("delete", "Deleted"), ref_name, location)
+            }
+            Step::ArchiveData { from, to } => format!(
+                "{} {} to {}",
+                verb("move", "Moved"),
+                from.to_string_lossy(),
+                to.to_string_lossy()
+            ),
+            Step::DeleteData(path) => format!("{} {}", verb("delete", "Deleted"), path.to_string_lossy()),
+        }
+    }
+
+    pub fn run(&self) -> Result<(), failure::Error> {
+        match self {
+            Step::RemoveHook(path) => std::fs::remove_file(path)?,
+            Step::DeleteRef { repo_path, ref_name, .. } => {
+                Repository::open_bare(repo_path)?.find_reference(ref_name)?.delete()?;
+            }
+            Step::ArchiveData { from, to } => std::fs::rename(from, to)?,
+            // Doesn't follow the symlinks into the repositories
+            Step::DeleteData(path) => std::fs::remove_dir_all(path)?,
+        }
+        Ok(())
+    }
+}
+
+/// Where a symlink points, with the directory it's in resolved
+fn link_target(link: &Path) -> Option<PathBuf> {
+    let target = link.parent()?.join(read_link(link).ok()?);
+    Some(canonicalize(target.parent()?).ok()?.join(target.file_name()?))
+}
+
+/// The hooks of the repository that are symlinks to the given hook - the ones that point anywhere
+/// else (e.g. since they've been replaced) aren't ours to remove. The hook recorded in the settings is
+/// the one setup installed; installs from before it was recorded get their hook directories searched.
+pub(super) fn installed_hooks(repo_path: &Path, hook: &Path, recorded: Option<&Path>) -> Result<Vec<PathBuf>, failure::Error> {
+    let is_ours = |path: &Path| link_target(path).map(|target| target == hook).unwrap_or(false);
+    if let Some(recorded) = recorded {
+        let path = repo_path.join(recorded);
+        return Ok(if is_ours(&path) { vec![path] } else { vec![] });
+    }
+    let mut hooks = vec![];
+    for dir in HOOK_DIRS {
+        let dir = repo_path.join(dir);
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in read_dir(dir)? {
+            let path = entry?.path();
+            if is_ours(&path) {
+                hooks.push(path);
+            }
+        }
+    }
+    hooks.sort();
+    Ok(hooks)
+}
+
+fn sync_refs(repo_path: &Path, location: Location) -> Result<Vec<Step>, failure::Error> {
+    Ok(git::get_refs(&Repository::open_bare(repo_path)?, "refs/sync/*")?
+        .into_iter()
+        .map(|(ref_name, _)| Step::DeleteRef {
+            location,
+            repo_path: repo_path.to_owned(),
+            ref_name,
+        })
+        .collect())
+}
+
+/// The steps that leave both repositories as they'd be without subgit-sync: the hooks and the refs/sync
+/// refs go, and so does the data directory (with the working clones, the map and the settings)
+pub fn plan(subgit_location: &Path, data: DataDisposal) -> Result<Vec<Step>, failure::Error> {
+    let subgit_location = canonicalize(subgit_location)?;
+    let data_path = subgit_location.join("data");
+    let upstream_location = canonicalize(data_path.join("upstream.git"))?;
+    let hook = data_path.join("hook");
+    // Settings that can't be read don't stop the uninstall - the hook directories are searched instead
+    let recorded = Settings::try_load(&data_path).ok().and_then(|settings| settings.hooks());
+
+    let mut steps = vec![];
+    let repos = [
+        (&subgit_location, recorded.as_ref().map(|hooks| hooks.subgit.as_path())),
+        (&upstream_location, recorded.as_ref().map(|hooks| hooks.upstream.as_path())),
+    ];
+    for (repo_path, recorded) in &repos {
+        steps.extend(installed_hooks(repo_path, &hook, *recorded)?.into_iter().map(Step::RemoveHook));
+    }
+    steps.extend(sync_refs(&subgit_location, Location::SUBGIT)?);
+    steps.extend(sync_refs(&upstream_location, Location::UPSTREAM)?);
+    steps.push(match data {
+        DataDisposal::Delete => Step::DeleteData(data_path),
+        DataDisposal::Archive(to) => {
+            let to = to.unwrap_or_else(|| {
+                let name = subgit_location.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                subgit_location.with_file_name(format!(
+                    "{}.subgit-data-{}",
+                    name,
+                    Utc::now().format("%Y%m%dT%H%M%SZ")
+                ))
+            });
+            Step::ArchiveData { from: data_path, to }
+        }
+    });
+    Ok(steps)
+}
@@ -0,0 +1,74 @@
+use super::map;
+use super::settings::Settings;
+use super::uninstall::installed_hooks;
+use super::Location;
+use failure::format_err;
+use rusqlite::Connection;
+use std::fs::canonicalize;
+use std::path::{Path, PathBuf};
+
+/// What an upgrade did
+pub struct Upgraded {
+    /// Whether data/hook was replaced, or already was the new binary
+    pub replaced_hook: bool,
+    /// The hooks of both repositories, which all run the new binary now
+    pub hooks: Vec<PathBuf>,
+}
+
+/// The hooks of the repository that point at data/hook - there has to be at least one
+fn hooks_of(repo_path: &Path, location: Location, hook: &Path, recorded: Option<&Path>) -> Result<Vec<PathBuf>, failure::Error> {
+    let hooks = installed_hooks(repo_path, hook, recorded)?;
+    if hooks.is_empty() {
+        return Err(format_err!("No hook of the {} points at {:?} anymore", location, hook));
+    }
+    Ok(hooks)
+}
+
+/// Replaces the hook with the binary. The binary is copied next to the hook first and then renamed over
+/// it, so a hook starting at the same time runs either the old or the new binary, but never half of one
+fn swap_hook(binary: &Path, hook: &Path) -> Result<bool, failure::Error> {
+    let installed = std::fs::read(hook).ok();
+    if installed.is_some() && installed == Some(std::fs::read(binary)?) {
+        return Ok(false);
+    }
+    let staged = hook.with_file_name("hook.new");
+    std::fs::copy(binary, &staged)?;
+    std::fs::rename(&staged, hook)?;
+    Ok(true)
+}
+
+/// Upgrades a subgit to the given binary: checks that the hooks of both repositories still point at
+/// data/hook, swaps the binary in, and brings the settings and the map up to the binary's format. The
+/// caller has to hold the lock.
+pub fn upgrade(subgit_location: &Path, binary: &Path) -> Result<Upgraded, failure::Error> {
+    let subgit_location = canonicalize(subgit_location)?;
+    let data_path = subgit_location.join("data");
+    let upstream_location = canonicalize(data_path.join("upstream.git"))
+        .map_err(|err| format_err!("data/upstream.git doesn't lead to the upstream anymore: {}", err))?;
+    let hook = data_path.join("hook");
+    // Read up front, so that unreadable settings stop the upgrade before the hook is swapped
+    let recorded = Settings::try_load(&data_path)?.hooks();
+
+    let mut hooks = hooks_of(
+        &subgit_location,
+        Location::SUBGIT,
+        &hook,
+        recorded.as_ref().map(|hooks| hooks.subgit.as_path()),
+    )?;
+    hooks.extend(hooks_of(
+        &upstream_location,
+        Location::UPSTREAM,
+        &hook,
+        recorded.as_ref().map(|hooks| hooks.upstream.as_path()),
+    )?);
+
+    let replaced_hook = swap_hook(binary, &hook)?;
+    if let Some(broken) = hooks.iter().find(|hook| !hook.exists()) {
+        return Err(format_err!("The hook {:?} doesn't resolve", broken));
+    }
+
+    Settings::upgrade(&data_path)?;
+    map::upgrade_schema(&Connection::open(data_path.join("map.sqlite"))?)?;
+
+    Ok(Upgraded { replaced_hook, hooks })
+}
@@ -0,0 +1,253 @@
+use super::excludes::Excludes;
+use super::map::ReadOnlyMapper;
+use super::read_only::ReadOnlySubGit;
+use super::settings::PathMapping;
+use super::Location;
+use crate::git;
+use git2::{ObjectType, Oid, Repository, Tree};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::{self, Display, Formatter};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    /// The mapped directory has different content on each side
+    Tree {
+        upstream: Oid,
+        subgit: Oid,
+        mapping: PathMapping,
+    },
+    /// The parents of the commits aren't counterparts of each other
+    Parents { upstream: Oid, subgit: Oid },
+    /// One of the commits doesn't exist
+    Missing { upstream: Oid, subgit: Oid },
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Tree { upstream, subgit, mapping } => write!(
+                f,
+                "tree mismatch: upstream {} ({}/) vs subgit {} ({}/)",
+                upstream, mapping.upstream, subgit, mapping.subgit
+            ),
+            Mismatch::Parents { upstream, subgit } => {
+                write!(f, "parent mismatch: upstream {} vs subgit {}", upstream, subgit)
+            }
+            Mismatch::Missing { upstream, subgit } => {
+                write!(f, "missing commit: upstream {} vs subgit {}", upstream, subgit)
+            }
+        }
+    }
+}
+
+/// What was checked, and everything that didn't match
+pub struct Report {
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+type Entries = BTreeMap<PathBuf, (Oid, i32, Option<ObjectType>)>;
+
+/// The entries of a tree, leaving out the ones the filter matches
+fn entries(repo: &Repository, tree: Option<Oid>, prefix: &Path, skipped: &dyn Fn(&Path, bool) -> bool) -> Result<Entries, failure::Error> {
+    let mut found = BTreeMap::new();
+    if let Some(oid) = tree {
+        for entry in repo.find_tree(oid)?.iter() {
+            let path = prefix.join(OsStr::from_bytes(entry.name_bytes()));
+            if !skipped(&path, entry.kind() == Some(ObjectType::Tree)) {
+                found.insert(path, (entry.id(), entry.filemode(), entry.kind()));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Whether the upstream directory, without its excluded entries, has the same content as the subgit
+/// directory. Directories that are left empty don't count, since they don't get copied.
+fn same_content(
+    read_only: &ReadOnlySubGit,
+    excludes: &Excludes,
+    translated_gitmodules: bool,
+    upstream: Option<Oid>,
+    subgit: Option<Oid>,
+    prefix: &Path,
+) -> Result<bool, failure::Error> {
+    if upstream == subgit {
+        return Ok(true);
+    }
+    // The .gitmodules at the root of a repo gets its paths rewritten rather than copied
+    let gitmodules = |path: &Path| translated_gitmodules && path == Path::new(".gitmodules");
+    let upstream_entries = entries(&read_only.upstream, upstream, prefix, &|path, is_dir| {
+        gitmodules(path) || excludes.is_excluded(path, is_dir)
+    })?;
+    let subgit_entries = entries(&read_only.subgit, subgit, prefix, &|path, _| gitmodules(path))?;
+
+    let paths: std::collections::BTreeSet<&PathBuf> = upstream_entries.keys().chain(subgit_entries.keys()).collect();
+    for path in paths {
+        let upstream_entry = upstream_entries.get(path);
+        let subgit_entry = subgit_entries.get(path);
+        let same = match (upstream_entry, subgit_entry) {
+            (Some((u, _, Some(ObjectType::Tree))), Some((s, _, Some(ObjectType::Tree)))) => {
+                same_content(read_only, excludes, translated_gitmodules, Some(*u), Some(*s), path)?
+            }
+            (Some((u, _, Some(ObjectType::Tree))), None) => {
+                same_content(read_only, excludes, translated_gitmodules, Some(*u), None, path)?
+            }
+            (None, Some((s, _, Some(ObjectType::Tree)))) => {
+                same_content(read_only, excludes, translated_gitmodules, None, Some(*s), path)?
+            }
+            (Some(u), Some(s)) => u == s,
+            _ => false,
+        };
+        if !same {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn subtree(tree: &Tree, location: &str) -> Option<Oid> {
+    git::find_subtree(tree, Path::new(location))
+}
+
+/// The mappings whose directories don't have the same content in the two trees
+pub(super) fn mismatched_mappings(
+    read_only: &ReadOnlySubGit,
+    excludes: &Excludes,
+    upstream_tree: &Tree,
+    subgit_tree: &Tree,
+) -> Result<Vec<PathMapping>, failure::Error> {
+    let mut mismatched = vec![];
+    for mapping in read_only.settings.mappings() {
+        let translated_gitmodules = mapping.upstream.is_empty() || mapping.subgit.is_empty();
+        if !same_content(
+            read_only,
+            excludes,
+            translated_gitmodules,
+            subtree(upstream_tree, &mapping.upstream),
+            subtree(subgit_tree, &mapping.subgit),
+            Path::new(""),
+        )? {
+            mismatched.push(mapping);
+        }
+    }
+    Ok(mismatched)
+}
+
+/// Whether the dest commit is a faithful copy of the source commit, parent-wise: it's either the
+/// counterpart of a source parent (the source commit didn't change the mapped directories) or its
+/// parents are counterparts of the source parents. A source root commit is copied on top of the
+/// empty base commit.
+fn parents_match(
+    mapper: &ReadOnlyMapper,
+    source: (&Repository, Location, Oid),
+    dest: (&Repository, Oid),
+    dest_empty: Option<Oid>,
+) -> Result<bool, failure::Error> {
+    let (source_repo, source_location, source_sha) = source;
+    let (dest_repo, dest_sha) = dest;
+    let source_parents: Vec<Oid> = source_repo.find_commit(source_sha)?.parent_ids().collect();
+    let mut counterparts: Vec<Oid> = source_parents
+        .iter()
+        .flat_map(|parent| mapper.get_all_translated(parent, source_location))
+        .collect();
+    if source_parents.is_empty() {
+        counterparts.extend(dest_empty);
+    }
+    if counterparts.contains(&dest_sha) {
+        return Ok(true);
+    }
+    Ok(dest_repo
+        .find_commit(dest_sha)?
+        .parent_ids()
+        .all(|parent| counterparts.contains(&parent)))
+}
+
+fn verify_pair(
+    read_only: &ReadOnlySubGit,
+    mapper: &ReadOnlyMapper,
+    excludes: &Excludes,
+    upstream_sha: Oid,
+    subgit_sha: Oid,
+) -> Result<Vec<Mismatch>, failure::Error> {
+    let (upstream_commit, subgit_commit) = match (
+        read_only.upstream.find_commit(upstream_sha),
+        read_only.subgit.find_commit(subgit_sha),
+    ) {
+        (Ok(upstream_commit), Ok(subgit_commit)) => (upstream_commit, subgit_commit),
+        _ => {
+            return Ok(vec![Mismatch::Missing {
+                upstream: upstream_sha,
+                subgit: subgit_sha,
+            }])
+        }
+    };
+    let upstream_tree = upstream_commit.tree()?;
+    let subgit_tree = subgit_commit.tree()?;
+
+    let mut mismatches: Vec<Mismatch> = mismatched_mappings(read_only, excludes, &upstream_tree, &subgit_tree)?
+        .into_iter()
+        .map(|mapping| Mismatch::Tree {
+            upstream: upstream_sha,
+            subgit: subgit_sha,
+            mapping,
+        })
+        .collect();
+
+    // The map doesn't say which side a commit was copied from, so either direction will do
+    let empty = |repo: &Repository| repo.refname_to_id("refs/sync/empty").ok();
+    let imported = parents_match(
+        mapper,
+        (&read_only.upstream, Location::UPSTREAM, upstream_sha),
+        (&read_only.subgit, subgit_sha),
+        empty(&read_only.subgit),
+    )?;
+    let consistent = imported
+        || parents_match(
+            mapper,
+            (&read_only.subgit, Location::SUBGIT, subgit_sha),
+            (&read_only.upstream, upstream_sha),
+            empty(&read_only.upstream),
+        )?;
+    if !consistent {
+        mismatches.push(Mismatch::Parents {
+            upstream: upstream_sha,
+            subgit: subgit_sha,
+        });
+    }
+    Ok(mismatches)
+}
+
+/// Checks that each mapped pair of commits has the same content in the mapped directories, and
+/// consistent parents. With tips_only, only the commits the synced refs point to on both sides are
+/// checked, instead of every pair in the map.
+pub fn verify(subgit_location: &Path, tips_only: bool) -> Result<Report, failure::Error> {
+    let read_only = ReadOnlySubGit::open(subgit_location)?;
+    let excludes = Excludes::new(&read_only.settings.excludes())?;
+    let conn = read_only.open_map()?;
+    let mapper = ReadOnlyMapper::new(&conn);
+    let pairs: Vec<(Oid, Oid)> = if tips_only {
+        read_only
+            .ref_pairs()?
+            .into_iter()
+            .filter_map(|pair| match (pair.upstream_sha, pair.subgit_sha) {
+                (Some(upstream), Some(subgit)) => Some((upstream, subgit)),
+                _ => None,
+            })
+            .collect()
+    } else {
+        mapper.get_all_pairs()
+    };
+
+    let mut mismatches = vec![];
+    for (upstream_sha, subgit_sha) in &pairs {
+        mismatches.extend(verify_pair(&read_only, &mapper, &excludes, *upstream_sha, *subgit_sha)?);
+    }
+    Ok(Report {
+        checked: pairs.len(),
+        mismatches,
+    })
+}
@@ -34,8 +34,10 @@ pub struct TestWrapper {
     root: PathBuf,
     upstream: ExtGit,
     downstream: ExtGit,
-    upstream_sub_path: PathBuf,
-    downstream_sub_path: PathBuf,
+    /// The (upstream, subgit) directory pairs that should have the same content
+    sub_paths: Vec<(PathBuf, PathBuf)>,
+    /// The exclude patterns passed to the setup, which are left out of the comparison
+    excludes: Vec<String>,
     daemon: Option<GitDaemon>,
 }
 
@@ -129,6 +131,17 @@ pub enum GitType {
     Subgit,
 }
 
+/// The output of the process if it succeeds, and otherwise the output followed by the errors
+fn output_of(mut process: std::process::Command) -> Result<String, String> {
+    let output = process.output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if output.status.success() {
+        Ok(stdout)
+    } else {
+        Err(stdout + &String::from_utf8_lossy(&output.stderr))
+    }
+}
+
 fn get_git_version() -> Result<Version, Box<Error>> {
     let version_string = String::from_utf8(
         util::command_raw(std::env::current_dir()?, "git", vec!["--version"].iter())?.stdout,
@@ -144,14 +157,71 @@ impl TestWrapper {
         self.upstream.clone()
     }
 
+    /// The directory holding the bare repositories and their clones
+    pub fn root(&self) -> PathBuf {
+        std::fs::canonicalize(&self.root).unwrap()
+    }
+
+    pub fn bare_path(&self, git: GitType) -> PathBuf {
+        self.root().join(match git {
+            GitType::Upstream => "upstream.git",
+            GitType::Subgit => "subgit.git",
+        })
+    }
+
+    /// Runs git on one of the bare repositories directly, so that no hook runs
+    pub fn bare_git(&self, git: GitType, args: Vec<&str>) -> Result<String, String> {
+        let mut process = std::process::Command::new("git");
+        process.arg("--git-dir").arg(self.bare_path(git)).args(args);
+        output_of(process).map(|output| output.trim().to_owned())
+    }
+
+    pub fn update_ref(&self, git: GitType, args: Vec<&str>) {
+        let mut args = args;
+        args.insert(0, "update-ref");
+        self.bare_git(git, args).unwrap();
+    }
+
+    pub fn has_ref(&self, git: GitType, name: &str) -> bool {
+        self.bare_git(git, vec!["rev-parse", "--verify", "--quiet", name]).is_ok()
+    }
+
+    /// Runs the installed hook binary like git runs it in the subgit, e.g. for sync-all
+    pub fn run_hook(&self, args: Vec<&str>) -> Result<String, String> {
+        let subgit = self.bare_path(GitType::Subgit);
+        let mut process = std::process::Command::new(subgit.join("data/hook"));
+        process.args(args).env("GIT_DIR", &subgit).current_dir(&subgit);
+        output_of(process)
+    }
+
+    /// Runs one of the commands that take the subgit as their first argument (like status), from a shell
+    /// rather than a hook
+    pub fn run_command(&self, command: &str, args: Vec<&str>) -> Result<String, String> {
+        self.run_command_of(self.bare_path(GitType::Subgit).join("data/hook"), command, args)
+    }
+
+    /// Like run_command, with another binary than the installed one
+    pub fn run_command_of<B: AsRef<Path>>(&self, binary: B, command: &str, args: Vec<&str>) -> Result<String, String> {
+        let mut process = std::process::Command::new(binary.as_ref());
+        process
+            .arg(command)
+            .arg(self.bare_path(GitType::Subgit))
+            .args(args)
+            .env_remove("GIT_DIR");
+        output_of(process)
+    }
+
     pub fn do_then_verify<D: FnOnce(&ExtGit, &ExtGit) -> Result<(), Box<Error>>>(&self, doer: D) {
         let res = doer(&self.upstream, &self.downstream);
         res.unwrap();
 
-        assert_dir_content_equal(
-            &self.upstream.path.join(&self.upstream_sub_path),
-            &self.downstream.path.join(&self.downstream_sub_path),
-        );
+        for (upstream_sub_path, downstream_sub_path) in &self.sub_paths {
+            assert_dir_content_equal_excluding(
+                &self.upstream.path.join(upstream_sub_path),
+                &self.downstream.path.join(downstream_sub_path),
+                &self.excludes,
+            );
+        }
     }
 
     pub fn verify_push_changes_and_pull_in_other(
@@ -183,7 +253,7 @@ impl TestWrapper {
     fn new_instance<P: AsRef<Path>, S: FnOnce(&ExtGit) -> ()>(
         root: P,
         setup: S,
-        subgit_eq: &str,
+        mappings: &[(&str, &str)],
         extra: &[&str],
         git_daemon: Option<GitDaemon>,
     ) -> Result<TestWrapper, Box<Error>> {
@@ -216,7 +286,14 @@ impl TestWrapper {
         for v in extra {
             process.arg(*v);
         }
-        process.arg(subgit_eq);
+        let (upstream_path, subgit_path) = mappings[0];
+        process.arg(upstream_path);
+        if !subgit_path.is_empty() {
+            process.arg("-p").arg(subgit_path);
+        }
+        for (upstream_path, subgit_path) in &mappings[1..] {
+            process.arg("-M").arg(format!("{}:{}", upstream_path, subgit_path));
+        }
         process.env("RUST_BACKTRACE", "1");
 
         let res: ExitStatus = process.spawn().unwrap().wait().unwrap();
@@ -233,8 +310,15 @@ impl TestWrapper {
                 path: d.join("subgit"),
                 git_version: version.clone(),
             },
-            upstream_sub_path: PathBuf::from(subgit_eq),
-            downstream_sub_path: PathBuf::from(""),
+            sub_paths: mappings
+                .iter()
+                .map(|(upstream_path, subgit_path)| (PathBuf::from(upstream_path), PathBuf::from(subgit_path)))
+                .collect(),
+            excludes: extra
+                .windows(2)
+                .filter(|pair| pair[0] == "-x" || pair[0] == "--exclude")
+                .map(|pair| pair[1].to_owned())
+                .collect(),
             daemon: git_daemon,
         };
 
@@ -254,6 +338,21 @@ impl TestWrapper {
         gen_args: A,
         use_daemon: bool,
     ) -> Result<TestWrapper, Box<Error>> {
+        TestWrapper::new_mapped_adv(name, setup, &[(subgit_eq, "")], gen_args, use_daemon)
+    }
+
+    /// Publishes each (upstream path, subgit path) pair - the first one is passed as the main mapping
+    pub fn new_mapped_adv<
+        P: AsRef<str>,
+        S: FnOnce(&ExtGit),
+        A: FnOnce(&Path, Option<GitDaemon>) -> Vec<String>,
+    >(
+        name: P,
+        setup: S,
+        mappings: &[(&str, &str)],
+        gen_args: A,
+        use_daemon: bool,
+    ) -> Result<TestWrapper, Box<dyn Error>> {
         let root = test_dir(name.as_ref());
         let git_daemon = if use_daemon {
             Some(GitDaemon::new(&root, &name.as_ref()))
@@ -272,7 +371,7 @@ impl TestWrapper {
             //            extra_args.push(&gd.subgit);
             extra_args.push("-w");
         }
-        TestWrapper::new_instance(&root, setup, subgit_eq, &extra_args, git_daemon.clone())
+        TestWrapper::new_instance(&root, setup, mappings, &extra_args, git_daemon.clone())
     }
 
     pub fn new<P: AsRef<str>, S: FnOnce(&ExtGit) -> ()>(
@@ -282,6 +381,14 @@ impl TestWrapper {
     ) -> Result<TestWrapper, Box<Error>> {
         TestWrapper::new_adv(name, setup, subgit_eq, |r, _| vec![], true)
     }
+
+    pub fn new_mapped<P: AsRef<str>, S: FnOnce(&ExtGit)>(
+        name: P,
+        setup: S,
+        mappings: &[(&str, &str)],
+    ) -> Result<TestWrapper, Box<dyn Error>> {
+        TestWrapper::new_mapped_adv(name, setup, mappings, |_, _| vec![], true)
+    }
 }
 
 fn set_push_setting<P: AsRef<Path>>(path: P) {
@@ -349,6 +456,20 @@ impl ExtGit {
         Ok(command_output.trim().parse()?)
     }
 
+    /// Whether the origin has the ref
+    pub fn has_remote_ref(&self, name: &str) -> bool {
+        self.command_output(vec!["ls-remote", "origin"])
+            .unwrap()
+            .lines()
+            .any(|line| line.ends_with(&format!("\t{}", name)))
+    }
+
+    /// The subject of the commit, after fetching from the origin
+    pub fn subject(&self, rev: &str) -> String {
+        self.command_output(vec!["fetch", "origin"]).unwrap();
+        self.command_output(vec!["log", "-1", "--format=%s", rev]).unwrap()
+    }
+
     pub fn command_output(&self, args: Vec<&str>) -> Result<String, Box<Error>> {
         let command_res = util::command_raw(&self.path, "git", args.iter())?;
         let res_out = String::from_utf8((command_res).stdout).unwrap();
@@ -364,6 +485,8 @@ pub struct Content {
 pub enum FileAction {
     Remove(PathBuf),
     Assign(Content),
+    Rename(PathBuf, PathBuf),
+    Symlink(PathBuf, PathBuf),
 }
 
 impl FileAction {
@@ -378,6 +501,15 @@ impl FileAction {
         FileAction::Remove(path.as_ref().to_owned())
     }
 
+    pub fn rename<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> Self {
+        FileAction::Rename(from.as_ref().to_owned(), to.as_ref().to_owned())
+    }
+
+    /// Replaces whatever is at path with a symlink pointing at target (which is relative to the link)
+    pub fn symlink<P: AsRef<Path>, T: AsRef<Path>>(path: P, target: T) -> Self {
+        FileAction::Symlink(path.as_ref().to_owned(), target.as_ref().to_owned())
+    }
+
     fn apply<R: AsRef<Path>>(&self, root: R) {
         match self {
             FileAction::Remove(path) => std::fs::remove_file(&root.as_ref().join(path)).unwrap(),
@@ -386,6 +518,19 @@ impl FileAction {
                     .unwrap();
                 std::fs::write(&root.as_ref().join(&content.path), &content.content).unwrap()
             }
+            FileAction::Rename(from, to) => {
+                let to = root.as_ref().join(to);
+                std::fs::create_dir_all(to.parent().unwrap()).unwrap();
+                std::fs::rename(root.as_ref().join(from), to).unwrap()
+            }
+            FileAction::Symlink(path, target) => {
+                let link = root.as_ref().join(path);
+                if std::fs::symlink_metadata(&link).is_ok() {
+                    std::fs::remove_file(&link).unwrap();
+                }
+                std::fs::create_dir_all(link.parent().unwrap()).unwrap();
+                std::os::unix::fs::symlink(target, &link).unwrap()
+            }
         }
     }
 }
@@ -79,17 +79,19 @@ pub fn assert_contents_equal<P1: AsRef<Path>, P2: AsRef<Path>, R1: AsRef<Path>,
 }
 
 pub fn assert_dir_content_equal<D1: AsRef<Path>, D2: AsRef<Path>>(origin: D1, comp: D2) {
-    let raw = command_raw(
-        std::env::current_dir().unwrap(),
-        "diff",
-        [
-            "--exclude=.git",
-            &origin.as_ref().to_string_lossy(),
-            &comp.as_ref().to_string_lossy(),
-        ]
-        .iter(),
-    )
-    .unwrap();
+    assert_dir_content_equal_excluding(origin, comp, &[]);
+}
+
+/// Compares the directories, skipping anything matching the (file name) patterns
+pub fn assert_dir_content_equal_excluding<D1: AsRef<Path>, D2: AsRef<Path>>(origin: D1, comp: D2, excludes: &[String]) {
+    // .gitmodules lives at the root of each repo and is translated rather than copied
+    let mut args = vec!["-r".to_owned(), "--exclude=.git".to_owned(), "--exclude=.gitmodules".to_owned()];
+    for exclude in excludes {
+        args.push(format!("--exclude={}", exclude.trim_end_matches('/').rsplit('/').next().unwrap()));
+    }
+    args.push(origin.as_ref().to_string_lossy().to_string());
+    args.push(comp.as_ref().to_string_lossy().to_string());
+    let raw = command_raw(std::env::current_dir().unwrap(), "diff", args.iter()).unwrap();
     assert_eq!("", &String::from_utf8(raw.stdout).unwrap());
 }
 
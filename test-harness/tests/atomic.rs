@@ -0,0 +1,84 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+            upstream.push_adv(vec!["origin", "HEAD:refs/heads/old"]).unwrap();
+        },
+        "sub",
+        |_, _| vec!["-a".to_owned()],
+        true,
+    )
+    .unwrap()
+}
+
+/// Where the notes of the upstream working clone point
+fn working_clone_notes(test: &TestWrapper) -> String {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(test.root().join("subgit.git/data/upstream"))
+        .args(["rev-parse", "refs/notes/subgit"])
+        .output()
+        .unwrap();
+    String::from_utf8_lossy(&output.stdout).trim().to_owned()
+}
+
+#[test]
+pub fn export_all_refs_of_a_push() {
+    let test = base("atomic_export_all_refs_of_a_push");
+    assert!(test.root().join("subgit.git/hooks/pre-receive").exists());
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Subgit commit").unwrap();
+    downstream
+        .push_adv(vec!["origin", "master", "HEAD:refs/heads/feature", ":refs/heads/old"])
+        .unwrap();
+
+    assert_eq!("Subgit commit", upstream.subject("origin/master"));
+    assert_eq!("Subgit commit", upstream.subject("origin/feature"));
+    assert!(!upstream.has_remote_ref("refs/heads/old"));
+}
+
+#[test]
+pub fn reject_the_whole_push() {
+    let test = base("atomic_reject_the_whole_push");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+    test.bare_git(GitType::Upstream, vec!["config", "receive.denyDeletes", "true"]).unwrap();
+    let notes = working_clone_notes(&test);
+
+    downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Subgit commit").unwrap();
+    assert!(downstream
+        .push_adv(vec!["origin", "master", "HEAD:refs/heads/feature", ":refs/heads/old"])
+        .is_err());
+
+    assert_eq!("First Commit from Upstream", upstream.subject("origin/master"));
+    assert!(!upstream.has_remote_ref("refs/heads/feature"));
+    assert!(upstream.has_remote_ref("refs/heads/old"));
+    assert!(!downstream.has_remote_ref("refs/heads/feature"));
+    assert!(downstream.has_remote_ref("refs/heads/old"));
+    // The notes of the rolled back mappings aren't written either
+    assert_eq!(notes, working_clone_notes(&test));
+
+    // Nothing was recorded for the rejected push, so the same commits go through once allowed
+    test.bare_git(GitType::Upstream, vec!["config", "receive.denyDeletes", "false"]).unwrap();
+    downstream
+        .push_adv(vec!["origin", "master", "HEAD:refs/heads/feature", ":refs/heads/old"])
+        .unwrap();
+    assert_eq!("Subgit commit", upstream.subject("origin/feature"));
+    assert!(!upstream.has_remote_ref("refs/heads/old"));
+}
@@ -0,0 +1,77 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "To be rewritten")]);
+            upstream.add(".").unwrap();
+            upstream.commit("Second Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+fn rewrite_upstream(test: &TestWrapper) {
+    let upstream = test.get_upstream();
+    upstream.command_output(vec!["reset", "--hard", "HEAD^"]).unwrap();
+    upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Rewritten")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Rewritten Commit from Upstream").unwrap();
+    upstream.push_adv(vec!["--force", "origin", "master"]).unwrap();
+
+    std::thread::sleep(Duration::new(2, 0));
+}
+
+#[test]
+pub fn back_up_dropped_subgit_history() {
+    let test = base("backups_back_up_dropped_subgit_history");
+    let downstream = test.get_subgit();
+    let old_tip = downstream.command_output(vec!["rev-parse", "origin/master"]).unwrap();
+    assert_eq!("", test.run_hook(vec!["list-backups"]).unwrap());
+
+    rewrite_upstream(&test);
+
+    assert_eq!("Rewritten Commit from Upstream", downstream.subject("origin/master"));
+    let listed = test.run_hook(vec!["list-backups"]).unwrap();
+    let lines: Vec<&str> = listed.lines().collect();
+    assert_eq!(1, lines.len());
+    assert!(lines[0].starts_with(&format!("{} refs/subgit-backup/heads/master/", old_tip)));
+    assert!(lines[0].contains(&format!("Z-{}", old_tip)), "{}", lines[0]);
+
+    // Fast-forwards don't need a backup
+    let upstream = test.get_upstream();
+    upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Moving on")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Third Commit from Upstream").unwrap();
+    upstream.push().unwrap();
+    std::thread::sleep(Duration::new(2, 0));
+    assert_eq!("Third Commit from Upstream", downstream.subject("origin/master"));
+    assert_eq!(1, test.run_hook(vec!["list-backups"]).unwrap().lines().count());
+}
+
+#[test]
+pub fn restore_backup() {
+    let test = base("backups_restore_backup");
+    rewrite_upstream(&test);
+    let listed = test.run_hook(vec!["list-backups"]).unwrap();
+    let backup = listed.trim().split(' ').nth(1).unwrap().to_owned();
+
+    assert!(test.run_hook(vec!["restore-backup", &backup]).unwrap_err().contains("already exists"));
+    assert!(test.run_hook(vec!["restore-backup", "refs/subgit-backup/heads/nothing/1"]).is_err());
+
+    let output = test.run_hook(vec!["restore-backup", &backup, "refs/heads/rescued"]).unwrap();
+    assert_eq!(format!("Restored {} as refs/heads/rescued", backup), output.lines().last().unwrap());
+    assert_eq!("Second Commit from Upstream", test.get_subgit().subject("origin/rescued"));
+    assert_eq!("Second Commit from Upstream", test.get_upstream().subject("origin/rescued"));
+}
@@ -0,0 +1,144 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![
+                FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)"),
+                FileAction::overwrite("root.txt", "Outside of the subgit"),
+            ]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+/// Commits the changes on a new upstream branch off master and on master, and pushes both
+fn diverge_upstream(test: &TestWrapper, feature: Vec<FileAction>, master: Vec<FileAction>) {
+    let upstream = test.get_upstream();
+    upstream.checkout_adv(vec!["-b", "feature"]).unwrap();
+    upstream.commit_changes(feature, "Feature commit");
+    upstream.push_adv(vec!["origin", "feature"]).unwrap();
+    upstream.checkout("master").unwrap();
+    upstream.commit_and_push_changes(master, "Master commit");
+    std::thread::sleep(Duration::new(2, 0));
+}
+
+/// Pushes from the working clone, with what git reports
+fn push(git: &ExtGit) -> Result<String, String> {
+    let output = std::process::Command::new("git")
+        .arg("push")
+        .current_dir(git.path())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    if output.status.success() {
+        Ok(stderr)
+    } else {
+        Err(stderr)
+    }
+}
+
+#[test]
+pub fn refuse_merge_conflicting_outside_mapped_dirs() {
+    let test = base("branches_refuse_merge_conflicting_outside_mapped_dirs");
+    diverge_upstream(
+        &test,
+        vec![
+            FileAction::overwrite("root.txt", "Changed on the feature branch"),
+            FileAction::overwrite("sub/feature.txt", "From the feature branch"),
+        ],
+        vec![
+            FileAction::overwrite("root.txt", "Changed on master"),
+            FileAction::overwrite("sub/master.txt", "From master"),
+        ],
+    );
+    let upstream = test.get_upstream();
+    let master = upstream.command_output(vec!["rev-parse", "master"]).unwrap();
+    let feature = upstream.command_output(vec!["rev-parse", "feature"]).unwrap();
+
+    // The subgit can't see the conflict, so it merges cleanly there
+    let downstream = test.get_subgit();
+    downstream.pull().unwrap();
+    downstream.merge(vec!["origin/feature", "-m", "Merge feature"]).unwrap();
+    let error = push(&downstream).unwrap_err();
+    assert!(error.contains("outside of the mapped directories"), "{}", error);
+    assert!(error.contains(&master) && error.contains(&feature), "{}", error);
+
+    assert_eq!("Master commit", upstream.subject("origin/master"));
+}
+
+#[test]
+pub fn merge_conflicting_inside_mapped_dirs() {
+    let test = base("branches_merge_conflicting_inside_mapped_dirs");
+    diverge_upstream(
+        &test,
+        vec![
+            FileAction::overwrite("sub/hello.txt", "Hello from the feature branch"),
+            FileAction::overwrite("feature.txt", "Outside, from the feature branch"),
+        ],
+        vec![
+            FileAction::overwrite("sub/hello.txt", "Hello from master"),
+            FileAction::overwrite("root.txt", "Changed on master"),
+        ],
+    );
+
+    let downstream = test.get_subgit();
+    downstream.pull().unwrap();
+    assert!(downstream.merge(vec!["origin/feature", "-m", "Merge feature"]).is_err());
+    downstream.commit_changes(vec![FileAction::overwrite("hello.txt", "Hello from both")], "Merge feature");
+    push(&downstream).unwrap();
+
+    test.do_then_verify(|upstream, _| {
+        upstream.pull()?;
+        assert_eq!("Merge feature", upstream.subject("origin/master"));
+        let read = |path: &str| std::fs::read_to_string(upstream.path().join(path)).unwrap();
+        assert_eq!("Hello from both", read("sub/hello.txt"));
+        assert_eq!("Changed on master", read("root.txt"));
+        assert_eq!("Outside, from the feature branch", read("feature.txt"));
+        Ok(())
+    });
+}
+
+#[test]
+pub fn sync_all_branches_sharing_a_subgit_commit() {
+    let test = base("branches_sync_all_branches_sharing_a_subgit_commit");
+    // The feature commit collapses into the first subgit commit, which then stands for an upstream commit of
+    // each branch
+    let upstream = test.get_upstream();
+    upstream.checkout_adv(vec!["-b", "feature"]).unwrap();
+    upstream.commit_changes(vec![FileAction::overwrite("root.txt", "Changed on the feature branch")], "Feature commit");
+    upstream.push_adv(vec!["-u", "origin", "feature"]).unwrap();
+    std::thread::sleep(Duration::new(2, 0));
+    let downstream = test.get_subgit();
+    assert_eq!("First Commit from Upstream", downstream.subject("origin/feature"));
+
+    // Both branches move on while the upstream hook is missing, and sync-all catches the subgit up
+    let hook = test.bare_path(GitType::Upstream).join("hooks/post-receive");
+    let target = std::fs::read_link(&hook).unwrap();
+    std::fs::remove_file(&hook).unwrap();
+    upstream.commit_and_push_changes(vec![FileAction::overwrite("sub/feature.txt", "From the feature branch")], "Second feature commit");
+    upstream.checkout("master").unwrap();
+    upstream.commit_and_push_changes(vec![FileAction::overwrite("sub/master.txt", "From master")], "Master commit");
+    std::os::unix::fs::symlink(target, &hook).unwrap();
+
+    test.run_hook(vec!["sync-all"]).unwrap();
+    assert_eq!("Master commit", downstream.subject("origin/master"));
+    assert_eq!("Second feature commit", downstream.subject("origin/feature"));
+    assert!(test.bare_git(GitType::Subgit, vec!["cat-file", "-e", "master:master.txt"]).is_ok());
+    assert!(test.bare_git(GitType::Subgit, vec!["cat-file", "-e", "master:feature.txt"]).is_err());
+    assert!(test.bare_git(GitType::Subgit, vec!["cat-file", "-e", "feature:master.txt"]).is_err());
+    assert_eq!(
+        test.bare_git(GitType::Subgit, vec!["rev-parse", "master~1"]),
+        test.bare_git(GitType::Subgit, vec!["rev-parse", "feature~1"])
+    );
+}
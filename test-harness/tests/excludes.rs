@@ -0,0 +1,106 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![
+                FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)"),
+                FileAction::overwrite("sub/internal/notes.txt", "Not for the subgit"),
+                FileAction::overwrite("sub/config/db.secret.yaml", "password: hunter2"),
+                FileAction::overwrite("sub/config/db.yaml", "host: localhost"),
+            ]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+        |_, _| vec!["-x".to_owned(), "internal/".to_owned(), "-x".to_owned(), "*.secret.yaml".to_owned()],
+        true,
+    )
+    .unwrap()
+}
+
+fn tracked_files(git: &ExtGit) -> String {
+    git.command_output(vec!["ls-tree", "-r", "--name-only", "HEAD"]).unwrap()
+}
+
+#[test]
+pub fn import_leaves_out_excluded() {
+    let test = base("excludes_import_leaves_out_excluded");
+
+    assert_eq!("config/db.yaml\nhello.txt", tracked_files(&test.get_subgit()));
+}
+
+#[test]
+pub fn upstream_changes_to_excluded_stay_upstream() {
+    let test = base("excludes_upstream_changes_to_excluded_stay_upstream");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    upstream.update_working(vec![
+        FileAction::overwrite("sub/internal/more.txt", "Still not for the subgit"),
+        FileAction::overwrite("sub/config/db.secret.yaml", "password: correct horse"),
+        FileAction::overwrite("sub/hello.txt", "Hello again"),
+    ]);
+    upstream.add(".").unwrap();
+    upstream.commit("Change excluded files").unwrap();
+    upstream.push().unwrap();
+
+    std::thread::sleep(Duration::new(2, 0));
+
+    downstream.pull().unwrap();
+    assert_eq!("config/db.yaml\nhello.txt", tracked_files(&downstream));
+    assert_eq!("Hello again", downstream.command_output(vec!["show", "HEAD:hello.txt"]).unwrap());
+}
+
+#[test]
+pub fn subgit_push_keeps_upstream_excluded() {
+    let test = base("excludes_subgit_push_keeps_upstream_excluded");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    downstream.update_working(vec![
+        FileAction::overwrite("config/db.yaml", "host: db.example.com"),
+        FileAction::remove("hello.txt"),
+    ]);
+    downstream.add("-A").unwrap();
+    downstream.commit("Change from the subgit").unwrap();
+    downstream.push().unwrap();
+
+    upstream.pull().unwrap();
+    assert_eq!(
+        "sub/config/db.secret.yaml\nsub/config/db.yaml\nsub/internal/notes.txt",
+        tracked_files(&upstream)
+    );
+    assert_eq!(
+        "host: db.example.com",
+        upstream.command_output(vec!["show", "HEAD:sub/config/db.yaml"]).unwrap()
+    );
+}
+
+#[test]
+pub fn subgit_push_adding_excluded_is_rejected() {
+    let test = base("excludes_subgit_push_adding_excluded_is_rejected");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+    let upstream_head = upstream.command_output(vec!["ls-remote", "origin", "master"]).unwrap();
+
+    downstream.update_working(vec![FileAction::overwrite("config/prod.secret.yaml", "password: oops")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Leak a secret").unwrap();
+    assert!(downstream.push().is_err());
+
+    // Dropping it again doesn't help - the commit adding it is still part of the push
+    downstream.update_working(vec![FileAction::remove("config/prod.secret.yaml")]);
+    downstream.add("-A").unwrap();
+    downstream.commit("Remove the secret").unwrap();
+    assert!(downstream.push().is_err());
+
+    assert_eq!(upstream_head, upstream.command_output(vec!["ls-remote", "origin", "master"]).unwrap());
+}
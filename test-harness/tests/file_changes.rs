@@ -0,0 +1,179 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![
+                FileAction::overwrite(
+                    "sub/hello.txt",
+                    "Hello world (from upstream)",
+                ),
+                FileAction::overwrite(
+                    "root.txt",
+                    "Hello world (from upstream, outside of sub)",
+                ),
+            ]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+fn push_from_upstream(upstream: &ExtGit, downstream: &ExtGit, changes: Vec<FileAction>, message: &str) {
+    upstream.commit_and_push_changes(changes, message);
+
+    std::thread::sleep(Duration::new(2, 0));
+
+    downstream.pull().unwrap();
+}
+
+fn push_from_subgit(upstream: &ExtGit, downstream: &ExtGit, changes: Vec<FileAction>, message: &str) {
+    downstream.commit_and_push_changes(changes, message);
+
+    upstream.pull().unwrap();
+}
+
+#[test]
+pub fn rename_inside_from_upstream() {
+    let test = base("file_changes_rename_inside_from_upstream");
+
+    test.do_then_verify(|upstream, downstream| {
+        push_from_upstream(
+            upstream,
+            downstream,
+            vec![FileAction::rename("sub/hello.txt", "sub/nested/renamed.txt")],
+            "Rename inside of sub",
+        );
+        Ok(())
+    });
+}
+
+#[test]
+pub fn rename_into_sub_from_upstream() {
+    let test = base("file_changes_rename_into_sub_from_upstream");
+
+    test.do_then_verify(|upstream, downstream| {
+        push_from_upstream(
+            upstream,
+            downstream,
+            vec![FileAction::rename("root.txt", "sub/root.txt")],
+            "Rename into sub",
+        );
+        Ok(())
+    });
+}
+
+#[test]
+pub fn rename_out_of_sub_from_upstream() {
+    let test = base("file_changes_rename_out_of_sub_from_upstream");
+
+    test.do_then_verify(|upstream, downstream| {
+        push_from_upstream(
+            upstream,
+            downstream,
+            vec![FileAction::rename("sub/hello.txt", "hello.txt")],
+            "Rename out of sub",
+        );
+        Ok(())
+    });
+}
+
+#[test]
+pub fn copy_into_sub_from_upstream() {
+    let test = base("file_changes_copy_into_sub_from_upstream");
+
+    test.do_then_verify(|upstream, downstream| {
+        push_from_upstream(
+            upstream,
+            downstream,
+            vec![
+                FileAction::overwrite("sub/hello_copy.txt", "Hello world (from upstream)"),
+                FileAction::overwrite("sub/root_copy.txt", "Hello world (from upstream, outside of sub)"),
+            ],
+            "Copy files into sub",
+        );
+        Ok(())
+    });
+}
+
+#[test]
+pub fn typechange_from_upstream() {
+    let test = base("file_changes_typechange_from_upstream");
+
+    test.do_then_verify(|upstream, downstream| {
+        push_from_upstream(
+            upstream,
+            downstream,
+            vec![
+                FileAction::overwrite("sub/target.txt", "Link target"),
+                FileAction::symlink("sub/hello.txt", "target.txt"),
+            ],
+            "Turn a file into a symlink",
+        );
+        assert!(downstream
+            .command_output(vec!["ls-tree", "HEAD", "hello.txt"])
+            .unwrap()
+            .starts_with("120000"));
+        Ok(())
+    });
+
+    test.do_then_verify(|upstream, downstream| {
+        push_from_upstream(
+            upstream,
+            downstream,
+            vec![FileAction::overwrite("sub/hello.txt", "Back to a regular file")],
+            "Turn a symlink back into a file",
+        );
+        Ok(())
+    });
+}
+
+#[test]
+pub fn rename_from_subgit() {
+    let test = base("file_changes_rename_from_subgit");
+
+    test.do_then_verify(|upstream, downstream| {
+        push_from_subgit(
+            upstream,
+            downstream,
+            vec![FileAction::rename("hello.txt", "nested/renamed.txt")],
+            "Rename from subgit",
+        );
+        assert_eq!(
+            "Hello world (from upstream, outside of sub)",
+            std::fs::read_to_string(upstream.path().join("root.txt")).unwrap()
+        );
+        Ok(())
+    });
+}
+
+#[test]
+pub fn typechange_from_subgit() {
+    let test = base("file_changes_typechange_from_subgit");
+
+    test.do_then_verify(|upstream, downstream| {
+        push_from_subgit(
+            upstream,
+            downstream,
+            vec![
+                FileAction::overwrite("target.txt", "Link target"),
+                FileAction::symlink("hello.txt", "target.txt"),
+            ],
+            "Turn a file into a symlink from subgit",
+        );
+        assert!(upstream
+            .command_output(vec!["ls-tree", "HEAD", "sub/hello.txt"])
+            .unwrap()
+            .starts_with("120000"));
+        Ok(())
+    });
+}
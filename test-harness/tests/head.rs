@@ -0,0 +1,78 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str, args: Vec<&'static str>) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+        move |_, _| args.iter().map(|arg| arg.to_string()).collect(),
+        true,
+    )
+    .unwrap()
+}
+
+/// Renames the upstream default branch, without anything being pushed
+fn rename_upstream_default(test: &TestWrapper, new_branch: &str) {
+    test.bare_git(GitType::Upstream, vec!["branch", "-m", "master", new_branch]).unwrap();
+    assert_eq!(
+        format!("refs/heads/{}", new_branch),
+        test.bare_git(GitType::Upstream, vec!["symbolic-ref", "HEAD"]).unwrap()
+    );
+}
+
+fn subgit_heads(test: &TestWrapper) -> (String, String) {
+    let local = test.bare_path(GitType::Subgit).join("data/local.git");
+    (
+        test.bare_git(GitType::Subgit, vec!["symbolic-ref", "HEAD"]).unwrap(),
+        std::fs::read_to_string(local.join("HEAD")).unwrap().trim().trim_start_matches("ref: ").to_owned(),
+    )
+}
+
+#[test]
+pub fn follow_upstream_head_on_push() {
+    let test = base("head_follow_upstream_head_on_push", vec![]);
+    let upstream = test.get_upstream();
+    rename_upstream_default(&test, "main");
+
+    upstream.command_output(vec!["fetch", "origin"]).unwrap();
+    upstream.command_output(vec!["checkout", "-b", "main", "origin/main"]).unwrap();
+    upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello main")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Commit on main").unwrap();
+    upstream.push_adv(vec!["origin", "main"]).unwrap();
+
+    std::thread::sleep(Duration::new(2, 0));
+
+    assert_eq!(
+        ("refs/heads/main".to_owned(), "refs/heads/main".to_owned()),
+        subgit_heads(&test)
+    );
+    let remote_head = test.get_subgit().command_output(vec!["ls-remote", "--symref", "origin", "HEAD"]).unwrap();
+    assert!(remote_head.starts_with("ref: refs/heads/main\tHEAD"));
+}
+
+#[test]
+pub fn follow_renamed_upstream_head_on_sync_all() {
+    let test = base(
+        "head_follow_renamed_upstream_head_on_sync_all",
+        vec!["-R", "refs/heads/trunk:refs/heads/main"],
+    );
+    rename_upstream_default(&test, "trunk");
+
+    test.run_hook(vec!["sync-all"]).unwrap();
+
+    assert_eq!(
+        ("refs/heads/main".to_owned(), "refs/heads/main".to_owned()),
+        subgit_heads(&test)
+    );
+}
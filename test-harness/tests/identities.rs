@@ -0,0 +1,84 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+
+fn base(name: &str, sync_committer: bool) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream
+                .command_output(vec!["commit", "--author=Jane Internal <jane@corp.example>", "-m", "First Commit"])
+                .unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+        |root, _| {
+            let import = root.join("import.mailmap");
+            let export = root.join("export.mailmap");
+            std::fs::write(&import, "Jane Doe <jane@example.com> <jane@corp.example>\n").unwrap();
+            std::fs::write(&export, "Contributor <contributor@corp.example> <joe@example.com>\n").unwrap();
+            let mut args = vec![
+                "-i".to_owned(),
+                import.to_string_lossy().to_string(),
+                "-e".to_owned(),
+                export.to_string_lossy().to_string(),
+            ];
+            if sync_committer {
+                args.push("-c".to_owned());
+                args.push("Sync Bot <sync@example.com>".to_owned());
+            }
+            args
+        },
+        true,
+    )
+    .unwrap()
+}
+
+fn identities(git: &ExtGit) -> String {
+    git.command_output(vec!["log", "-1", "--format=%an <%ae> / %cn <%ce>"]).unwrap()
+}
+
+#[test]
+pub fn mailmap_both_ways() {
+    let test = base("identities_mailmap_both_ways", false);
+
+    test.do_then_verify(|upstream, downstream| {
+        assert_eq!("Jane Doe <jane@example.com> / test user <test@example.com>", identities(downstream));
+
+        downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+        downstream.add(".").unwrap();
+        downstream
+            .command_output(vec!["commit", "--author=Joe <joe@example.com>", "-m", "Subgit commit"])
+            .unwrap();
+        downstream.push().unwrap();
+
+        upstream.pull().unwrap();
+        assert_eq!(
+            "Contributor <contributor@corp.example> / test user <test@example.com>",
+            identities(upstream)
+        );
+        Ok(())
+    });
+}
+
+#[test]
+pub fn sync_committer() {
+    let test = base("identities_sync_committer", true);
+
+    test.do_then_verify(|upstream, downstream| {
+        assert_eq!("Jane Doe <jane@example.com> / Sync Bot <sync@example.com>", identities(downstream));
+
+        downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+        downstream.add(".").unwrap();
+        downstream.commit("Subgit commit").unwrap();
+        downstream.push().unwrap();
+
+        upstream.pull().unwrap();
+        assert_eq!("test user <test@example.com> / Sync Bot <sync@example.com>", identities(upstream));
+        Ok(())
+    });
+}
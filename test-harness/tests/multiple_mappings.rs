@@ -0,0 +1,81 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new_mapped(
+        name,
+        |upstream| {
+            upstream.update_working(vec![
+                FileAction::overwrite("libs/core/lib.rs", "pub fn core() {}"),
+                FileAction::overwrite("docs/public/index.md", "# Public docs"),
+                FileAction::overwrite("docs/internal/index.md", "# Internal docs"),
+                FileAction::overwrite("root.txt", "Hello world (from upstream)"),
+            ]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        &[("libs/core", "core"), ("docs/public", "docs")],
+    )
+    .unwrap()
+}
+
+#[test]
+pub fn import_multiple_mappings() {
+    let test = base("multiple_mappings_import");
+
+    let downstream = test.get_subgit();
+    assert!(!downstream.path().join("root.txt").exists());
+    assert!(!downstream.path().join("internal").exists());
+    assert!(!downstream.path().join("docs/internal").exists());
+}
+
+#[test]
+pub fn push_multiple_mappings_from_upstream() {
+    let test = base("multiple_mappings_push_from_upstream");
+
+    test.do_then_verify(|upstream, downstream| {
+        upstream.commit_and_push_changes(
+            vec![
+                FileAction::overwrite("libs/core/lib.rs", "pub fn core() { /* changed */ }"),
+                FileAction::overwrite("docs/public/guide.md", "# Guide"),
+                FileAction::overwrite("docs/internal/secret.md", "# Secret"),
+            ],
+            "Change both mapped directories",
+        );
+
+        std::thread::sleep(Duration::new(2, 0));
+
+        downstream.pull().unwrap();
+        Ok(())
+    });
+}
+
+#[test]
+pub fn push_multiple_mappings_from_subgit() {
+    let test = base("multiple_mappings_push_from_subgit");
+
+    test.do_then_verify(|upstream, downstream| {
+        downstream.commit_and_push_changes(
+            vec![
+                FileAction::overwrite("core/extra.rs", "pub fn extra() {}"),
+                FileAction::overwrite("docs/guide.md", "# Guide from subgit"),
+                FileAction::remove("docs/index.md"),
+            ],
+            "Change both mapped directories from the subgit",
+        );
+
+        upstream.pull().unwrap();
+
+        assert!(upstream.path().join("libs/core/extra.rs").exists());
+        assert!(upstream.path().join("docs/public/guide.md").exists());
+        assert!(!upstream.path().join("docs/public/index.md").exists());
+        assert!(upstream.path().join("docs/internal/index.md").exists());
+        assert!(upstream.path().join("root.txt").exists());
+        Ok(())
+    });
+}
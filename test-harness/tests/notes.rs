@@ -0,0 +1,104 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+fn rev(git: &ExtGit, rev: &str) -> String {
+    git.command_output(vec!["rev-parse", rev]).unwrap()
+}
+
+fn note(git: &ExtGit, rev: &str) -> String {
+    git.command_output(vec!["fetch", "origin", "+refs/notes/subgit:refs/notes/subgit"]).unwrap();
+    git.command_output(vec!["log", "-1", "--notes=subgit", "--format=%N", rev]).unwrap()
+}
+
+#[test]
+pub fn import_writes_notes() {
+    let test = base("notes_import_writes_notes");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    assert_eq!(format!("Upstream-Commit: {}", rev(&upstream, "HEAD")), note(&downstream, "HEAD"));
+    assert_eq!(format!("Subgit-Commit: {}", rev(&downstream, "HEAD")), note(&upstream, "HEAD"));
+}
+
+#[test]
+pub fn notes_follow_both_directions() {
+    let test = base("notes_follow_both_directions");
+
+    test.do_then_verify(|upstream, downstream| {
+        downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+        downstream.add(".").unwrap();
+        downstream.commit("Subgit commit").unwrap();
+        downstream.push().unwrap();
+
+        upstream.pull().unwrap();
+        assert_eq!(format!("Subgit-Commit: {}", rev(downstream, "HEAD")), note(upstream, "HEAD"));
+        assert_eq!(format!("Upstream-Commit: {}", rev(upstream, "HEAD")), note(downstream, "HEAD"));
+
+        upstream.update_working(vec![FileAction::overwrite("sub/test.txt", "hello from upstream")]);
+        upstream.add(".").unwrap();
+        upstream.commit("Upstream commit").unwrap();
+        upstream.push().unwrap();
+
+        std::thread::sleep(Duration::new(2, 0));
+
+        downstream.pull().unwrap();
+        assert_eq!(format!("Upstream-Commit: {}", rev(upstream, "HEAD")), note(downstream, "HEAD"));
+        assert_eq!(format!("Subgit-Commit: {}", rev(downstream, "HEAD")), note(upstream, "HEAD"));
+        Ok(())
+    });
+}
+
+fn notes_tip(path: std::path::PathBuf) -> String {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(["rev-parse", "refs/notes/subgit"])
+        .output()
+        .unwrap();
+    String::from_utf8_lossy(&output.stdout).trim().to_owned()
+}
+
+#[test]
+pub fn rejected_push_leaves_notes_alone() {
+    let test = base("notes_rejected_push_leaves_notes_alone");
+    let root = test.root();
+    let working_clone = root.join("subgit.git/data/upstream");
+    let upstream_notes = notes_tip(test.bare_path(GitType::Upstream));
+    let working_notes = notes_tip(working_clone.clone());
+
+    let reject = test.bare_path(GitType::Upstream).join("hooks/pre-receive");
+    std::fs::write(&reject, "#!/bin/sh\nexit 1\n").unwrap();
+    std::process::Command::new("chmod").arg("+x").arg(&reject).status().unwrap();
+
+    let downstream = test.get_subgit();
+    downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Subgit commit").unwrap();
+    assert!(downstream.push().is_err());
+    assert_eq!(upstream_notes, notes_tip(test.bare_path(GitType::Upstream)));
+    assert_eq!(working_notes, notes_tip(working_clone));
+
+    std::fs::remove_file(&reject).unwrap();
+    downstream.push().unwrap();
+    let upstream = test.get_upstream();
+    upstream.pull().unwrap();
+    assert_eq!(format!("Subgit-Commit: {}", rev(&downstream, "HEAD")), note(&upstream, "HEAD"));
+}
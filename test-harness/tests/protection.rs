@@ -0,0 +1,78 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+        |_, _| {
+            vec![
+                "-P".to_owned(),
+                "deny-delete,deny-non-ff:refs/heads/master".to_owned(),
+                "-P".to_owned(),
+                "deny-delete:refs/heads/stable".to_owned(),
+                "-P".to_owned(),
+                "deny-create:refs/heads/release/*".to_owned(),
+            ]
+        },
+        true,
+    )
+    .unwrap()
+}
+
+fn remote_ref(git: &ExtGit, name: &str) -> String {
+    git.command_output(vec!["ls-remote", "origin", name]).unwrap()
+}
+
+#[test]
+pub fn deny_non_fast_forward_and_delete() {
+    let test = base("protection_deny_non_fast_forward_and_delete");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+    downstream.push_adv(vec!["origin", "HEAD:refs/heads/stable"]).unwrap();
+    let upstream_master = remote_ref(&upstream, "refs/heads/master");
+    let subgit_master = remote_ref(&downstream, "refs/heads/master");
+
+    downstream.update_working(vec![FileAction::overwrite("hello.txt", "Rewritten in the subgit")]);
+    downstream.add(".").unwrap();
+    downstream.command_output(vec!["commit", "--amend", "-m", "Rewritten"]).unwrap();
+    assert!(downstream.push_adv(vec!["--force", "origin", "master"]).is_err());
+    assert!(downstream.push_adv(vec!["origin", ":refs/heads/stable"]).is_err());
+
+    assert_eq!(subgit_master, remote_ref(&downstream, "refs/heads/master"));
+    assert_eq!(upstream_master, remote_ref(&upstream, "refs/heads/master"));
+    assert!(remote_ref(&downstream, "refs/heads/stable").contains("stable"));
+    assert!(remote_ref(&upstream, "refs/heads/stable").contains("stable"));
+}
+
+#[test]
+pub fn allow_fast_forward_and_unprotected_refs() {
+    let test = base("protection_allow_fast_forward_and_unprotected_refs");
+
+    test.do_then_verify(|upstream, downstream| {
+        downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+        downstream.add(".").unwrap();
+        downstream.commit("Subgit commit").unwrap();
+        downstream.push().unwrap();
+
+        assert!(downstream.push_adv(vec!["origin", "HEAD:refs/heads/release/1.0"]).is_err());
+        assert_eq!("", remote_ref(upstream, "refs/heads/release/1.0"));
+
+        downstream.push_adv(vec!["origin", "HEAD:refs/heads/feature"]).unwrap();
+        assert!(remote_ref(upstream, "refs/heads/feature").contains("feature"));
+        downstream.push_adv(vec!["origin", ":refs/heads/feature"]).unwrap();
+        assert_eq!("", remote_ref(upstream, "refs/heads/feature"));
+
+        upstream.pull().unwrap();
+        Ok(())
+    });
+}
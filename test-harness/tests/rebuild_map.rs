@@ -0,0 +1,114 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![
+                FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)"),
+                FileAction::overwrite("sub/internal/notes.txt", "Not for the subgit"),
+                FileAction::overwrite("root.txt", "Outside of the subgit"),
+            ]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.update_working(vec![FileAction::overwrite("root.txt", "Still outside of the subgit")]);
+            upstream.add(".").unwrap();
+            upstream.commit("Second Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+        |_, _| vec!["-x".to_owned(), "internal/".to_owned()],
+        true,
+    )
+    .unwrap()
+}
+
+fn build_history(test: &TestWrapper) {
+    let downstream = test.get_subgit();
+    downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Subgit commit").unwrap();
+    downstream.push().unwrap();
+
+    let upstream = test.get_upstream();
+    upstream.command_output(vec!["pull"]).unwrap();
+    upstream.update_working(vec![FileAction::overwrite("root.txt", "Outside again")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Unrelated change from Upstream").unwrap();
+    upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello again")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Third Commit from Upstream").unwrap();
+    upstream.push().unwrap();
+
+    std::thread::sleep(Duration::new(2, 0));
+}
+
+#[test]
+pub fn rebuild_lost_map() {
+    let test = base("rebuild_map_rebuild_lost_map");
+    build_history(&test);
+    let verified = test.run_command("verify", vec![]).unwrap();
+
+    // Nothing to go on but the commits themselves
+    let data = test.root().join("subgit.git/data");
+    test.update_ref(GitType::Upstream, vec!["-d", "refs/notes/subgit"]);
+    test.update_ref(GitType::Subgit, vec!["-d", "refs/notes/subgit"]);
+    std::fs::remove_file(data.join("map.sqlite")).unwrap();
+
+    let output = test.run_command("rebuild-map", vec![]).unwrap();
+    assert!(output.ends_with(", 0 commits left to resolve by hand\n"), "{}", output);
+    std::fs::rename(data.join("map.rebuilt.sqlite"), data.join("map.sqlite")).unwrap();
+
+    assert_eq!(verified, test.run_command("verify", vec![]).unwrap());
+
+    // And the sync picks up where it left off
+    let upstream = test.get_upstream();
+    upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "After the rebuild")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Fourth Commit from Upstream").unwrap();
+    upstream.push().unwrap();
+    std::thread::sleep(Duration::new(2, 0));
+
+    let downstream = test.get_subgit();
+    assert_eq!("Fourth Commit from Upstream", downstream.subject("origin/master"));
+    assert_eq!("Subgit commit", downstream.subject("origin/master~2"));
+}
+
+#[test]
+pub fn keep_existing_output() {
+    let test = base("rebuild_map_keep_existing_output");
+    let data = test.root().join("subgit.git/data");
+
+    let output = test
+        .run_command("rebuild-map", vec!["-o", data.join("map.sqlite").to_str().unwrap()])
+        .unwrap_err();
+    assert!(output.contains("already exists"), "{}", output);
+}
+
+#[test]
+pub fn newest_counterpart_is_last_in_history() {
+    let test = base("rebuild_map_newest_counterpart_is_last_in_history");
+    build_history(&test);
+
+    let data = test.root().join("subgit.git/data");
+    std::fs::remove_file(data.join("map.sqlite")).unwrap();
+    test.run_command("rebuild-map", vec![]).unwrap();
+    std::fs::rename(data.join("map.rebuilt.sqlite"), data.join("map.sqlite")).unwrap();
+
+    // The first subgit commit stands for both upstream commits, and a new branch off it builds on the second
+    let downstream = test.get_subgit();
+    downstream.command_output(vec!["fetch", "origin"]).unwrap();
+    downstream.checkout_adv(vec!["-b", "topic", "origin/master~2"]).unwrap();
+    downstream.commit_changes(vec![FileAction::overwrite("topic.txt", "From the topic branch")], "Topic commit");
+    downstream.push_adv(vec!["origin", "topic"]).unwrap();
+
+    let upstream = test.get_upstream();
+    upstream.command_output(vec!["fetch", "origin"]).unwrap();
+    assert_eq!("Topic commit", upstream.subject("origin/topic"));
+    assert_eq!("Second Commit from Upstream", upstream.subject("origin/topic~1"));
+}
@@ -227,3 +227,54 @@ pub fn downstream_push_good_ref_but_not_bad_refs() {
         ""
     );
 }
+
+fn show_ref(git: &ExtGit, name: &str) -> String {
+    git.command_output(vec!["ls-remote", "origin", name]).unwrap()
+}
+
+#[test]
+pub fn glob_and_negated_filters() {
+    let test = TestWrapper::new_adv(
+        "glob_and_negated_filters",
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite(
+                "sub/hello.txt",
+                "Hello world (from upstream)",
+            )]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+            upstream.push_adv(vec!["origin", "HEAD:refs/heads/release/1.0"]).unwrap();
+            upstream.push_adv(vec!["origin", "HEAD:refs/heads/release/internal-1.0"]).unwrap();
+        },
+        "sub",
+        |_, _| {
+            vec![
+                "-m".to_owned(),
+                "refs/heads/master,refs/heads/release/*,!refs/heads/release/internal-*".to_owned(),
+            ]
+        },
+        true,
+    )
+    .unwrap();
+
+    test.do_then_verify(|upstream, downstream| {
+        assert!(show_ref(downstream, "refs/heads/release/1.0").contains("release/1.0"));
+        assert_eq!("", show_ref(downstream, "refs/heads/release/internal-1.0"));
+
+        upstream.push_adv(vec!["origin", "HEAD:refs/heads/release/2.0"]).unwrap();
+        upstream.push_adv(vec!["origin", "HEAD:refs/heads/release/internal-2.0"]).unwrap();
+
+        std::thread::sleep(Duration::new(2, 0));
+
+        assert!(show_ref(downstream, "refs/heads/release/2.0").contains("release/2.0"));
+        assert_eq!("", show_ref(downstream, "refs/heads/release/internal-2.0"));
+
+        downstream.push_adv(vec!["origin", "HEAD:refs/heads/release/3.0"]).unwrap();
+        downstream.push_adv(vec!["origin", "HEAD:refs/heads/release/internal-3.0"]).unwrap();
+
+        assert!(show_ref(upstream, "refs/heads/release/3.0").contains("release/3.0"));
+        assert_eq!("", show_ref(upstream, "refs/heads/release/internal-3.0"));
+        Ok(())
+    });
+}
@@ -0,0 +1,85 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+
+            upstream.update_working(vec![FileAction::overwrite("sub/component.txt", "Component x")]);
+            upstream.add(".").unwrap();
+            upstream.commit("Component x Commit").unwrap();
+            upstream.command_output(vec!["tag", "component-x-v1"]).unwrap();
+            upstream.push_adv(vec!["origin", "HEAD:refs/heads/component-x/main"]).unwrap();
+            upstream.push_adv(vec!["origin", "HEAD:refs/heads/main"]).unwrap();
+            upstream.push_adv(vec!["origin", "component-x-v1"]).unwrap();
+            upstream.command_output(vec!["reset", "--hard", "HEAD^"]).unwrap();
+        },
+        "sub",
+        |_, _| {
+            vec![
+                "-R".to_owned(),
+                "refs/heads/component-x/main:refs/heads/main".to_owned(),
+                "-R".to_owned(),
+                "refs/tags/component-x-v*:refs/tags/v*".to_owned(),
+            ]
+        },
+        true,
+    )
+    .unwrap()
+}
+
+fn subject(git: &ExtGit, rev: &str) -> String {
+    git.command_output(vec!["fetch", "origin", "--tags"]).unwrap();
+    git.command_output(vec!["log", "-1", "--format=%s", rev]).unwrap()
+}
+
+#[test]
+pub fn import_renamed_refs() {
+    let test = base("ref_names_import_renamed_refs");
+    let downstream = test.get_subgit();
+
+    assert_eq!("Component x Commit", subject(&downstream, "origin/main"));
+    assert_eq!("First Commit from Upstream", subject(&downstream, "origin/master"));
+    assert_eq!("Component x Commit", subject(&downstream, "v1"));
+    assert!(!downstream.has_remote_ref("refs/heads/component-x/main"));
+    assert!(!downstream.has_remote_ref("refs/tags/component-x-v1"));
+}
+
+#[test]
+pub fn push_renamed_refs_both_ways() {
+    let test = base("ref_names_push_renamed_refs_both_ways");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    downstream.command_output(vec!["checkout", "-b", "main", "origin/main"]).unwrap();
+    downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Subgit commit on main").unwrap();
+    downstream.push_adv(vec!["origin", "main"]).unwrap();
+    downstream.command_output(vec!["tag", "v2"]).unwrap();
+    downstream.push_adv(vec!["origin", "v2"]).unwrap();
+
+    assert_eq!("Subgit commit on main", subject(&upstream, "origin/component-x/main"));
+    assert_eq!("Component x Commit", subject(&upstream, "origin/main"));
+    assert_eq!("Subgit commit on main", subject(&upstream, "component-x-v2"));
+    assert!(!upstream.has_remote_ref("refs/tags/v2"));
+
+    upstream.command_output(vec!["checkout", "-b", "component-x/main", "origin/component-x/main"]).unwrap();
+    upstream.update_working(vec![FileAction::overwrite("sub/test.txt", "hello from upstream")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Upstream commit on component-x/main").unwrap();
+    upstream.push_adv(vec!["origin", "component-x/main"]).unwrap();
+
+    std::thread::sleep(Duration::new(2, 0));
+
+    assert_eq!("Upstream commit on component-x/main", subject(&downstream, "origin/main"));
+}
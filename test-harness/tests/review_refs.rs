@@ -0,0 +1,103 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Reviewed change")]);
+            upstream.add(".").unwrap();
+            upstream.commit("Change under review").unwrap();
+            upstream.push_adv(vec!["origin", "HEAD:refs/merge-requests/1/head"]).unwrap();
+            upstream.command_output(vec!["reset", "--hard", "HEAD^"]).unwrap();
+
+            upstream.update_working(vec![FileAction::overwrite("root.txt", "Elsewhere")]);
+            upstream.add(".").unwrap();
+            upstream.commit("Unrelated change under review").unwrap();
+            upstream.push_adv(vec!["origin", "HEAD:refs/merge-requests/2/head"]).unwrap();
+            upstream.command_output(vec!["reset", "--hard", "HEAD^"]).unwrap();
+        },
+        "sub",
+        |_, _| vec!["-v".to_owned(), "refs/merge-requests/*/head:refs/review/*".to_owned()],
+        true,
+    )
+    .unwrap()
+}
+
+fn subject(git: &ExtGit, rev: &str) -> String {
+    git.command_output(vec!["fetch", "origin", "+refs/review/*:refs/remotes/review/*"]).unwrap();
+    git.command_output(vec!["log", "-1", "--format=%s", rev]).unwrap()
+}
+
+#[test]
+pub fn mirror_relevant_review_refs() {
+    let test = base("review_refs_mirror_relevant_review_refs");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    assert_eq!("Change under review", subject(&downstream, "review/1"));
+    assert!(!downstream.has_remote_ref("refs/review/2"));
+    assert!(!downstream.has_remote_ref("refs/merge-requests/1/head"));
+
+    upstream.command_output(vec!["fetch", "origin", "refs/merge-requests/2/head"]).unwrap();
+    upstream.command_output(vec!["checkout", "FETCH_HEAD"]).unwrap();
+    upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Now relevant")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Relevant change under review").unwrap();
+    upstream.push_adv(vec!["origin", "HEAD:refs/merge-requests/2/head"]).unwrap();
+
+    std::thread::sleep(Duration::new(2, 0));
+
+    assert_eq!("Relevant change under review", subject(&downstream, "review/2"));
+}
+
+#[test]
+pub fn reject_pushes_to_review_refs() {
+    let test = base("review_refs_reject_pushes_to_review_refs");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Subgit commit").unwrap();
+    assert!(downstream.push_adv(vec!["origin", "HEAD:refs/review/1"]).is_err());
+    assert!(downstream.push_adv(vec!["origin", "HEAD:refs/review/3"]).is_err());
+    assert!(downstream.push_adv(vec!["origin", ":refs/review/1"]).is_err());
+
+    assert_eq!("Change under review", subject(&downstream, "review/1"));
+    assert!(!downstream.has_remote_ref("refs/review/3"));
+    assert!(!upstream.has_remote_ref("refs/merge-requests/3/head"));
+}
+
+#[test]
+pub fn skip_review_refs_that_cannot_be_inspected() {
+    let test = base("review_refs_skip_review_refs_that_cannot_be_inspected");
+    // A review ref whose parent commit is missing from the upstream
+    let tree = test.bare_git(GitType::Upstream, vec!["rev-parse", "master^{tree}"]).unwrap();
+    let content = format!(
+        "tree {}\nparent {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nBroken\n",
+        tree, "1234567890123456789012345678901234567890"
+    );
+    let content_path = test.root().join("broken-commit");
+    std::fs::write(&content_path, content).unwrap();
+    let broken = test
+        .bare_git(GitType::Upstream, vec!["hash-object", "-t", "commit", "-w", content_path.to_str().unwrap()])
+        .unwrap();
+    test.update_ref(GitType::Upstream, vec!["refs/merge-requests/3/head", &broken]);
+    test.update_ref(GitType::Upstream, vec!["refs/tags/later", "master"]);
+
+    test.run_hook(vec!["sync-all"]).unwrap();
+    assert!(!test.has_ref(GitType::Subgit, "refs/review/3"));
+    assert!(test.has_ref(GitType::Subgit, "refs/tags/later"));
+    let log = std::fs::read_to_string(test.root().join("subgit.git/data/logs/sync.log")).unwrap();
+    assert!(log.contains("Skipping refs/merge-requests/3/head, since it could not be inspected"), "{}", log);
+}
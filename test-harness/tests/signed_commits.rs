@@ -0,0 +1,231 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn keygen(dir: &Path, name: &str) -> PathBuf {
+    let key = std::fs::canonicalize(dir).unwrap().join(name);
+    let status = std::process::Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", name, "-f"])
+        .arg(&key)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    key
+}
+
+fn base(name: &str, policy: &'static str) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+        move |root, _| {
+            let policy = if policy == "resign-ssh" {
+                format!("resign-ssh:{}", keygen(root, "server_key").to_string_lossy())
+            } else {
+                policy.to_owned()
+            };
+            vec!["-s".to_owned(), policy]
+        },
+        true,
+    )
+    .unwrap()
+}
+
+fn commit_signed(git: &ExtGit, message: &str) {
+    let key = keygen(&git.path().join(".."), "user_key");
+    git.command_output(vec![
+        "-c",
+        "gpg.format=ssh",
+        "-c",
+        &format!("user.signingkey={}", key.to_string_lossy()),
+        "commit",
+        "-S",
+        "-m",
+        message,
+    ])
+    .unwrap();
+}
+
+fn raw_commit(git: &ExtGit) -> String {
+    git.command_output(vec!["cat-file", "-p", "HEAD"]).unwrap()
+}
+
+#[test]
+pub fn strip_signatures() {
+    let test = base("signed_commits_strip_signatures", "strip");
+
+    test.do_then_verify(|upstream, downstream| {
+        upstream.update_working(vec![FileAction::overwrite("sub/signed.txt", "signed from upstream")]);
+        upstream.add(".").unwrap();
+        commit_signed(upstream, "Signed upstream commit");
+        upstream.push().unwrap();
+        assert!(raw_commit(upstream).contains("gpgsig"));
+
+        std::thread::sleep(Duration::new(2, 0));
+
+        downstream.pull().unwrap();
+        let upstream_sha = upstream.command_output(vec!["rev-parse", "HEAD"]).unwrap();
+        assert!(!raw_commit(downstream).contains("gpgsig"));
+        assert_eq!(
+            format!("Signed upstream commit\n\nSignature-Stripped: {}", upstream_sha),
+            downstream.command_output(vec!["log", "-1", "--format=%B"]).unwrap()
+        );
+        Ok(())
+    });
+}
+
+#[test]
+pub fn resign_with_server_key() {
+    let test = base("signed_commits_resign_with_server_key", "resign-ssh");
+
+    test.do_then_verify(|upstream, downstream| {
+        downstream.update_working(vec![FileAction::overwrite("signed.txt", "signed from subgit")]);
+        downstream.add(".").unwrap();
+        commit_signed(downstream, "Signed subgit commit");
+        downstream.push().unwrap();
+
+        upstream.pull().unwrap();
+        assert_eq!("Signed subgit commit", upstream.command_output(vec!["log", "-1", "--format=%B"]).unwrap());
+
+        let server_key = std::fs::read_to_string(upstream.path().join("../server_key.pub")).unwrap();
+        let allowed_signers = upstream.path().join("../allowed_signers");
+        std::fs::write(&allowed_signers, format!("* {}", server_key)).unwrap();
+        upstream
+            .command_output(vec![
+                "config",
+                "gpg.ssh.allowedSignersFile",
+                &std::fs::canonicalize(&allowed_signers).unwrap().to_string_lossy(),
+            ])
+            .unwrap();
+        assert!(upstream
+            .command_output(vec!["-c", "gpg.format=ssh", "log", "-1", "--format=%G?"])
+            .unwrap()
+            .starts_with('G'));
+        Ok(())
+    });
+}
+
+#[test]
+pub fn refuse_signed_commits() {
+    let test = base("signed_commits_refuse_signed_commits", "refuse");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+    let upstream_head = upstream.command_output(vec!["ls-remote", "origin", "master"]).unwrap();
+
+    downstream.update_working(vec![FileAction::overwrite("signed.txt", "signed from subgit")]);
+    downstream.add(".").unwrap();
+    commit_signed(&downstream, "Signed subgit commit");
+    assert!(downstream.push().is_err());
+
+    assert_eq!(upstream_head, upstream.command_output(vec!["ls-remote", "origin", "master"]).unwrap());
+}
+
+#[test]
+pub fn refuse_signed_upstream_commits() {
+    let test = base("signed_commits_refuse_signed_upstream_commits", "refuse");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    upstream.update_working(vec![FileAction::overwrite("sub/signed.txt", "signed from upstream")]);
+    upstream.add(".").unwrap();
+    commit_signed(&upstream, "Signed upstream commit");
+    upstream.push().unwrap();
+    std::thread::sleep(Duration::new(2, 0));
+
+    // The ref is skipped as a whole, and the other refs still go through
+    assert_eq!("First Commit from Upstream", downstream.subject("origin/master"));
+    upstream.push_adv(vec!["origin", "HEAD~1:refs/heads/unsigned"]).unwrap();
+    std::thread::sleep(Duration::new(2, 0));
+    assert_eq!("First Commit from Upstream", downstream.subject("origin/unsigned"));
+
+    let signed = upstream.command_output(vec!["rev-parse", "HEAD"]).unwrap();
+    let log = std::fs::read_to_string(test.bare_path(GitType::Subgit).join("data/logs/sync.log")).unwrap();
+    assert!(log.contains(&format!("Skipping refs/heads/master, since {} is signed", signed)), "{}", log);
+    assert!(!log.contains("panicked"), "{}", log);
+}
+
+fn tag_signed(git: &ExtGit, tag: &str, message: &str) {
+    let key = keygen(&git.path().join(".."), "user_tag_key");
+    git.command_output(vec![
+        "-c",
+        "gpg.format=ssh",
+        "-c",
+        &format!("user.signingkey={}", key.to_string_lossy()),
+        "tag",
+        "-s",
+        tag,
+        "-m",
+        message,
+    ])
+    .unwrap();
+    git.push_adv(vec!["origin", tag]).unwrap();
+    std::thread::sleep(Duration::new(2, 0));
+}
+
+fn raw_tag(git: &ExtGit, tag: &str) -> String {
+    git.command_output(vec!["fetch", "origin", "--tags", "--force"]).unwrap();
+    git.command_output(vec!["cat-file", "-p", &format!("refs/tags/{}", tag)]).unwrap()
+}
+
+#[test]
+pub fn strip_tag_signatures() {
+    let test = base("signed_commits_strip_tag_signatures", "strip");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    tag_signed(&upstream, "v1", "Signed release");
+    let tag = upstream.command_output(vec!["rev-parse", "v1"]).unwrap();
+    let raw = raw_tag(&downstream, "v1");
+    assert!(!raw.contains("SIGNATURE"), "{}", raw);
+    assert!(raw.ends_with(&format!("Signed release\n\nSignature-Stripped: {}", tag)), "{}", raw);
+}
+
+#[test]
+pub fn resign_tags_with_server_key() {
+    let test = base("signed_commits_resign_tags_with_server_key", "resign-ssh");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    tag_signed(&upstream, "v1", "Signed release");
+    let raw = raw_tag(&downstream, "v1");
+    assert!(raw.contains("Signed release\n-----BEGIN SSH SIGNATURE-----"), "{}", raw);
+    assert!(!raw.contains(&raw_tag(&upstream, "v1")[raw.find("-----BEGIN").unwrap()..]), "{}", raw);
+
+    let server_key = std::fs::read_to_string(upstream.path().join("../server_key.pub")).unwrap();
+    let allowed_signers = upstream.path().join("../allowed_signers");
+    std::fs::write(&allowed_signers, format!("* {}", server_key)).unwrap();
+    downstream
+        .command_output(vec![
+            "-c",
+            "gpg.format=ssh",
+            "-c",
+            &format!("gpg.ssh.allowedSignersFile={}", std::fs::canonicalize(&allowed_signers).unwrap().to_string_lossy()),
+            "tag",
+            "-v",
+            "v1",
+        ])
+        .unwrap();
+}
+
+#[test]
+pub fn refuse_signed_tags() {
+    let test = base("signed_commits_refuse_signed_tags", "refuse");
+    let upstream = test.get_upstream();
+
+    tag_signed(&upstream, "v1", "Signed release");
+    upstream.command_output(vec!["tag", "-a", "v2", "-m", "Unsigned release"]).unwrap();
+    upstream.push_adv(vec!["origin", "v2"]).unwrap();
+    std::thread::sleep(Duration::new(2, 0));
+
+    assert!(!test.has_ref(GitType::Subgit, "refs/tags/v1"));
+    assert!(test.has_ref(GitType::Subgit, "refs/tags/v2"));
+}
@@ -0,0 +1,94 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Second version")]);
+            upstream.add(".").unwrap();
+            upstream.commit("Second Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+            upstream.push_adv(vec!["origin", "HEAD:refs/heads/other"]).unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+fn verdicts(test: &TestWrapper) -> Vec<(String, String)> {
+    test.run_command("status", vec![]).unwrap()
+        .lines()
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let verdict = parts.next().unwrap().to_owned();
+            (parts.next().unwrap().to_owned(), verdict)
+        })
+        .collect()
+}
+
+#[test]
+pub fn report_sync_state() {
+    let test = base("status_report_sync_state");
+    assert_eq!(
+        vec![
+            ("refs/heads/master".to_owned(), "in-sync".to_owned()),
+            ("refs/heads/other".to_owned(), "in-sync".to_owned()),
+        ],
+        verdicts(&test)
+    );
+
+    let downstream = test.get_subgit();
+    downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Subgit commit").unwrap();
+    downstream.push_adv(vec!["origin", "HEAD:refs/heads/feature"]).unwrap();
+    downstream.command_output(vec!["reset", "--hard", "HEAD^"]).unwrap();
+    downstream.update_working(vec![FileAction::overwrite("test.txt", "another hello from subgit")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Other subgit commit").unwrap();
+    downstream.push_adv(vec!["origin", "HEAD:refs/heads/diverged"]).unwrap();
+
+    test.update_ref(GitType::Upstream, vec!["refs/heads/diverged", "refs/heads/feature"]);
+    test.update_ref(GitType::Upstream, vec!["refs/heads/fresh", "refs/heads/master"]);
+    test.update_ref(GitType::Upstream, vec!["-d", "refs/heads/other"]);
+    test.update_ref(GitType::Subgit, vec!["refs/heads/master", "refs/heads/master^"]);
+
+    assert_eq!(
+        vec![
+            ("refs/heads/diverged".to_owned(), "diverged".to_owned()),
+            ("refs/heads/feature".to_owned(), "in-sync".to_owned()),
+            ("refs/heads/fresh".to_owned(), "missing".to_owned()),
+            ("refs/heads/master".to_owned(), "behind".to_owned()),
+            ("refs/heads/other".to_owned(), "missing".to_owned()),
+        ],
+        verdicts(&test)
+    );
+}
+
+#[test]
+pub fn report_as_json() {
+    let test = base("status_report_as_json");
+    let output = test.run_command("status", vec!["--json"]).unwrap();
+    let refs: Vec<&str> = output.lines().filter(|line| line.contains("\"ref_name\"")).collect();
+    assert_eq!(vec!["    \"ref_name\": \"refs/heads/master\",", "    \"ref_name\": \"refs/heads/other\","], refs);
+    assert_eq!(2, output.matches("\"state\": \"in-sync\"").count());
+    assert!(output.trim_start().starts_with('['));
+}
+
+#[test]
+pub fn report_unreadable_settings() {
+    let test = base("status_report_unreadable_settings");
+    std::fs::write(test.root().join("subgit.git/data/settings.json"), "{ not json").unwrap();
+
+    let output = test.run_command("status", vec![]).unwrap_err();
+    assert!(output.contains("Could not parse"), "{}", output);
+    // Reported like any other error of the command, rather than from an unwrap while loading
+    assert!(!output.contains("settings.rs"), "{}", output);
+}
@@ -0,0 +1,125 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+// Doesn't need to exist - nothing ever clones the submodules
+const DEP_SHA: &str = "1111111111111111111111111111111111111111";
+const OTHER_SHA: &str = "2222222222222222222222222222222222222222";
+
+fn add_gitlink(git: &ExtGit, path: &str, sha: &str) {
+    git.command_output(vec!["update-index", "--add", "--cacheinfo", &format!("160000,{},{}", sha, path)])
+        .unwrap();
+    std::fs::create_dir_all(git.path().join(path)).unwrap();
+}
+
+fn gitmodules_value(git: &ExtGit, key: &str) -> String {
+    git.command_output(vec!["config", "-f", ".gitmodules", key])
+        .unwrap()
+        .trim()
+        .to_owned()
+}
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![
+                FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)"),
+                FileAction::overwrite(
+                    ".gitmodules",
+                    "[submodule \"sub/vendor/dep\"]\n\tpath = sub/vendor/dep\n\turl = https://example.com/dep.git\n\
+                     [submodule \"other\"]\n\tpath = other\n\turl = https://example.com/other.git\n",
+                ),
+            ]);
+            add_gitlink(upstream, "sub/vendor/dep", DEP_SHA);
+            add_gitlink(upstream, "other", OTHER_SHA);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+#[test]
+pub fn import_submodule() {
+    let test = base("submodules_import_submodule");
+
+    let downstream = test.get_subgit();
+    assert!(downstream
+        .command_output(vec!["ls-tree", "HEAD", "vendor/dep"])
+        .unwrap()
+        .starts_with(&format!("160000 commit {}", DEP_SHA)));
+    assert_eq!("vendor/dep", gitmodules_value(&downstream, "submodule.vendor/dep.path"));
+    assert_eq!("https://example.com/dep.git", gitmodules_value(&downstream, "submodule.vendor/dep.url"));
+    assert_eq!(
+        "",
+        downstream
+            .command_output(vec!["config", "-f", ".gitmodules", "--get-regexp", "submodule.other.*"])
+            .unwrap_or_default()
+    );
+}
+
+#[test]
+pub fn push_submodule_from_upstream() {
+    let test = base("submodules_push_submodule_from_upstream");
+
+    test.do_then_verify(|upstream, downstream| {
+        upstream
+            .command_output(vec!["config", "-f", ".gitmodules", "submodule.sub/lib/new.path", "sub/lib/new"])
+            .unwrap();
+        upstream
+            .command_output(vec!["config", "-f", ".gitmodules", "submodule.sub/lib/new.url", "https://example.com/new.git"])
+            .unwrap();
+        add_gitlink(upstream, "sub/lib/new", OTHER_SHA);
+        upstream.add(".").unwrap();
+        upstream.commit("Add a submodule from upstream").unwrap();
+        upstream.push().unwrap();
+
+        std::thread::sleep(Duration::new(2, 0));
+
+        downstream.pull().unwrap();
+
+        assert!(downstream
+            .command_output(vec!["ls-tree", "HEAD", "lib/new"])
+            .unwrap()
+            .starts_with(&format!("160000 commit {}", OTHER_SHA)));
+        assert_eq!("lib/new", gitmodules_value(downstream, "submodule.lib/new.path"));
+        Ok(())
+    });
+}
+
+#[test]
+pub fn push_submodule_from_subgit() {
+    let test = base("submodules_push_submodule_from_subgit");
+
+    test.do_then_verify(|upstream, downstream| {
+        downstream
+            .command_output(vec!["config", "-f", ".gitmodules", "submodule.lib/new.path", "lib/new"])
+            .unwrap();
+        downstream
+            .command_output(vec!["config", "-f", ".gitmodules", "submodule.lib/new.url", "https://example.com/new.git"])
+            .unwrap();
+        add_gitlink(downstream, "lib/new", OTHER_SHA);
+        downstream.add(".").unwrap();
+        downstream.commit("Add a submodule from subgit").unwrap();
+        downstream.push().unwrap();
+
+        upstream.pull().unwrap();
+
+        assert!(upstream
+            .command_output(vec!["ls-tree", "HEAD", "sub/lib/new"])
+            .unwrap()
+            .starts_with(&format!("160000 commit {}", OTHER_SHA)));
+        assert_eq!("sub/lib/new", gitmodules_value(upstream, "submodule.sub/lib/new.path"));
+        // The submodules that aren't part of the subgit are left alone
+        assert_eq!("sub/vendor/dep", gitmodules_value(upstream, "submodule.sub/vendor/dep.path"));
+        assert_eq!("other", gitmodules_value(upstream, "submodule.other.path"));
+        assert!(!upstream.path().join("sub/.gitmodules").exists());
+        Ok(())
+    });
+}
@@ -0,0 +1,55 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+            upstream.push_adv(vec!["origin", "HEAD:refs/heads/gone"]).unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+fn setup_stale_refs(test: &TestWrapper) {
+    let downstream = test.get_subgit();
+    let head = downstream.command_output(vec!["rev-parse", "HEAD"]).unwrap();
+    assert!(downstream.has_remote_ref("refs/heads/gone"));
+
+    test.update_ref(GitType::Upstream, vec!["-d", "refs/heads/gone"]);
+    test.update_ref(GitType::Subgit, vec!["refs/heads/subgit-only/wip", &head]);
+}
+
+#[test]
+pub fn dry_run_lists_stale_refs() {
+    let test = base("sync_all_dry_run_lists_stale_refs");
+    setup_stale_refs(&test);
+
+    let output = test.run_hook(vec!["sync-all", "--dry-run", "--keep", "refs/heads/subgit-only/*"]).unwrap();
+    assert_eq!("Would remove refs/heads/gone", output.trim());
+    assert!(test.get_subgit().has_remote_ref("refs/heads/gone"));
+}
+
+#[test]
+pub fn prune_stale_refs() {
+    let test = base("sync_all_prune_stale_refs");
+    setup_stale_refs(&test);
+
+    test.run_hook(vec!["sync-all", "-k", "refs/heads/subgit-only/*"]).unwrap();
+    let downstream = test.get_subgit();
+    assert!(!downstream.has_remote_ref("refs/heads/gone"));
+    assert!(downstream.has_remote_ref("refs/heads/subgit-only/wip"));
+    assert!(downstream.has_remote_ref("refs/heads/master"));
+
+    test.run_hook(vec!["sync-all"]).unwrap();
+    assert!(!downstream.has_remote_ref("refs/heads/subgit-only/wip"));
+    assert!(test.get_upstream().has_remote_ref("refs/heads/master"));
+}
@@ -0,0 +1,134 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.command_output(vec!["tag", "v1-light"]).unwrap();
+            upstream.command_output(vec!["tag", "-a", "v1", "-m", "Release v1"]).unwrap();
+            upstream.push().unwrap();
+            upstream.push_adv(vec!["origin", "--tags"]).unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+fn rev(git: &ExtGit, rev: &str) -> String {
+    git.command_output(vec!["rev-parse", rev]).unwrap()
+}
+
+fn has_remote_tag(git: &ExtGit, tag: &str) -> bool {
+    git.command_output(vec!["ls-remote", "--tags", "origin"])
+        .unwrap()
+        .lines()
+        .any(|line| line.ends_with(&format!("refs/tags/{}", tag)))
+}
+
+fn assert_annotated(git: &ExtGit, tag: &str, message: &str) {
+    git.command_output(vec!["fetch", "origin", "--tags", "--force"]).unwrap();
+    assert_eq!("tag", git.command_output(vec!["cat-file", "-t", &format!("refs/tags/{}", tag)]).unwrap());
+    assert_eq!(rev(git, "HEAD"), rev(git, &format!("{}^{{commit}}", tag)));
+    assert_eq!(
+        message,
+        git.command_output(vec!["tag", "-l", "--format=%(contents)", tag]).unwrap()
+    );
+    assert_eq!(
+        "test user <test@example.com>",
+        git.command_output(vec!["tag", "-l", "--format=%(taggername) %(taggeremail)", tag]).unwrap()
+    );
+}
+
+#[test]
+pub fn import_tags() {
+    let test = base("tags_import_tags");
+    let downstream = test.get_subgit();
+
+    downstream.command_output(vec!["fetch", "origin", "--tags"]).unwrap();
+    assert_eq!("commit", downstream.command_output(vec!["cat-file", "-t", "refs/tags/v1-light"]).unwrap());
+    assert_eq!(rev(&downstream, "HEAD"), rev(&downstream, "v1-light"));
+    assert_annotated(&downstream, "v1", "Release v1");
+}
+
+#[test]
+pub fn push_and_delete_tags_from_upstream() {
+    let test = base("tags_push_and_delete_tags_from_upstream");
+
+    test.do_then_verify(|upstream, downstream| {
+        upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello again")]);
+        upstream.add(".").unwrap();
+        upstream.commit("Second Commit from Upstream").unwrap();
+        upstream.command_output(vec!["tag", "-a", "v2", "-m", "Release v2"]).unwrap();
+        upstream.push().unwrap();
+        upstream.push_adv(vec!["origin", "v2"]).unwrap();
+
+        std::thread::sleep(Duration::new(2, 0));
+
+        downstream.pull().unwrap();
+        assert_annotated(downstream, "v2", "Release v2");
+
+        upstream.push_adv(vec!["origin", ":refs/tags/v2"]).unwrap();
+
+        std::thread::sleep(Duration::new(2, 0));
+
+        assert!(!has_remote_tag(downstream, "v2"));
+        assert!(has_remote_tag(downstream, "v1"));
+        Ok(())
+    });
+}
+
+#[test]
+pub fn move_tags_from_upstream() {
+    let test = base("tags_move_tags_from_upstream");
+
+    test.do_then_verify(|upstream, downstream| {
+        upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello again")]);
+        upstream.add(".").unwrap();
+        upstream.commit("Second Commit from Upstream").unwrap();
+        upstream.command_output(vec!["tag", "-a", "-f", "v1", "-m", "Release v1, again"]).unwrap();
+        upstream.push().unwrap();
+        upstream.push_adv(vec!["--force", "origin", "v1"]).unwrap();
+
+        std::thread::sleep(Duration::new(2, 0));
+
+        downstream.pull().unwrap();
+        assert_annotated(downstream, "v1", "Release v1, again");
+        // The tag objects are written without leaving a tag behind in the working clone
+        let working_clone = test.root().join("subgit.git/data/local");
+        assert!(!working_clone.join(".git/refs/tags/v1").exists());
+        Ok(())
+    });
+}
+
+#[test]
+pub fn push_and_delete_tags_from_subgit() {
+    let test = base("tags_push_and_delete_tags_from_subgit");
+
+    test.do_then_verify(|upstream, downstream| {
+        downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+        downstream.add(".").unwrap();
+        downstream.commit("Subgit commit").unwrap();
+        downstream.command_output(vec!["tag", "-a", "v3", "-m", "Release v3"]).unwrap();
+        downstream.command_output(vec!["tag", "v3-light"]).unwrap();
+        downstream.push().unwrap();
+        downstream.push_adv(vec!["origin", "v3", "v3-light"]).unwrap();
+
+        upstream.pull().unwrap();
+        assert_annotated(upstream, "v3", "Release v3");
+        assert_eq!(rev(upstream, "HEAD"), rev(upstream, "v3-light"));
+
+        downstream.push_adv(vec!["origin", ":refs/tags/v3"]).unwrap();
+
+        assert!(!has_remote_tag(upstream, "v3"));
+        assert!(has_remote_tag(upstream, "v3-light"));
+        Ok(())
+    });
+}
@@ -0,0 +1,74 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+        |_, _| vec!["-t".to_owned()],
+        true,
+    )
+    .unwrap()
+}
+
+fn rev(git: &ExtGit, rev: &str) -> String {
+    git.command_output(vec!["rev-parse", rev]).unwrap()
+}
+
+fn message(git: &ExtGit, rev: &str) -> String {
+    git.command_output(vec!["log", "-1", "--format=%B", rev]).unwrap()
+}
+
+#[test]
+pub fn import_adds_upstream_trailer() {
+    let test = base("trailers_import_adds_upstream_trailer");
+    let upstream = test.get_upstream();
+    let downstream = test.get_subgit();
+
+    assert_eq!(
+        format!("First Commit from Upstream\n\nUpstream-Commit: {}", rev(&upstream, "HEAD")),
+        message(&downstream, "HEAD")
+    );
+}
+
+#[test]
+pub fn trailers_round_trip() {
+    let test = base("trailers_round_trip");
+
+    test.do_then_verify(|upstream, downstream| {
+        downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+        downstream.add(".").unwrap();
+        downstream.commit("Subgit commit\n\nUpstream-Commit: 1111111111111111111111111111111111111111").unwrap();
+        downstream.push().unwrap();
+
+        upstream.pull().unwrap();
+        assert_eq!(
+            format!("Subgit commit\n\nSubgit-Commit: {}", rev(downstream, "HEAD")),
+            message(upstream, "HEAD")
+        );
+
+        upstream.update_working(vec![FileAction::overwrite("sub/test.txt", "hello from upstream")]);
+        upstream.add(".").unwrap();
+        upstream.commit("Upstream commit\n\nSubgit-Commit: 2222222222222222222222222222222222222222").unwrap();
+        upstream.push().unwrap();
+
+        std::thread::sleep(Duration::new(2, 0));
+
+        downstream.pull().unwrap();
+        assert_eq!(
+            format!("Upstream commit\n\nUpstream-Commit: {}", rev(upstream, "HEAD")),
+            message(downstream, "HEAD")
+        );
+        Ok(())
+    });
+}
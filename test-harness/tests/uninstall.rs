@@ -0,0 +1,122 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![
+                FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)"),
+                FileAction::overwrite("root.txt", "Outside of the subgit"),
+            ]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+fn exists(path: PathBuf) -> bool {
+    std::fs::symlink_metadata(path).is_ok()
+}
+
+#[test]
+pub fn uninstall_and_archive() {
+    let test = base("uninstall_uninstall_and_archive");
+    let root = test.root();
+    let archive = root.join("archived-data");
+
+    let output = test
+        .run_command("uninstall", vec!["--dry-run", "--archive", archive.to_str().unwrap()])
+        .unwrap();
+    assert!(output.contains("Would remove the hook"), "{}", output);
+    assert!(exists(root.join("subgit.git/hooks/update")));
+    assert!(exists(root.join("upstream.git/hooks/post-receive")));
+    assert!(test.has_ref(GitType::Subgit, "refs/sync/empty"));
+    assert!(exists(root.join("subgit.git/data")));
+
+    test.run_command("uninstall", vec!["--archive", archive.to_str().unwrap()]).unwrap();
+    assert!(!exists(root.join("subgit.git/hooks/update")));
+    assert!(!exists(root.join("upstream.git/hooks/post-receive")));
+    assert!(!test.has_ref(GitType::Subgit, "refs/sync/empty"));
+    assert!(!test.has_ref(GitType::Upstream, "refs/sync/empty"));
+    assert!(!exists(root.join("subgit.git/data")));
+    assert!(archive.join("settings.json").exists());
+
+    // Both are plain repositories now - pushing to one doesn't touch the other
+    let upstream = test.get_upstream();
+    upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "After the uninstall")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Second Commit from Upstream").unwrap();
+    upstream.push().unwrap();
+    std::thread::sleep(Duration::new(2, 0));
+
+    let downstream = test.get_subgit();
+    assert_eq!("First Commit from Upstream", downstream.subject("origin/master"));
+}
+
+#[test]
+pub fn keep_replaced_hooks() {
+    let test = base("uninstall_keep_replaced_hooks");
+    let root = test.root();
+    let hook = root.join("upstream.git/hooks/post-receive");
+    std::fs::remove_file(&hook).unwrap();
+    std::fs::write(&hook, "#!/bin/sh\n").unwrap();
+
+    let output = test.run_command("uninstall", vec!["--delete-data"]).unwrap();
+    assert!(!exists(root.join("subgit.git/hooks/update")));
+    assert!(hook.exists());
+    assert!(!exists(root.join("subgit.git/data")));
+    assert!(!output.contains("subgit-data"), "{}", output);
+}
+
+#[test]
+pub fn uninstall_custom_hook_path() {
+    let test = TestWrapper::new_adv(
+        "uninstall_uninstall_custom_hook_path",
+        |upstream| {
+            let hooks = upstream.path().with_file_name("upstream.git").join("custom_hooks/post-receive.d");
+            std::fs::create_dir_all(hooks).unwrap();
+            upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)")]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+        |_, _| vec!["--upstream_hook_path".to_owned(), "custom_hooks/post-receive.d/subgit-sync".to_owned()],
+        true,
+    )
+    .unwrap();
+    let root = test.root();
+    let hook = root.join("upstream.git/custom_hooks/post-receive.d/subgit-sync");
+    assert!(exists(hook.clone()));
+    let settings = std::fs::read_to_string(root.join("subgit.git/data/settings.json")).unwrap();
+    assert!(settings.contains("custom_hooks/post-receive.d/subgit-sync"), "{}", settings);
+
+    test.run_command("uninstall", vec!["--delete-data"]).unwrap();
+    assert!(!exists(hook));
+    assert!(!exists(root.join("subgit.git/hooks/update")));
+}
+
+#[test]
+pub fn uninstall_without_recorded_hooks() {
+    let test = base("uninstall_uninstall_without_recorded_hooks");
+    let root = test.root();
+    // Installs from before the hooks were recorded in the settings
+    let settings_path = root.join("subgit.git/data/settings.json");
+    let settings = std::fs::read_to_string(&settings_path).unwrap();
+    assert!(settings.contains("\"hooks\": {"), "{}", settings);
+    std::fs::write(&settings_path, settings.replace("\"hooks\": {", "\"unknown\": {")).unwrap();
+
+    let output = test.run_command("uninstall", vec!["--delete-data"]).unwrap();
+    assert!(output.contains("Removed the hook"), "{}", output);
+    assert!(!exists(root.join("subgit.git/hooks/update")));
+    assert!(!exists(root.join("upstream.git/hooks/post-receive")));
+}
@@ -0,0 +1,114 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new(
+        name,
+        |upstream| {
+            upstream.update_working(vec![
+                FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)"),
+                FileAction::overwrite("root.txt", "Outside of the subgit"),
+            ]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+    )
+    .unwrap()
+}
+
+/// Runs the upgrade command of the freshly built binary
+fn upgrade(test: &TestWrapper) -> Result<String, String> {
+    test.run_command_of(std::fs::canonicalize("../target/debug/subgit-sync").unwrap(), "upgrade", vec![])
+}
+
+fn push_upstream(test: &TestWrapper, content: &str, message: &str) {
+    let upstream = test.get_upstream();
+    upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", content)]);
+    upstream.add(".").unwrap();
+    upstream.commit(message).unwrap();
+    upstream.push().unwrap();
+    std::thread::sleep(Duration::new(2, 0));
+}
+
+#[test]
+pub fn upgrade_hook_and_settings() {
+    let test = base("upgrade_upgrade_hook_and_settings");
+    let data = test.root().join("subgit.git/data");
+
+    // An install whose hook does nothing, with a settings file from before multiple mappings
+    std::fs::remove_file(data.join("hook")).unwrap();
+    std::fs::copy("/bin/true", data.join("hook")).unwrap();
+    let settings = std::fs::read_to_string(data.join("settings.json")).unwrap();
+    let mapping = "\"mappings\": [\n    {\n      \"upstream\": \"sub\",\n      \"subgit\": \"\"\n    }\n  ],";
+    assert!(settings.contains(mapping), "{}", settings);
+    std::fs::write(data.join("settings.json"), settings.replace(mapping, "\"upstream_path\": \"sub\",")).unwrap();
+
+    push_upstream(&test, "Not synced", "Second Commit from Upstream");
+    assert_eq!("First Commit from Upstream", test.get_subgit().subject("origin/master"));
+
+    let output = upgrade(&test).unwrap();
+    assert!(output.starts_with("Installed "), "{}", output);
+    let settings = std::fs::read_to_string(data.join("settings.json")).unwrap();
+    assert!(settings.contains(mapping), "{}", settings);
+    assert!(!settings.contains("upstream_path"), "{}", settings);
+
+    push_upstream(&test, "Synced again", "Third Commit from Upstream");
+    assert_eq!("Third Commit from Upstream", test.get_subgit().subject("origin/master"));
+
+    let output = upgrade(&test).unwrap();
+    assert!(output.starts_with("The hook already is "), "{}", output);
+}
+
+#[test]
+pub fn refuse_during_sync() {
+    let test = base("upgrade_refuse_during_sync");
+    let data = test.root().join("subgit.git/data");
+
+    let mut sync = std::process::Command::new("flock")
+        .arg("-o")
+        .arg(data.join("lock"))
+        .args(["sleep", "30"])
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::new(1, 0));
+    let output = upgrade(&test);
+    sync.kill().unwrap();
+    sync.wait().unwrap();
+    let output = output.unwrap_err();
+    assert!(output.contains("A sync is in progress"), "{}", output);
+
+    assert!(upgrade(&test).is_ok());
+}
+
+#[test]
+pub fn refuse_without_hooks() {
+    let test = base("upgrade_refuse_without_hooks");
+    // Lets the sync started by the setup finish
+    std::thread::sleep(Duration::new(2, 0));
+    std::fs::remove_file(test.root().join("upstream.git/hooks/post-receive")).unwrap();
+
+    let output = upgrade(&test).unwrap_err();
+    assert!(output.contains("No hook of the upstream"), "{}", output);
+}
+
+#[test]
+pub fn refuse_unreadable_settings() {
+    let test = base("upgrade_refuse_unreadable_settings");
+    // Lets the sync started by the setup finish
+    std::thread::sleep(Duration::new(2, 0));
+    let data = test.root().join("subgit.git/data");
+    std::fs::remove_file(data.join("hook")).unwrap();
+    std::fs::copy("/bin/true", data.join("hook")).unwrap();
+    std::fs::write(data.join("settings.json"), "{ not json").unwrap();
+
+    let output = upgrade(&test).unwrap_err();
+    assert!(output.contains("Could not parse"), "{}", output);
+    assert_eq!(std::fs::read("/bin/true").unwrap(), std::fs::read(data.join("hook")).unwrap());
+    assert_eq!("{ not json", std::fs::read_to_string(data.join("settings.json")).unwrap());
+}
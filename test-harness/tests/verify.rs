@@ -0,0 +1,80 @@
+extern crate log;
+extern crate simplelog;
+extern crate subgit_sync;
+
+use test_harness::harness::*;
+use std::time::Duration;
+
+fn base(name: &str) -> TestWrapper {
+    TestWrapper::new_adv(
+        name,
+        |upstream| {
+            upstream.update_working(vec![
+                FileAction::overwrite("sub/hello.txt", "Hello world (from upstream)"),
+                FileAction::overwrite("sub/internal/notes.txt", "Not for the subgit"),
+                FileAction::overwrite("root.txt", "Outside of the subgit"),
+            ]);
+            upstream.add(".").unwrap();
+            upstream.commit("First Commit from Upstream").unwrap();
+            upstream.update_working(vec![FileAction::overwrite("root.txt", "Still outside of the subgit")]);
+            upstream.add(".").unwrap();
+            upstream.commit("Second Commit from Upstream").unwrap();
+            upstream.push().unwrap();
+        },
+        "sub",
+        |_, _| vec!["-x".to_owned(), "internal/".to_owned()],
+        true,
+    )
+    .unwrap()
+}
+
+fn push_both_ways(test: &TestWrapper) {
+    let downstream = test.get_subgit();
+    downstream.update_working(vec![FileAction::overwrite("test.txt", "hello from subgit")]);
+    downstream.add(".").unwrap();
+    downstream.commit("Subgit commit").unwrap();
+    downstream.push().unwrap();
+
+    let upstream = test.get_upstream();
+    upstream.command_output(vec!["pull"]).unwrap();
+    upstream.update_working(vec![FileAction::overwrite("sub/internal/notes.txt", "Still not for the subgit")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Excluded change from Upstream").unwrap();
+    upstream.update_working(vec![FileAction::overwrite("sub/hello.txt", "Hello again")]);
+    upstream.add(".").unwrap();
+    upstream.commit("Third Commit from Upstream").unwrap();
+    upstream.push().unwrap();
+
+    std::thread::sleep(Duration::new(2, 0));
+}
+
+#[test]
+pub fn verify_matching_histories() {
+    let test = base("verify_verify_matching_histories");
+    push_both_ways(&test);
+
+    let output = test.run_command("verify", vec![]).unwrap();
+    assert!(output.ends_with("found 0 mismatches\n"), "{}", output);
+
+    let output = test.run_command("verify", vec!["--tips"]).unwrap();
+    assert_eq!("Verified 1 commit pairs, found 0 mismatches\n", output);
+}
+
+#[test]
+pub fn report_mismatched_tips() {
+    let test = base("verify_report_mismatched_tips");
+    push_both_ways(&test);
+    test.update_ref(GitType::Subgit, vec!["refs/heads/master", "refs/heads/master^"]);
+    let upstream_tip = test.get_upstream().command_output(vec!["rev-parse", "HEAD"]).unwrap();
+    let subgit_tip = test.bare_git(GitType::Subgit, vec!["rev-parse", "refs/heads/master"]).unwrap();
+
+    let output = test.run_command("verify", vec!["--tips"]).unwrap_err();
+    let expected = format!(
+        "tree mismatch: upstream {} (sub/) vs subgit {} (/)\nVerified 1 commit pairs, found 1 mismatches\n",
+        upstream_tip, subgit_tip
+    );
+    assert!(output.starts_with(&expected), "{}", output);
+
+    // The map itself is still fine
+    assert!(test.run_command("verify", vec![]).is_ok());
+}